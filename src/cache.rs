@@ -1,63 +1,168 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
-use serde::{Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tracing::warn;
 use std::borrow::Borrow;
 use std::hash::Hash;
-use std::{collections::HashMap, fs::{self, OpenOptions}, path::PathBuf, sync::{Arc, RwLock}};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{collections::HashMap, fs::{self, OpenOptions}, path::{Path, PathBuf}, sync::{Arc, RwLock}};
 use directories::ProjectDirs;
 use miette::{IntoDiagnostic, Result};
 
 use crate::{APPLICATION, ORGANIZATION, QUALIFIER};
+use crate::cloudflare::dns::CachedRecord;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
 
 #[derive(Debug)]
 pub struct Cache<K, V> {
     pub map: HashMap<K, V>,
+    /// The JSON file, or the database when built with the `sqlite` feature
     path: PathBuf,
+    #[cfg(feature = "sqlite")]
+    name: String,
+}
+
+/// A value stored in a cache file. Files record the version they were written with, so entries
+/// from older versions can be migrated instead of discarded.
+pub trait CacheEntry: Serialize + DeserializeOwned {
+    /// Bump whenever the serialized form changes, and handle the old form in [`CacheEntry::migrate`]
+    const VERSION: u32;
+
+    /// Convert an entry written with an older `version`. Files from before versioning are version 0.
+    /// Entries that can't be converted are dropped.
+    fn migrate(version: u32, value: serde_json::Value) -> Option<Self> {
+        let _ = version;
+        serde_json::from_value(value).ok()
+    }
+}
+
+/// The on-disk form of a cache
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    entries: T,
 }
 
 impl<K, V> Cache<K, V>
 where
     K: Serialize + DeserializeOwned + Eq + Hash,
-    V: Serialize + DeserializeOwned,
+    V: CacheEntry,
 {
     
     pub fn load(name: &str) -> Result<Self> {
-        let base = ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION).ok_or(miette::miette!("No project dirs"))?;
-        let cache_dir = base.cache_dir();
+        Self::load_in(&cache_dir()?, name)
+    }
 
-        // Ensure the cache dir exists
-        fs::create_dir_all(cache_dir).into_diagnostic()?;
+    /// Load `name` from a directory other than the cache directory
+    pub fn load_in(dir: &Path, name: &str) -> Result<Self> {
+        // Ensure the dir exists
+        fs::create_dir_all(dir).into_diagnostic()?;
 
-        let path = cache_dir.join(format!("{}.json", name));
+        let json_path = dir.join(format!("{}.json", name));
 
+        #[cfg(feature = "sqlite")]
+        {
+            let path = sqlite::database_path(dir);
+            let map = match sqlite::load_cache(&path, name)? {
+                Some((version, entries)) => {
+                    let entries = entries
+                        .into_iter()
+                        .filter_map(|(key, value)| Some((serde_json::from_value(serde_json::Value::String(key)).ok()?, value)))
+                        .collect();
+                    Self::read_entries(name, version, entries)
+                }
+                // Never saved to the database, so carry over the JSON file if there is one
+                None => Self::read_file(name, &json_path)?,
+            };
+            Ok(Self { map, path, name: name.to_string() })
+        }
+
+        #[cfg(not(feature = "sqlite"))]
+        Ok(Self { map: Self::read_file(name, &json_path)?, path: json_path })
+    }
+
+    /// Read a JSON cache file, or nothing if it doesn't exist
+    fn read_file(name: &str, path: &Path) -> Result<HashMap<K, V>> {
         // Return a fresh cache if the file doesn't exist
         if !path.exists() {
-            return Ok(Self { map: HashMap::new(), path });
+            return Ok(HashMap::new());
         }
 
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(&path)
+            .open(path)
             .into_diagnostic()?;
 
         let map = match serde_json::from_reader(file) {
-            Ok(m) => m,
+            Ok(value) => Self::read_versioned(name, value),
             Err(e) => {
                 warn!(path=path.to_str(), error=e.to_string(), "{} cache file was invalid, overwriting.", name);
                 HashMap::new()
             }
         };
+        Ok(map)
+    }
+
+    /// Read entries from a parsed cache file, migrating them from the version it was written with
+    fn read_versioned(name: &str, value: serde_json::Value) -> HashMap<K, V> {
+        let (version, entries) = match serde_json::from_value::<Envelope<HashMap<K, serde_json::Value>>>(value.clone()) {
+            Ok(envelope) => (envelope.version, envelope.entries),
+            // Written before cache files were versioned
+            Err(_) => match serde_json::from_value(value) {
+                Ok(entries) => (0, entries),
+                Err(e) => {
+                    warn!(error=e.to_string(), "{} cache file was invalid, overwriting.", name);
+                    return HashMap::new();
+                }
+            },
+        };
+        Self::read_entries(name, version, entries)
+    }
+
+    /// Migrate entries written with `version` to the current version
+    fn read_entries(name: &str, version: u32, entries: HashMap<K, serde_json::Value>) -> HashMap<K, V> {
+        if version > V::VERSION {
+            warn!(version, supported=V::VERSION, "{} cache file was written by a newer cfdns, overwriting.", name);
+            return HashMap::new();
+        }
 
-        Ok(Self { map, path })
+        let total = entries.len();
+        let map: HashMap<K, V> = entries
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let entry = if version == V::VERSION {
+                    serde_json::from_value(value).ok()
+                } else {
+                    V::migrate(version, value)
+                };
+                entry.map(|entry| (key, entry))
+            })
+            .collect();
+        if map.len() < total {
+            warn!(dropped=total - map.len(), version, "Some {} cache entries could not be read and were dropped.", name);
+        }
+        map
     }
 
     pub fn save(&self) -> Result<()> {
-        let text = serde_json::to_string_pretty(&self.map).into_diagnostic()?;
-        fs::write(&self.path, text).into_diagnostic()?;
-        Ok(())
+        #[cfg(feature = "sqlite")]
+        return sqlite::save_cache(&self.path, &self.name, V::VERSION, &self.map);
+
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let envelope = Envelope { version: V::VERSION, entries: &self.map };
+            let text = serde_json::to_string_pretty(&envelope).into_diagnostic()?;
+            fs::write(&self.path, text).into_diagnostic()?;
+            Ok(())
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
     pub fn into_threadsafe(self) -> Arc<RwLock<Cache<K, V>>> {
@@ -66,6 +171,15 @@ where
 
     // Convenience wrappers
     pub fn insert(&mut self, key: K, value: V) { self.map.insert(key, value); }
+    pub fn clear(&mut self) { self.map.clear(); }
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> { self.map.values_mut() }
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(key)
+    }
     pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
@@ -75,6 +189,80 @@ where
     }
 }
 
+/// Directory holding every cache file
+pub fn cache_dir() -> Result<PathBuf> {
+    let base = ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION).ok_or(miette::miette!("No project dirs"))?;
+    Ok(base.cache_dir().to_path_buf())
+}
+
 pub type AsyncCache<K, V> = Arc<RwLock<Cache<K, V>>>;
-pub type AsyncZoneCache = AsyncCache<String, String>;
+pub type ZoneCache = Cache<String, CachedZone>;
+pub type AsyncZoneCache = AsyncCache<String, CachedZone>;
+/// A and AAAA records keyed by [`record_cache_key`]
+pub type AsyncRecordCache = AsyncCache<String, CachedRecord>;
+
+/// How long a cached zone ID is used before it is looked up again
+const ZONE_CACHE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedZone {
+    pub id: String,
+    /// Unix time in seconds when the ID was looked up
+    pub at: u64,
+}
+
+impl CacheEntry for CachedZone {
+    const VERSION: u32 = 1;
+
+    fn migrate(version: u32, value: serde_json::Value) -> Option<Self> {
+        match version {
+            // A bare zone ID, kept until it expires or is rejected
+            0 => value.as_str()
+                .map(|id| Self { id: id.to_string(), at: unix_now() })
+                .or_else(|| serde_json::from_value(value).ok()),
+            _ => serde_json::from_value(value).ok(),
+        }
+    }
+}
+
+impl ZoneCache {
+    /// The cached ID of a zone, unless it has expired
+    pub fn zone_id(&self, zone: &str) -> Option<&str> {
+        self.get(zone)
+            .filter(|cached| unix_now().saturating_sub(cached.at) < ZONE_CACHE_TTL_SECS)
+            .map(|cached| cached.id.as_str())
+    }
+
+    pub fn insert_zone(&mut self, zone: impl Into<String>, id: String) {
+        self.insert(zone.into(), CachedZone { id, at: unix_now() });
+    }
+}
+
+impl CacheEntry for CachedRecord {
+    const VERSION: u32 = 1;
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+pub fn record_cache_key(name: &str, ip: &IpAddr) -> String {
+    record_key(name, if ip.is_ipv4() { "A" } else { "AAAA" })
+}
+
+/// Key for one record type of a domain, such as `home.example.com/AAAA`
+pub fn record_key(name: &str, r#type: &str) -> String {
+    format!("{}/{}", name.trim_end_matches('.').to_ascii_lowercase(), r#type)
+}
+
+/// How long ago a unix timestamp was, roughly
+pub fn format_age(at: u64) -> String {
+    let secs = unix_now().saturating_sub(at);
+    match secs {
+        0..60 => format!("{secs}s ago"),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
 
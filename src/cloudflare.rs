@@ -1,51 +1,414 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::{env, fs, io};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 use cloudflare::framework::{
     self, Environment,
-    auth::Credentials,
-    client::{ClientConfig, async_api::Client},
+    auth::{AuthClient, Credentials},
+    client::ClientConfig,
+    endpoint::{RequestBody, spec::EndpointSpec},
+    response::{ApiErrors, ApiFailure, ApiResponse, ApiSuccess, ResponseConverter, ResponseInfo},
 };
+use miette::Diagnostic;
+use reqwest::StatusCode;
+use url::Url;
+use thiserror::Error;
+use tracing::warn;
 
-pub fn make_client(token: String) -> Result<Arc<Client>, framework::Error> {
-    let auth = Credentials::UserAuthToken { token };
-    let c = ClientConfig::default();
-    let e = Environment::Production;
-    Ok(Arc::new(Client::new(auth, c, e)?))
+use crate::API_URL_OVERRIDE;
+use dns::{CachedRecord, CommentedRecord, RecordSettings};
+use zone::ZoneError;
+use crate::config::{self, ApiEnvironment, Cloudflare, Config, Retry};
+
+/// Environment variable that takes precedence over `cloudflare.token`
+pub const TOKEN_ENV: &str = "CFDNS_TOKEN";
+
+pub fn make_client(cloudflare: &Cloudflare) -> Result<Arc<ApiClient>, ClientError> {
+    build_client(cloudflare, env::var(TOKEN_ENV).ok(), None)
+}
+
+fn build_client(
+    cloudflare: &Cloudflare,
+    token_override: Option<String>,
+    proxy: Option<&config::Proxy>,
+) -> Result<Arc<ApiClient>, ClientError> {
+    let credentials = Credentials::UserAuthToken { token: token(cloudflare, token_override)? };
+    let environment = environment(cloudflare)?;
+
+    let mut builder = reqwest::Client::builder().timeout(
+        cloudflare.timeout_secs.map_or(ClientConfig::default().http_timeout, Duration::from_secs),
+    );
+    if let Some(proxy) = proxy {
+        let invalid = |source| ClientError::InvalidProxy { url: proxy.https.clone(), source };
+        let mut https = reqwest::Proxy::https(&proxy.https).map_err(invalid)?;
+        if let Some(no_proxy) = &proxy.no_proxy {
+            https = https.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+        }
+        builder = builder.proxy(https);
+    }
+    let http = builder.build().map_err(framework::Error::from)?;
+
+    Ok(Arc::new(ApiClient { http, environment, credentials, retry: cloudflare.retry, calls: AtomicU64::new(0) }))
+}
+
+/// A Cloudflare API client that retries rate limited and transient failures.
+/// Requests are sent with our own HTTP client rather than the cloudflare crate's, which can't be
+/// given a proxy and doesn't expose response headers.
+pub struct ApiClient {
+    http: reqwest::Client,
+    environment: Environment,
+    credentials: Credentials,
+    retry: Retry,
+    /// Requests sent so far, including retries
+    calls: AtomicU64,
+}
+
+impl ApiClient {
+    /// Number of requests sent since the client was created
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Issue an API request, retrying according to the configured policy
+    pub async fn request<Endpoint>(&self, endpoint: &Endpoint) -> ApiResponse<Endpoint::ResponseType>
+    where
+        Endpoint: EndpointSpec + Send + Sync,
+        Endpoint::ResponseType: ResponseConverter<Endpoint::JsonResponse>,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.send(endpoint).await {
+                (Err(e), retry_after) if attempt < self.retry.max_attempts && is_retryable(&e, &endpoint.method()) => {
+                    let delay = retry_after.unwrap_or_else(|| backoff(self.retry, attempt));
+                    warn!(attempt, delay_ms = delay.as_millis() as u64, error = %e, "Cloudflare request failed, retrying");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+
+    /// Issue a list request for every page of results, starting from the first
+    pub async fn request_all_pages<Endpoint, Page, T>(&self, endpoint_for_page: impl Fn(u32) -> Endpoint) -> Result<Vec<T>, ApiFailure>
+    where
+        Endpoint: EndpointSpec<JsonResponse = Page, ResponseType = ApiSuccess<Page>> + Send + Sync,
+        Page: IntoIterator<Item = T>,
+        ApiSuccess<Page>: ResponseConverter<Page>,
+    {
+        let mut results = Vec::new();
+        let mut page = 1;
+        loop {
+            let res = self.request(&endpoint_for_page(page)).await?;
+            let total_pages = res.result_info
+                .as_ref()
+                .and_then(|info| info.get("total_pages"))
+                .and_then(|pages| pages.as_u64())
+                .unwrap_or(1);
+            results.extend(res.result);
+
+            if u64::from(page) >= total_pages {
+                break;
+            }
+            page += 1;
+        }
+        Ok(results)
+    }
+
+    /// Send a single request, along with the delay asked for by a Retry-After header
+    async fn send<Endpoint>(&self, endpoint: &Endpoint) -> (ApiResponse<Endpoint::ResponseType>, Option<Duration>)
+    where
+        Endpoint: EndpointSpec + Send + Sync,
+        Endpoint::ResponseType: ResponseConverter<Endpoint::JsonResponse>,
+    {
+        let mut request = self.http
+            .request(endpoint.method(), endpoint.url(&self.environment))
+            .auth(&self.credentials);
+        match endpoint.body() {
+            Some(RequestBody::Json(json)) => request = request.body(json),
+            Some(RequestBody::Raw(bytes)) => request = request.body(bytes),
+            Some(RequestBody::MultiPart(_)) => return (Err(multipart_unsupported()), None),
+            None => {}
+        }
+        if let Some(content_type) = endpoint.content_type() {
+            request = request.header(reqwest::header::CONTENT_TYPE, content_type.as_ref());
+        }
+
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => return (Err(ApiFailure::Invalid(e)), None),
+        };
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs);
+
+        let result = if !status.is_success() {
+            let errors: ApiErrors = response.json().await.unwrap_or_default();
+            Err(ApiFailure::Error(status, errors))
+        } else if Endpoint::IS_RAW_BODY {
+            response.bytes().await
+                .map(|bytes| Endpoint::ResponseType::from_raw(bytes.to_vec()))
+                .map_err(ApiFailure::Invalid)
+        } else {
+            response.json::<ApiSuccess<Endpoint::JsonResponse>>().await
+                .map(Endpoint::ResponseType::from_json)
+                .map_err(ApiFailure::Invalid)
+        };
+        (result, retry_after)
+    }
+}
+
+/// The error for requests with a multipart body, which are refused before anything is sent since
+/// none of the endpoints cfdns uses upload files
+fn multipart_unsupported() -> ApiFailure {
+    let info = ResponseInfo {
+        code: 0,
+        message: "multipart request bodies are not supported".to_string(),
+        other: HashMap::new(),
+    };
+    ApiFailure::Error(StatusCode::NOT_IMPLEMENTED, ApiErrors { errors: vec![info], other: HashMap::new() })
+}
+
+/// Rate limiting, server errors and failures to reach the API are worth another attempt. A request
+/// that failed with a server error or timed out may still have been carried out, so only idempotent
+/// ones are sent again, and a create isn't repeated into a duplicate record.
+fn is_retryable(failure: &ApiFailure, method: &reqwest::Method) -> bool {
+    match failure {
+        ApiFailure::Error(StatusCode::TOO_MANY_REQUESTS, _) => true,
+        ApiFailure::Invalid(e) if e.is_connect() => true,
+        _ => may_have_applied(failure) && method.is_idempotent(),
+    }
+}
+
+/// Whether Cloudflare may have carried out a request despite it failing
+fn may_have_applied(failure: &ApiFailure) -> bool {
+    match failure {
+        ApiFailure::Error(StatusCode::NOT_IMPLEMENTED, _) => false,
+        ApiFailure::Error(status, _) => status.is_server_error(),
+        ApiFailure::Invalid(e) => e.is_timeout(),
+    }
+}
+
+/// Exponential backoff from the base delay, with jitter so concurrent records don't retry in lockstep
+fn backoff(retry: Retry, attempt: u32) -> Duration {
+    let delay = retry.base_delay_ms.saturating_mul(1 << (attempt - 1).min(16));
+    // RandomState is seeded randomly, which is plenty for jitter
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(delay / 2 + random % (delay / 2 + 1))
+}
+
+/// API clients for the default credentials and each additional account
+pub struct Clients<A = ApiClient> {
+    default: Arc<A>,
+    by_zone: HashMap<String, Arc<A>>,
+}
+
+impl Clients {
+    pub fn new(config: &Config) -> Result<Self, ClientError> {
+        let proxy = config.proxy.as_ref();
+        let default = build_client(&config.cloudflare, env::var(TOKEN_ENV).ok(), proxy)?;
+        let mut by_zone = HashMap::new();
+        for account in config.accounts.values() {
+            // CFDNS_TOKEN only replaces the default credentials
+            let client = build_client(&account.cloudflare, None, proxy)?;
+            for zone in &account.zones {
+                by_zone.insert(zone.clone(), client.clone());
+            }
+        }
+        Ok(Self { default, by_zone })
+    }
+
+    /// Requests sent by every client so far
+    pub fn api_calls(&self) -> u64 {
+        let mut clients = vec![&self.default];
+        for client in self.by_zone.values() {
+            // Zones of the same account share a client
+            if !clients.iter().any(|seen| Arc::ptr_eq(seen, client)) {
+                clients.push(client);
+            }
+        }
+        clients.iter().map(|client| client.calls()).sum()
+    }
+}
+
+impl<A> Clients<A> {
+    /// The client whose credentials manage `zone`
+    pub fn for_zone(&self, zone: &str) -> &A {
+        self.by_zone.get(zone).unwrap_or(&self.default)
+    }
+}
+
+/// A single API for every zone
+impl<A> From<A> for Clients<A> {
+    fn from(api: A) -> Self {
+        Self { default: Arc::new(api), by_zone: HashMap::new() }
+    }
+}
+
+/// The Cloudflare operations `update` relies on, so record processing can run against a test double
+pub trait DnsApi: Send + Sync {
+    fn fetch_zone_id(&self, zone_name: &str) -> impl Future<Output = Result<String, ZoneError>> + Send;
+
+    /// The first A and AAAA records with this name, preferring ones cfdns created
+    fn fetch_ip_records(
+        &self,
+        zone_id: &str,
+        domain: &str,
+    ) -> impl Future<Output = Result<(Option<CommentedRecord>, Option<CommentedRecord>), ApiFailure>> + Send;
+
+    /// Create a record marked with [`dns::MANAGED_COMMENT`]
+    fn create_record(
+        &self,
+        zone_id: &str,
+        domain: &str,
+        ip: IpAddr,
+        settings: RecordSettings<'_>,
+    ) -> impl Future<Output = Result<CommentedRecord, ApiFailure>> + Send;
+
+    /// Point an existing record at a new address. Settings left unset keep the record's current
+    /// values, and so does the comment of a record cfdns doesn't manage.
+    fn update_record(
+        &self,
+        zone_id: &str,
+        record: &CachedRecord,
+        ip: IpAddr,
+        settings: RecordSettings<'_>,
+    ) -> impl Future<Output = Result<CommentedRecord, ApiFailure>> + Send;
+
+    /// Every A and AAAA record with this name, for names published as a set of records
+    fn fetch_all_ip_records(
+        &self,
+        zone_id: &str,
+        domain: &str,
+    ) -> impl Future<Output = Result<Vec<CommentedRecord>, ApiFailure>> + Send;
+
+    fn delete_record(&self, zone_id: &str, record: &CachedRecord) -> impl Future<Output = Result<(), ApiFailure>> + Send;
+}
+
+impl DnsApi for ApiClient {
+    async fn fetch_zone_id(&self, zone_name: &str) -> Result<String, ZoneError> {
+        zone::fetch_zone_id(self, zone_name).await
+    }
+
+    async fn fetch_ip_records(&self, zone_id: &str, domain: &str) -> Result<(Option<CommentedRecord>, Option<CommentedRecord>), ApiFailure> {
+        dns::fetch_ip_records(self, zone_id, domain).await
+    }
+
+    async fn create_record(&self, zone_id: &str, domain: &str, ip: IpAddr, settings: RecordSettings<'_>) -> Result<CommentedRecord, ApiFailure> {
+        dns::create_dns_record(self, zone_id, domain, ip, settings).await
+    }
+
+    async fn update_record(&self, zone_id: &str, record: &CachedRecord, ip: IpAddr, settings: RecordSettings<'_>) -> Result<CommentedRecord, ApiFailure> {
+        dns::update_dns_record(self, zone_id, record, ip, settings).await
+    }
+
+    async fn fetch_all_ip_records(&self, zone_id: &str, domain: &str) -> Result<Vec<CommentedRecord>, ApiFailure> {
+        dns::fetch_all_ip_records(self, zone_id, domain).await
+    }
+
+    async fn delete_record(&self, zone_id: &str, record: &CachedRecord) -> Result<(), ApiFailure> {
+        dns::delete_ip_record(self, zone_id, record).await
+    }
+}
+
+fn token(cloudflare: &Cloudflare, token_override: Option<String>) -> Result<String, ClientError> {
+    match token_override {
+        Some(token) if !token.is_empty() => Ok(token),
+        _ if !cloudflare.token.is_empty() => Ok(cloudflare.token.clone()),
+        _ => match &cloudflare.token_file {
+            Some(path) => fs::read_to_string(path)
+                .map(|token| token.trim().to_string())
+                .map_err(|source| ClientError::TokenFile { path: path.clone(), source }),
+            None => Err(ClientError::MissingToken),
+        },
+    }
+}
+
+fn environment(cloudflare: &Cloudflare) -> Result<Environment, ClientError> {
+    let url = match (API_URL_OVERRIDE.get(), cloudflare.environment) {
+        (Some(url), _) => url.as_str(),
+        (None, ApiEnvironment::Production) => return Ok(Environment::Production),
+        (None, ApiEnvironment::Custom) => cloudflare.api_url.as_deref().ok_or(ClientError::MissingApiUrl)?,
+    };
+    // Endpoint paths are joined onto the base, which drops the last segment without a trailing slash
+    let url = if url.ends_with('/') { url.to_string() } else { format!("{url}/") };
+    Url::parse(&url).map_err(|source| ClientError::InvalidApiUrl { url: url.clone(), source })?;
+    Ok(Environment::Custom(url))
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum ClientError {
+    #[error("could not create Cloudflare API client")]
+    Framework(#[from] framework::Error),
+    #[error("no Cloudflare API token configured")]
+    #[diagnostic(help("set `cloudflare.token` or `cloudflare.token_file` in your config, or the CFDNS_TOKEN environment variable"))]
+    MissingToken,
+    #[error("unable to read Cloudflare API token from {path}")]
+    #[diagnostic(help("ensure the file exists and you have permission to read it"))]
+    TokenFile { path: PathBuf, #[source] source: io::Error },
+    #[error("the custom Cloudflare environment requires an API URL")]
+    #[diagnostic(help("set `cloudflare.api_url` in your config"))]
+    MissingApiUrl,
+    #[error("invalid proxy URL `{url}`")]
+    #[diagnostic(help("the proxy URL should look like http://proxy.example.com:3128"))]
+    InvalidProxy { url: String, #[source] source: reqwest::Error },
+    #[error("invalid Cloudflare API URL `{url}`")]
+    #[diagnostic(help("the API URL should look like https://api.example.com/client/v4/"))]
+    InvalidApiUrl { url: String, #[source] source: url::ParseError },
 }
 
 pub mod zone {
     use addr::parse_domain_name;
     use cloudflare::{
         endpoints::zones::zone::{ListZones, ListZonesParams},
-        framework::{client::async_api::Client, response::ApiFailure},
+        framework::response::ApiFailure,
     };
     use miette::{Diagnostic, Result};
     use reqwest::StatusCode;
     use thiserror::Error;
 
-    pub async fn fetch_zone_id(client: &Client, zone_name: &str) -> Result<String, ZoneError> {
-        let req = ListZones {
-            params: ListZonesParams {
-                name: Some(zone_name.to_string()),
-                ..Default::default()
-            },
-        };
-        let mut res = client
-            .request(&req)
+    use super::ApiClient;
+
+    /// The most zones Cloudflare returns in one page
+    const ZONES_PER_PAGE: u32 = 50;
+
+    pub async fn fetch_zone_id(client: &ApiClient, zone_name: &str) -> Result<String, ZoneError> {
+        let mut zones = client
+            .request_all_pages(|page| ListZones {
+                params: ListZonesParams {
+                    name: Some(zone_name.to_string()),
+                    page: Some(page),
+                    per_page: Some(ZONES_PER_PAGE),
+                    ..Default::default()
+                },
+            })
             .await
             .map_err(|e| from_api(zone_name.to_string(), e))?;
 
-        if res.result.len() < 1 {
+        if zones.is_empty() {
             return Err(ZoneError::NotFound(zone_name.to_string()));
         }
-        let zone = res.result.swap_remove(0);
+        let zone = zones.swap_remove(0);
 
         Ok(zone.id)
     }
 
     pub fn guess_zone_from_domain<'a>(domain: &'a str) -> Option<&'a str> {
-        let Ok(name) = parse_domain_name(&domain) else {
+        // A wildcard lives in the same zone as the name it covers
+        let domain = domain.strip_prefix("*.").unwrap_or(domain);
+        let Ok(name) = parse_domain_name(domain) else {
             return None;
         };
         name.root()
@@ -64,9 +427,13 @@ pub mod zone {
 
         #[error("Cloudflare API request failed for zone `{0}`")]
         Invalid(String, #[source] reqwest::Error),
+
+        #[error("Cloudflare API request for zone `{0}` timed out")]
+        #[diagnostic(help("raise `cloudflare.timeout_secs` if your connection is slow"))]
+        Timeout(String, #[source] reqwest::Error),
     }
 
-    fn from_api(zone_name: String, value: ApiFailure) -> ZoneError {
+    pub fn from_api(zone_name: String, value: ApiFailure) -> ZoneError {
         match value {
             ApiFailure::Error(code, _) => {
                 if code == StatusCode::NOT_FOUND {
@@ -77,74 +444,433 @@ pub mod zone {
                     ZoneError::Api(zone_name, code.as_u16())
                 }
             }
+            ApiFailure::Invalid(e) if e.is_timeout() => ZoneError::Timeout(zone_name, e),
             ApiFailure::Invalid(e) => ZoneError::Invalid(zone_name, e),
         }
     }
 }
 
 pub mod dns {
+    use std::env;
     use std::net::IpAddr;
+    use std::sync::OnceLock;
 
     use cloudflare::{
         endpoints::dns::dns::{
-            CreateDnsRecord, CreateDnsRecordParams, DnsContent, DnsRecord, ListDnsRecords,
-            ListDnsRecordsParams, UpdateDnsRecord, UpdateDnsRecordParams,
+            CreateDnsRecord, CreateDnsRecordParams, DeleteDnsRecord, DnsContent, DnsRecord,
+            ListDnsRecords, ListDnsRecordsParams,
+        },
+        framework::{
+            endpoint::{Method, RequestBody, serialize_query, spec::EndpointSpec},
+            response::{ApiFailure, ApiResult, ApiSuccess},
         },
-        framework::{client::async_api::Client, response::ApiFailure},
     };
     use miette::Diagnostic;
+    use reqwest::StatusCode;
+    use serde::{Deserialize, Serialize};
     use thiserror::Error;
-    use tracing::info;
+    use tracing::{info, warn};
+
+    use super::{ApiClient, DnsApi, backoff, may_have_applied};
+    use crate::config::APEX;
+
+    const RECORDS_PER_PAGE: u32 = 100;
+
+    /// Comment cfdns sets on the records it creates, so it can tell them apart from records
+    /// managed by hand
+    pub const MANAGED_COMMENT: &str = "managed-by=cfdns";
+
+    /// Environment variable naming this host in the marker of the records it creates, instead of
+    /// its hostname
+    pub const OWNER_ENV: &str = "CFDNS_OWNER";
+
+    /// The name of this host written after [`MANAGED_COMMENT`], so `prune` on one host leaves the
+    /// records of another alone. None if neither [`OWNER_ENV`] nor the hostname can be read.
+    pub fn local_owner() -> Option<&'static str> {
+        static OWNER: OnceLock<Option<String>> = OnceLock::new();
+        OWNER.get_or_init(|| {
+            env::var(OWNER_ENV).ok()
+                .or_else(hostname)
+                .map(|owner| owner.trim().to_string())
+                .filter(|owner| !owner.is_empty() && !owner.contains(';'))
+        }).as_deref()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn hostname() -> Option<String> {
+        std::fs::read_to_string("/proc/sys/kernel/hostname").ok()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn hostname() -> Option<String> {
+        nix::unistd::gethostname().ok()?.into_string().ok()
+    }
+
+    /// The comment for a record cfdns manages: [`MANAGED_COMMENT`] and this host's
+    /// [`local_owner`], followed by the one from the config
+    pub fn managed_comment(comment: Option<&str>) -> String {
+        marker(local_owner(), comment)
+    }
+
+    fn marker(owner: Option<&str>, comment: Option<&str>) -> String {
+        let mut marker = MANAGED_COMMENT.to_string();
+        if let Some(owner) = owner {
+            marker.push_str(&format!("; owner={owner}"));
+        }
+        if let Some(comment) = comment {
+            marker.push_str(&format!("; {comment}"));
+        }
+        marker
+    }
+
+    /// Whether a comment starts with [`MANAGED_COMMENT`]
+    fn is_marker(comment: Option<&str>) -> bool {
+        comment
+            .and_then(|comment| comment.strip_prefix(MANAGED_COMMENT))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("; "))
+    }
+
+    /// Whether a comment marks a record this host manages: one it created, or one marked before
+    /// markers named their host. Records another host created are left to that host.
+    fn is_own_marker(comment: Option<&str>) -> bool {
+        is_marker(comment) && marker_owner(comment).is_none_or(|owner| Some(owner) == local_owner())
+    }
+
+    /// The host named in a marker. Records created before markers named their host have none.
+    fn marker_owner(comment: Option<&str>) -> Option<&str> {
+        let rest = comment?.strip_prefix(MANAGED_COMMENT)?.strip_prefix("; owner=")?;
+        rest.split("; ").next()
+    }
+
+    /// A record along with its comment and tags, which the cloudflare crate's [`DnsRecord`] leaves out
+    #[derive(Debug, Deserialize)]
+    pub struct CommentedRecord {
+        #[serde(flatten)]
+        pub record: DnsRecord,
+        #[serde(default)]
+        pub comment: Option<String>,
+        #[serde(default)]
+        pub tags: Vec<String>,
+    }
+
+    impl ApiResult for CommentedRecord {}
+
+    impl CommentedRecord {
+        /// Whether cfdns on this host manages this record, see [`is_own_marker`]
+        pub fn is_managed(&self) -> bool {
+            is_own_marker(self.comment.as_deref())
+        }
+
+        /// The host whose cfdns created this record, if its marker names one
+        pub fn owner(&self) -> Option<&str> {
+            marker_owner(self.comment.as_deref())
+        }
+    }
+
+    /// One page of listed records
+    #[derive(Debug, Deserialize)]
+    #[serde(transparent)]
+    pub struct CommentedRecords(Vec<CommentedRecord>);
+
+    impl ApiResult for CommentedRecords {}
+
+    impl IntoIterator for CommentedRecords {
+        type Item = CommentedRecord;
+        type IntoIter = std::vec::IntoIter<CommentedRecord>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            self.0.into_iter()
+        }
+    }
+
+    /// [`ListDnsRecords`], keeping the comment of each record
+    struct ListCommentedRecords<'a> {
+        zone_identifier: &'a str,
+        params: ListDnsRecordsParams,
+    }
+
+    impl EndpointSpec for ListCommentedRecords<'_> {
+        type JsonResponse = CommentedRecords;
+        type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+        fn method(&self) -> Method {
+            Method::GET
+        }
+        fn path(&self) -> String {
+            format!("zones/{}/dns_records", self.zone_identifier)
+        }
+        fn query(&self) -> Option<String> {
+            serialize_query(&self.params)
+        }
+    }
+
+    /// [`CreateDnsRecord`] for an A or AAAA record, marked with [`MANAGED_COMMENT`]
+    struct CreateIpRecord<'a> {
+        zone_identifier: &'a str,
+        params: CreateIpRecordParams<'a>,
+    }
+
+    #[derive(Serialize)]
+    struct CreateIpRecordParams<'a> {
+        name: &'a str,
+        #[serde(flatten)]
+        content: DnsContent,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ttl: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        proxied: Option<bool>,
+        comment: String,
+        #[serde(skip_serializing_if = "<[_]>::is_empty")]
+        tags: &'a [String],
+    }
+
+    impl EndpointSpec for CreateIpRecord<'_> {
+        type JsonResponse = CommentedRecord;
+        type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+        fn method(&self) -> Method {
+            Method::POST
+        }
+        fn path(&self) -> String {
+            format!("zones/{}/dns_records", self.zone_identifier)
+        }
+        fn body(&self) -> Option<RequestBody<'_>> {
+            Some(RequestBody::Json(serde_json::to_string(&self.params).unwrap()))
+        }
+    }
+
+    /// [`UpdateDnsRecord`] as a PATCH, which leaves fields it doesn't send alone instead of
+    /// clearing them, so the record keeps its comment unless it is being adopted
+    struct PatchIpRecord<'a> {
+        zone_identifier: &'a str,
+        identifier: &'a str,
+        params: PatchIpRecordParams<'a>,
+    }
+
+    #[derive(Serialize)]
+    struct PatchIpRecordParams<'a> {
+        name: &'a str,
+        #[serde(flatten)]
+        content: DnsContent,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ttl: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        proxied: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        comment: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tags: Option<&'a [String]>,
+    }
+
+    impl EndpointSpec for PatchIpRecord<'_> {
+        type JsonResponse = CommentedRecord;
+        type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+        fn method(&self) -> Method {
+            Method::PATCH
+        }
+        fn path(&self) -> String {
+            format!("zones/{}/dns_records/{}", self.zone_identifier, self.identifier)
+        }
+        fn body(&self) -> Option<RequestBody<'_>> {
+            Some(RequestBody::Json(serde_json::to_string(&self.params).unwrap()))
+        }
+    }
+
+    /// Every record with this name, with their comments
+    async fn fetch_commented_records(client: &ApiClient, zone_id: &str, domain: Option<&str>) -> Result<Vec<CommentedRecord>, ApiFailure> {
+        let mut records: Vec<CommentedRecord> = client
+            .request_all_pages(|page| ListCommentedRecords {
+                zone_identifier: zone_id,
+                params: ListDnsRecordsParams {
+                    name: domain.map(str::to_string),
+                    page: Some(page),
+                    per_page: Some(RECORDS_PER_PAGE),
+                    ..Default::default()
+                },
+            })
+            .await?;
+        // The `*` of a wildcard name is only ever the literal label here, never a pattern that
+        // could pull in the records it covers
+        if let Some(domain) = domain.filter(|d| is_wildcard(d)) {
+            let domain = domain.trim_end_matches('.');
+            records.retain(|r| r.record.name.eq_ignore_ascii_case(domain));
+        }
+        Ok(records)
+    }
+
+    /// Whether `domain` is a wildcard name like `*.home.example.com`
+    pub fn is_wildcard(domain: &str) -> bool {
+        domain.starts_with("*.")
+    }
 
     pub async fn fetch_ip_records(
-        client: &Client,
+        client: &ApiClient,
         zone_id: &str,
         domain: &str,
-    ) -> Result<(Option<DnsRecord>, Option<DnsRecord>), ApiFailure> {
-        let req = ListDnsRecords {
-            zone_identifier: zone_id,
-            params: ListDnsRecordsParams {
-                name: Some(domain.to_string()),
-                ..Default::default()
-            },
-        };
-        let res = client.request(&req).await?;
+    ) -> Result<(Option<CommentedRecord>, Option<CommentedRecord>), ApiFailure> {
+        let records = fetch_commented_records(client, zone_id, Some(domain)).await?;
 
-        let mut v4 = None;
-        let mut v6 = None;
+        let mut v4: Option<CommentedRecord> = None;
+        let mut v6: Option<CommentedRecord> = None;
 
-        for record in res.result {
-            match record.content {
-                DnsContent::A { content: _ } => v4 = Some(record),
-                DnsContent::AAAA { content: _ } => v6 = Some(record),
-                _ => {}
+        for record in records {
+            let found = match record.record.content {
+                DnsContent::A { content: _ } => &mut v4,
+                DnsContent::AAAA { content: _ } => &mut v6,
+                _ => continue,
             };
+            // A record cfdns created wins over others with the same name and type
+            if found.as_ref().is_none_or(|r| !r.is_managed()) {
+                *found = Some(record);
+            }
         }
 
         Ok((v4, v6))
     }
 
+    pub async fn fetch_all_ip_records(client: &ApiClient, zone_id: &str, domain: &str) -> Result<Vec<CommentedRecord>, ApiFailure> {
+        let records = fetch_commented_records(client, zone_id, Some(domain)).await?;
+        Ok(records.into_iter().filter(|record| record_ip(&record.record).is_some()).collect())
+    }
+
+    /// Every A and AAAA record in a zone, with their comments
+    pub async fn list_ip_records(client: &ApiClient, zone_id: &str) -> Result<Vec<CommentedRecord>, ApiFailure> {
+        let records = fetch_commented_records(client, zone_id, None).await?;
+        Ok(records.into_iter().filter(|record| record_ip(&record.record).is_some()).collect())
+    }
+
+    /// The record name to send to Cloudflare, without a trailing dot. Apex records must use the zone
+    /// name itself, so `@`, an empty domain, or one that only differs from its zone by case or a
+    /// trailing dot is sent as the zone.
+    pub fn api_name<'a>(domain: &'a str, zone: &'a str) -> &'a str {
+        let zone = zone.trim_end_matches('.');
+        let domain = domain.trim_end_matches('.');
+        if domain.is_empty() || domain == APEX || domain.eq_ignore_ascii_case(zone) {
+            zone
+        } else {
+            domain
+        }
+    }
+
+    /// Every record in a zone, following pagination until all pages have been read
+    pub async fn list_all_records(client: &ApiClient, zone_id: &str) -> Result<Vec<CommentedRecord>, ApiFailure> {
+        fetch_commented_records(client, zone_id, None).await
+    }
+
+    /// The address held by an A or AAAA record
+    pub fn record_ip(record: &DnsRecord) -> Option<IpAddr> {
+        match record.content {
+            DnsContent::A { content } => Some(IpAddr::V4(content)),
+            DnsContent::AAAA { content } => Some(IpAddr::V6(content)),
+            _ => None,
+        }
+    }
+
+    /// Settings sent alongside the address of an A or AAAA record
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct RecordSettings<'a> {
+        pub ttl: Option<u32>,
+        pub proxied: Option<bool>,
+        /// Take over records cfdns didn't create, marking them with [`MANAGED_COMMENT`]
+        pub adopt: bool,
+        /// Comment from the config, written after [`MANAGED_COMMENT`]
+        pub comment: Option<&'a str>,
+        /// Tags from the config, replacing the record's tags when not empty
+        pub tags: &'a [String],
+    }
+
+    impl RecordSettings<'_> {
+        /// Whether an existing record has to be updated to match these settings
+        pub fn differs_from(&self, existing: &RecordState) -> bool {
+            self.ttl.is_some_and(|ttl| ttl != existing.ttl)
+                || self.proxied.is_some_and(|proxied| proxied != existing.proxied)
+        }
+
+        /// Whether a record cfdns manages, or is adopting, has to be updated to carry its marker and
+        /// the configured comment and tags
+        pub fn metadata_differs(&self, existing: &CachedRecord) -> bool {
+            if !existing.managed && !self.adopt {
+                return false;
+            }
+            // Adopted records, and ones marked before markers named their host, are claimed
+            let comment = !existing.managed
+                || marker_owner(existing.comment.as_deref()) != local_owner()
+                || (self.comment.is_some()
+                    && existing.comment.as_deref() != Some(managed_comment(self.comment).as_str()));
+            let tags = !self.tags.is_empty() && {
+                let (mut wanted, mut current) = (self.tags.to_vec(), existing.tags.clone());
+                wanted.sort();
+                current.sort();
+                wanted != current
+            };
+            comment || tags
+        }
+    }
+
+    /// The parts of an existing A or AAAA record that cfdns compares against
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct RecordState {
+        pub ip: IpAddr,
+        pub ttl: u32,
+        pub proxied: bool,
+    }
+
+    impl RecordState {
+        pub fn from_record(record: &DnsRecord) -> Option<Self> {
+            Some(Self { ip: record_ip(record)?, ttl: record.ttl, proxied: record.proxied })
+        }
+    }
+
+    /// Enough of an existing A or AAAA record to update it without looking it up first
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct CachedRecord {
+        pub id: String,
+        pub name: String,
+        #[serde(flatten)]
+        pub state: RecordState,
+        /// Whether the record carries [`MANAGED_COMMENT`]
+        #[serde(default)]
+        pub managed: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub comment: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub tags: Vec<String>,
+    }
+
+    impl CachedRecord {
+        pub fn from_record(record: &CommentedRecord) -> Option<Self> {
+            Some(Self::new(
+                record.record.id.clone(),
+                record.record.name.clone(),
+                RecordState::from_record(&record.record)?,
+                record.comment.clone(),
+                record.tags.clone(),
+            ))
+        }
+
+        /// A record read from somewhere other than Cloudflare, such as a snapshot
+        pub fn new(id: String, name: String, state: RecordState, comment: Option<String>, tags: Vec<String>) -> Self {
+            Self { managed: is_own_marker(comment.as_deref()), id, name, state, comment, tags }
+        }
+    }
+
     pub async fn try_update_record(
-        client: &Client,
+        client: &impl DnsApi,
         zone_id: &str,
         domain: &str,
-        existing: Option<DnsRecord>,
-        ip: IpAddr
-    ) -> Result<Option<DnsRecord>, UpdateError> {
+        existing: Option<CachedRecord>,
+        ip: IpAddr,
+        settings: RecordSettings<'_>,
+        force: bool,
+    ) -> Result<Option<CommentedRecord>, UpdateError> {
         if let Some(existing) = existing {
-            let existing_ip = match existing.content {
-                DnsContent::A { content } => IpAddr::V4(content),
-                DnsContent::AAAA { content } => IpAddr::V6(content),
-                _ => return Err(UpdateError::NotAnIpRecord),
-            };
-            if ip != existing_ip {
-                info!(domain, %ip, old_ip=%existing_ip, "Updating DNS record");
-                let updated_record = update_dns_record(client, zone_id, &existing, ip)
+            let state = existing.state;
+            if force || ip != state.ip || settings.differs_from(&state) || settings.metadata_differs(&existing) {
+                info!(domain, %ip, old_ip=%state.ip, "Updating DNS record");
+                let updated_record = client.update_record(zone_id, &existing, ip, settings)
                     .await
-                    .map_err(|source| UpdateError::Cloudflare {
-                        domain: domain.to_string(),
-                        source,
-                    })?;
+                    .map_err(|e| UpdateError::cloudflare(domain, e))?;
                 return Ok(Some(updated_record));
             } else {
                 info!(domain, %ip, "Skipping up-to-date record");
@@ -152,29 +878,159 @@ pub mod dns {
             }
         } else {
             info!(domain, %ip, "Creating new DNS record");
-            let created_record = create_dns_record(client, zone_id, domain, ip)
+            let created_record = client.create_record(zone_id, domain, ip, settings)
                 .await
-                .map_err(|source| UpdateError::Cloudflare {
-                    domain: domain.to_string(),
-                    source,
-                })?;
+                .map_err(|e| UpdateError::cloudflare(domain, e))?;
             return Ok(Some(created_record));
         }
     }
 
+    /// Update a record by its cached ID, without looking it up first. Returns `None` if the record
+    /// no longer exists, so the caller can fall back to [`try_update_record`].
+    pub async fn try_update_cached_record(
+        client: &impl DnsApi,
+        zone_id: &str,
+        domain: &str,
+        cached: &CachedRecord,
+        ip: IpAddr,
+        settings: RecordSettings<'_>,
+    ) -> Result<Option<CommentedRecord>, UpdateError> {
+        info!(domain, %ip, record_id = cached.id, "Updating DNS record by cached ID");
+        match client.update_record(zone_id, cached, ip, settings).await {
+            Ok(record) => Ok(Some(record)),
+            Err(ApiFailure::Error(StatusCode::NOT_FOUND, _)) => Ok(None),
+            Err(e) => Err(UpdateError::cloudflare(domain, e)),
+        }
+    }
+
+    /// Addresses added to and removed from a set of records with the same name and type
+    #[derive(Debug, Default, Clone)]
+    pub struct RecordSetChanges {
+        pub added: Vec<IpAddr>,
+        pub removed: Vec<IpAddr>,
+        /// Whether a record was sent to Cloudflare, including for settings alone
+        pub changed: bool,
+    }
+
+    /// What [`reconcile_record_set`] sends to Cloudflare to make a set of records hold the wanted addresses
+    #[derive(Debug, Default)]
+    pub struct RecordSetPlan {
+        /// Records already holding a wanted address, whose settings have to be updated
+        pub refresh: Vec<CachedRecord>,
+        /// Records holding an address that is no longer wanted, each pointed at a missing one
+        pub repoint: Vec<(CachedRecord, IpAddr)>,
+        /// Missing addresses left without a stale record to point at them
+        pub create: Vec<IpAddr>,
+        /// Stale records left over once every address has a record
+        pub delete: Vec<CachedRecord>,
+    }
+
+    impl RecordSetPlan {
+        pub fn changes(&self) -> RecordSetChanges {
+            let added = self.repoint.iter().map(|(_, ip)| *ip).chain(self.create.iter().copied());
+            let removed = self.repoint.iter().map(|(record, _)| record).chain(&self.delete).map(|r| r.state.ip);
+            RecordSetChanges {
+                added: added.collect(),
+                removed: removed.collect(),
+                changed: !self.refresh.is_empty() || !self.repoint.is_empty() || !self.create.is_empty() || !self.delete.is_empty(),
+            }
+        }
+    }
+
+    /// Plan how the records of one type with this name come to hold exactly `ips`, one record per
+    /// address. Records holding an address that is no longer wanted are pointed at a new one where
+    /// possible, and deleted otherwise, along with duplicates. Records cfdns didn't create are only
+    /// kept, unless they are being adopted.
+    pub fn plan_record_set(domain: &str, existing: Vec<CachedRecord>, ips: &[IpAddr], settings: RecordSettings<'_>, force: bool) -> RecordSetPlan {
+        let mut plan = RecordSetPlan::default();
+        let mut kept = Vec::new();
+        let mut stale = Vec::new();
+        for record in existing {
+            let ip = record.state.ip;
+            if !ips.contains(&ip) || kept.contains(&ip) {
+                if record.managed || settings.adopt {
+                    stale.push(record);
+                } else {
+                    warn!(domain, %ip, "Leaving a record cfdns on this host didn't create");
+                }
+                continue;
+            }
+            kept.push(ip);
+            if force || settings.differs_from(&record.state) || settings.metadata_differs(&record) {
+                plan.refresh.push(record);
+            }
+        }
+
+        let mut missing = ips.iter().copied().filter(|ip| !kept.contains(ip));
+        let mut stale = stale.into_iter();
+        loop {
+            match (missing.next(), stale.next()) {
+                (Some(ip), Some(record)) => plan.repoint.push((record, ip)),
+                (Some(ip), None) => plan.create.push(ip),
+                (None, Some(record)) => plan.delete.push(record),
+                (None, None) => break,
+            }
+        }
+        plan
+    }
+
+    /// Carry out [`plan_record_set`] for the records of one type with this name
+    pub async fn reconcile_record_set(
+        client: &impl DnsApi,
+        zone_id: &str,
+        domain: &str,
+        existing: Vec<CachedRecord>,
+        ips: &[IpAddr],
+        settings: RecordSettings<'_>,
+        force: bool,
+    ) -> Result<RecordSetChanges, UpdateError> {
+        let plan = plan_record_set(domain, existing, ips, settings, force);
+        for record in &plan.refresh {
+            let ip = record.state.ip;
+            info!(domain, %ip, "Updating DNS record");
+            client.update_record(zone_id, record, ip, settings)
+                .await
+                .map_err(|e| UpdateError::cloudflare(domain, e))?;
+        }
+        for (record, ip) in &plan.repoint {
+            info!(domain, %ip, old_ip=%record.state.ip, "Updating DNS record");
+            client.update_record(zone_id, record, *ip, settings)
+                .await
+                .map_err(|e| UpdateError::cloudflare(domain, e))?;
+        }
+        for &ip in &plan.create {
+            info!(domain, %ip, "Creating new DNS record");
+            client.create_record(zone_id, domain, ip, settings)
+                .await
+                .map_err(|e| UpdateError::cloudflare(domain, e))?;
+        }
+        for record in &plan.delete {
+            info!(domain, ip=%record.state.ip, "Deleting DNS record");
+            client.delete_record(zone_id, record)
+                .await
+                .map_err(|e| UpdateError::cloudflare(domain, e))?;
+        }
+        Ok(plan.changes())
+    }
+
+    /// The changes [`reconcile_record_set`] would make, without making them
+    pub fn reconcile_record_set_dry_run(domain: &str, existing: Vec<CachedRecord>, ips: &[IpAddr], settings: RecordSettings<'_>, force: bool) -> RecordSetChanges {
+        let changes = plan_record_set(domain, existing, ips, settings, force).changes();
+        info!(domain, added=?changes.added, removed=?changes.removed, "Reconciling DNS record set (dry-run)");
+        changes
+    }
+
     pub async fn try_update_record_dry_run(
         domain: &str,
-        existing: Option<DnsRecord>,
-        ip: IpAddr
+        existing: Option<&CachedRecord>,
+        ip: IpAddr,
+        settings: RecordSettings<'_>,
+        force: bool,
     ) -> Result<Option<()>, UpdateError> {
         if let Some(existing) = existing {
-            let existing_ip = match existing.content {
-                DnsContent::A { content } => IpAddr::V4(content),
-                DnsContent::AAAA { content } => IpAddr::V6(content),
-                _ => return Err(UpdateError::NotAnIpRecord),
-            };
-            if ip != existing_ip {
-                info!(domain, %ip, old_ip=%existing_ip, "Updating DNS record (dry-run)");
+            let state = existing.state;
+            if force || ip != state.ip || settings.differs_from(&state) || settings.metadata_differs(existing) {
+                info!(domain, %ip, old_ip=%state.ip, "Updating DNS record (dry-run)");
                 return Ok(Some(()));
             } else {
                 info!(domain, %ip, "Skipping up-to-date record (dry-run)");
@@ -186,54 +1042,163 @@ pub mod dns {
         }
     }
 
-    async fn create_dns_record(
-        client: &Client,
+    pub(super) async fn create_dns_record(
+        client: &ApiClient,
         zone_id: &str,
         domain: &str,
         ip: IpAddr,
-    ) -> Result<DnsRecord, ApiFailure> {
+        settings: RecordSettings<'_>,
+    ) -> Result<CommentedRecord, ApiFailure> {
         let content = match ip {
             IpAddr::V4(ip) => DnsContent::A { content: ip },
             IpAddr::V6(ip) => DnsContent::AAAA { content: ip },
         };
-        let req = CreateDnsRecord {
+        if is_wildcard(domain) {
+            info!(domain, "Creating a wildcard record, it answers for every name below {} without its own record", &domain[2..]);
+        }
+        let req = CreateIpRecord {
             zone_identifier: zone_id,
-            params: CreateDnsRecordParams {
+            params: CreateIpRecordParams {
                 name: domain,
                 content,
-                ttl: None,
-                priority: None,
-                proxied: None,
+                ttl: settings.ttl,
+                proxied: settings.proxied,
+                comment: managed_comment(settings.comment),
+                tags: settings.tags,
             },
         };
-        let res = client.request(&req).await?;
-        Ok(res.result)
+        // A create isn't retried by the client when it may have gone through, so look for the
+        // record before sending it again
+        let mut attempt = 1;
+        loop {
+            match client.request(&req).await {
+                Err(e) if attempt < client.retry.max_attempts && may_have_applied(&e) => {
+                    let delay = backoff(client.retry, attempt);
+                    warn!(domain, attempt, delay_ms = delay.as_millis() as u64, error = %e, "Creating DNS record failed, checking for it before retrying");
+                    tokio::time::sleep(delay).await;
+                    let created = fetch_all_ip_records(client, zone_id, domain)
+                        .await?
+                        .into_iter()
+                        .find(|r| r.is_managed() && record_ip(&r.record) == Some(ip));
+                    if let Some(created) = created {
+                        return Ok(created);
+                    }
+                    attempt += 1;
+                }
+                result => return result.map(|res| res.result),
+            }
+        }
     }
 
-    async fn update_dns_record(
-        client: &Client,
+    pub(super) async fn update_dns_record(
+        client: &ApiClient,
         zone_id: &str,
-        record: &DnsRecord,
+        record: &CachedRecord,
         new_ip: IpAddr,
-    ) -> Result<DnsRecord, ApiFailure> {
+        settings: RecordSettings<'_>,
+    ) -> Result<CommentedRecord, ApiFailure> {
         let content = match new_ip {
             IpAddr::V4(ip) => DnsContent::A { content: ip },
             IpAddr::V6(ip) => DnsContent::AAAA { content: ip },
         };
-        let req = UpdateDnsRecord {
+        let req = PatchIpRecord {
             zone_identifier: zone_id,
             identifier: &record.id,
-            params: UpdateDnsRecordParams {
+            params: PatchIpRecordParams {
                 name: &record.name,
                 content,
-                ttl: None,
-                proxied: None,
+                ttl: settings.ttl,
+                proxied: settings.proxied,
+                comment: (settings.adopt || record.managed).then(|| managed_comment(settings.comment)),
+                tags: (settings.adopt || record.managed).then_some(settings.tags).filter(|tags| !tags.is_empty()),
             },
         };
         let res = client.request(&req).await?;
         Ok(res.result)
     }
 
+    pub(super) async fn delete_ip_record(client: &ApiClient, zone_id: &str, record: &CachedRecord) -> Result<(), ApiFailure> {
+        let req = DeleteDnsRecord {
+            zone_identifier: zone_id,
+            identifier: &record.id,
+        };
+        client.request(&req).await?;
+        Ok(())
+    }
+
+    pub async fn fetch_txt_records(
+        client: &ApiClient,
+        zone_id: &str,
+        domain: &str,
+    ) -> Result<Vec<DnsRecord>, ApiFailure> {
+        let records = client
+            .request_all_pages(|page| ListDnsRecords {
+                zone_identifier: zone_id,
+                params: ListDnsRecordsParams {
+                    name: Some(domain.to_string()),
+                    page: Some(page),
+                    per_page: Some(RECORDS_PER_PAGE),
+                    ..Default::default()
+                },
+            })
+            .await?;
+        Ok(records
+            .into_iter()
+            .filter(|record| matches!(record.content, DnsContent::TXT { .. }))
+            .collect())
+    }
+
+    /// The value of a TXT record without the surrounding quotes Cloudflare may add
+    pub fn txt_value(record: &DnsRecord) -> Option<&str> {
+        match &record.content {
+            DnsContent::TXT { content } => Some(content.trim_matches('"')),
+            _ => None,
+        }
+    }
+
+    /// Add a TXT record holding `value` to a domain, returning `None` if one already holds it. Other
+    /// TXT records at the name are left alone, since they may be SPF records or another challenge
+    /// token for the same name.
+    pub async fn try_update_txt_record(
+        client: &ApiClient,
+        zone_id: &str,
+        domain: &str,
+        existing: Vec<DnsRecord>,
+        value: &str,
+    ) -> Result<Option<DnsRecord>, UpdateError> {
+        if existing.iter().any(|r| txt_value(r) == Some(value)) {
+            info!(domain, value, "Skipping up-to-date TXT record");
+            return Ok(None);
+        }
+        info!(domain, value, others = existing.len(), "Creating TXT record");
+        let req = CreateDnsRecord {
+            zone_identifier: zone_id,
+            params: CreateDnsRecordParams {
+                name: domain,
+                content: DnsContent::TXT { content: value.to_string() },
+                ttl: None,
+                priority: None,
+                proxied: None,
+            },
+        };
+        let res = client.request(&req).await.map_err(|e| UpdateError::cloudflare(domain, e))?;
+        Ok(Some(res.result))
+    }
+
+    pub async fn delete_dns_record(
+        client: &ApiClient,
+        zone_id: &str,
+        record: &DnsRecord,
+    ) -> Result<(), UpdateError> {
+        info!(domain = record.name, id = record.id, "Deleting DNS record");
+        let req = DeleteDnsRecord {
+            zone_identifier: zone_id,
+            identifier: &record.id,
+        };
+        client.request(&req).await.map_err(|e| UpdateError::cloudflare(&record.name, e))?;
+        Ok(())
+    }
+
     #[derive(Debug, Error, Diagnostic)]
     pub enum UpdateError {
         #[error("the record returned was not an A or AAAA record")]
@@ -242,5 +1207,208 @@ pub mod dns {
         #[error("the DNS update to `{domain}` failed")]
         #[help("check your permissions on your Cloudflare API token")]
         Cloudflare { domain: String, source: ApiFailure },
+        #[error("failed to fetch existing DNS records for `{domain}`")]
+        #[help("check your permissions on your Cloudflare API token")]
+        Fetch { domain: String, source: ApiFailure },
+        #[error("Cloudflare API request for `{domain}` timed out")]
+        #[help("raise `cloudflare.timeout_secs` if your connection is slow")]
+        Timeout { domain: String, #[source] source: reqwest::Error },
+        #[error("`{domain}` already points at {existing} with a record cfdns on this host didn't create")]
+        #[help("run with --force, or set `adopt: true` on the record, to let cfdns take it over")]
+        Unmanaged { domain: String, existing: IpAddr },
+    }
+
+    impl UpdateError {
+        /// A failed create, update or delete request
+        pub fn cloudflare(domain: impl Into<String>, failure: ApiFailure) -> Self {
+            match failure {
+                ApiFailure::Invalid(source) if source.is_timeout() => Self::Timeout { domain: domain.into(), source },
+                source => Self::Cloudflare { domain: domain.into(), source },
+            }
+        }
+
+        /// Whether Cloudflare rejected the zone as a whole, as it does for a zone ID that no longer exists
+        pub fn is_zone_rejected(&self) -> bool {
+            matches!(
+                self,
+                Self::Cloudflare { source: ApiFailure::Error(status, _), .. }
+                    | Self::Fetch { source: ApiFailure::Error(status, _), .. }
+                    if *status == StatusCode::NOT_FOUND || *status == StatusCode::FORBIDDEN
+            )
+        }
+
+        /// A failed request for existing records
+        pub fn fetch(domain: impl Into<String>, failure: ApiFailure) -> Self {
+            match failure {
+                ApiFailure::Invalid(source) if source.is_timeout() => Self::Timeout { domain: domain.into(), source },
+                source => Self::Fetch { domain: domain.into(), source },
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn marker_names_its_host() {
+            let comment = marker(Some("nas"), Some("eth0"));
+            assert_eq!(comment, "managed-by=cfdns; owner=nas; eth0");
+            assert!(is_marker(Some(&comment)));
+            assert_eq!(marker_owner(Some(&comment)), Some("nas"));
+            assert_eq!(marker_owner(Some(&marker(Some("nas"), None))), Some("nas"));
+        }
+
+        #[test]
+        fn markers_without_a_host_have_no_owner() {
+            assert!(is_marker(Some("managed-by=cfdns")));
+            assert!(is_marker(Some("managed-by=cfdns; eth0")));
+            assert_eq!(marker_owner(Some("managed-by=cfdns; eth0")), None);
+            assert!(!is_marker(Some("managed-by=cfdns-other")));
+            assert_eq!(marker_owner(Some("owner=nas")), None);
+        }
+
+        #[test]
+        fn apex_names_are_sent_as_the_zone() {
+            assert_eq!(api_name("@", "example.com"), "example.com");
+            assert_eq!(api_name("", "example.com"), "example.com");
+            assert_eq!(api_name("Example.COM", "example.com"), "example.com");
+            assert_eq!(api_name("example.com.", "example.com"), "example.com");
+            assert_eq!(api_name("example.com", "example.com."), "example.com");
+        }
+
+        #[test]
+        fn other_names_are_sent_as_written() {
+            assert_eq!(api_name("home.example.com", "example.com"), "home.example.com");
+            assert_eq!(api_name("home.example.com.", "example.com"), "home.example.com");
+            assert_eq!(api_name("*.example.com", "example.com"), "*.example.com");
+            assert_eq!(api_name("example.com", "home.example.com"), "example.com");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cloudflare::framework::endpoint::{Method, MultipartBody, MultipartPart};
+
+    use super::*;
+
+    /// An upload, which cfdns never sends
+    struct Upload;
+
+    impl MultipartBody for Upload {
+        fn parts(&self) -> Vec<(String, MultipartPart)> {
+            vec![("file".into(), MultipartPart::Text("contents".into()))]
+        }
+    }
+
+    impl EndpointSpec for Upload {
+        type JsonResponse = ();
+        type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+        fn method(&self) -> Method {
+            Method::POST
+        }
+
+        fn path(&self) -> String {
+            "upload".into()
+        }
+
+        fn body(&self) -> Option<RequestBody<'_>> {
+            Some(RequestBody::MultiPart(self))
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_multipart_bodies() {
+        let client = build_client(&Cloudflare::default(), Some("token".into()), None).unwrap();
+        let result = client.request(&Upload).await;
+        assert!(matches!(result, Err(ApiFailure::Error(StatusCode::NOT_IMPLEMENTED, _))));
+        assert_eq!(client.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn only_idempotent_requests_are_retried_after_a_timeout() {
+        let http = reqwest::Client::builder().timeout(Duration::from_millis(100)).build().unwrap();
+        // Accepts connections without ever answering
+        let silent = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let timeout = ApiFailure::Invalid(http.post(format!("http://{}/", silent.local_addr().unwrap())).send().await.unwrap_err());
+        assert!(is_retryable(&timeout, &Method::GET));
+        assert!(is_retryable(&timeout, &Method::DELETE));
+        assert!(!is_retryable(&timeout, &Method::POST));
+
+        // Nothing was sent when the connection is refused, so even a create is safe to resend
+        let closed = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap();
+        let refused = ApiFailure::Invalid(http.post(format!("http://{closed}/")).send().await.unwrap_err());
+        assert!(is_retryable(&refused, &Method::POST));
+    }
+
+    #[test]
+    fn only_idempotent_requests_are_retried_after_a_server_error() {
+        let bad_gateway = ApiFailure::Error(StatusCode::BAD_GATEWAY, ApiErrors::default());
+        assert!(is_retryable(&bad_gateway, &Method::GET));
+        assert!(!is_retryable(&bad_gateway, &Method::POST));
+        let rate_limited = ApiFailure::Error(StatusCode::TOO_MANY_REQUESTS, ApiErrors::default());
+        assert!(is_retryable(&rate_limited, &Method::POST));
+    }
+
+    /// Answer one request per connection on the loopback address with each of `responses` in turn,
+    /// returning the API URL and the method of every request
+    fn serve_api(responses: Vec<(u16, serde_json::Value)>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut methods = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                methods.push(line.split(' ').next().unwrap().to_string());
+                let mut length = 0;
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                let body = body.to_string();
+                write!(stream, "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+            }
+            methods
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn create_looks_for_the_record_before_sending_it_again() {
+        let ip = std::net::Ipv4Addr::new(198, 51, 100, 2);
+        let created = serde_json::json!({
+            "meta": {}, "name": "home.example.com", "ttl": 1, "proxied": false, "proxiable": true,
+            "modified_on": "2025-01-01T00:00:00Z", "created_on": "2025-01-01T00:00:00Z",
+            "type": "A", "content": ip.to_string(), "id": "record-0", "comment": dns::managed_comment(None),
+        });
+        let failed = serde_json::json!({ "success": false, "errors": [], "messages": [] });
+        let listed = serde_json::json!({ "success": true, "errors": [], "messages": [], "result": [created] });
+        let (url, server) = serve_api(vec![(502, failed), (200, listed)]);
+        let cloudflare = Cloudflare {
+            environment: ApiEnvironment::Custom,
+            api_url: Some(url),
+            retry: Retry { max_attempts: 3, base_delay_ms: 1 },
+            ..Default::default()
+        };
+        let client = build_client(&cloudflare, Some("token".into()), None).unwrap();
+
+        let record = client.create_record("zone-id", "home.example.com", IpAddr::V4(ip), RecordSettings::default()).await.unwrap();
+
+        assert_eq!(record.record.id, "record-0");
+        assert_eq!(server.join().unwrap(), ["POST", "GET"]);
     }
 }
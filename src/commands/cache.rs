@@ -0,0 +1,80 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use clap::Subcommand;
+use colored::Colorize;
+use miette::Result;
+use tracing::instrument;
+
+use crate::{
+    RECORD_CACHE_NAME, ZONE_CACHE_NAME,
+    cache::{Cache, ZoneCache, cache_dir, format_age},
+    cloudflare::dns::CachedRecord,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    /// Print the cached zone and record IDs
+    Show,
+    /// Clear a single zone, or every cached zone and record
+    Clear {
+        /// Zone to forget. Clears the whole cache if omitted.
+        zone: Option<String>,
+    },
+    /// Print the directory holding the cache files
+    Path,
+}
+
+#[instrument(skip_all, name = "cache")]
+pub async fn cache(action: CacheAction) -> Result<()> {
+    match action {
+        CacheAction::Show => show(),
+        CacheAction::Clear { zone } => clear(zone.as_deref()),
+        CacheAction::Path => {
+            println!("{}", cache_dir()?.display());
+            Ok(())
+        }
+    }
+}
+
+fn show() -> Result<()> {
+    let zones: ZoneCache = Cache::load(ZONE_CACHE_NAME)?;
+    let records: Cache<String, CachedRecord> = Cache::load(RECORD_CACHE_NAME)?;
+
+    println!("{} {}", "Zones".bold(), zones.path().display().to_string().dimmed());
+    let mut zone_entries: Vec<_> = zones.map.iter().collect();
+    zone_entries.sort_by_key(|(name, _)| *name);
+    for (name, zone) in zone_entries {
+        println!("  {}  {}  {}", name.bold(), zone.id, format_age(zone.at).dimmed());
+    }
+
+    println!("{} {}", "Records".bold(), records.path().display().to_string().dimmed());
+    let mut record_entries: Vec<_> = records.map.iter().collect();
+    record_entries.sort_by_key(|(key, _)| *key);
+    for (key, record) in record_entries {
+        println!("  {}  {}  {}", key.bold(), record.id, record.state.ip);
+    }
+    Ok(())
+}
+
+fn clear(zone: Option<&str>) -> Result<()> {
+    let mut zones: ZoneCache = Cache::load(ZONE_CACHE_NAME)?;
+    match zone {
+        Some(zone) => {
+            if zones.remove(zone).is_none() {
+                println!("Zone `{zone}` was not cached.");
+                return Ok(());
+            }
+            zones.save()?;
+            println!("Removed zone `{zone}` from the cache.");
+        }
+        None => {
+            let mut records: Cache<String, CachedRecord> = Cache::load(RECORD_CACHE_NAME)?;
+            zones.clear();
+            records.clear();
+            zones.save()?;
+            records.save()?;
+            println!("Cleared the zone and record caches.");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use futures::StreamExt;
+use miette::Result;
+use rtnetlink::{
+    Handle,
+    constants::{RTMGRP_IPV4_IFADDR, RTMGRP_IPV6_IFADDR},
+    packet_core::{NetlinkMessage, NetlinkPayload},
+    packet_route::RouteNetlinkMessage,
+    sys::{AsyncSocket, SocketAddr},
+};
+use tracing::{debug, error, info, instrument};
+
+use crate::{
+    commands::Updater,
+    config::{Config, WEB_LOOKUP_INTERFACE},
+    netlink::get_links,
+    networking::{self, DEFAULT_INTERFACE, NetworkError},
+    state,
+};
+
+/// How long to wait for address changes to settle before updating, since a DHCP renewal or SLAAC
+/// rotation usually arrives as a burst of messages
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Keep running and update records as soon as a configured interface gains or loses an address
+#[instrument(skip_all, name = "daemon")]
+pub async fn daemon(custom_config: Option<&Path>) -> Result<()> {
+    let (mut conn, handle, mut events) = rtnetlink::new_connection().map_err(NetworkError::Connection)?;
+    conn.socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR))
+        .map_err(NetworkError::Subscribe)?;
+    tokio::spawn(conn);
+
+    let config = match custom_config {
+        Some(custom) => Config::load(custom),
+        None => Config::load_default(),
+    }?;
+    let _lock = state::lock(Duration::ZERO).await?;
+
+    let updater = Updater::new(&config, handle.clone(), None)?;
+    let mut indices = interface_indices(&handle, &config).await?;
+
+    info!(interfaces = config.interfaces.len(), "Running initial update");
+    run(&updater, &handle, &config, None).await;
+
+    let mut pending: HashSet<String> = HashSet::new();
+    loop {
+        let event = if pending.is_empty() {
+            events.next().await
+        } else {
+            match tokio::time::timeout(DEBOUNCE, events.next()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    info!(interfaces = ?pending, "Addresses changed, updating records");
+                    run(&updater, &handle, &config, Some(&pending)).await;
+                    pending.clear();
+                    continue;
+                }
+            }
+        };
+
+        let Some((message, _)) = event else {
+            return Err(NetworkError::EventsClosed.into());
+        };
+        let Some(index) = address_event_index(message) else {
+            continue;
+        };
+
+        // Interfaces can be created after startup or renumbered when they are recreated
+        if !indices.contains_key(&index) {
+            indices = interface_indices(&handle, &config).await?;
+        }
+        if let Some(name) = indices.get(&index) {
+            debug!(interface = name, "Address event");
+            pending.insert(name.clone());
+        }
+        // The default route can move to any link, so every address change may affect `default`
+        // and the top-level records
+        if config.interfaces.contains_key(DEFAULT_INTERFACE) {
+            pending.insert(DEFAULT_INTERFACE.to_owned());
+        }
+        if !config.records.is_empty() {
+            pending.insert(WEB_LOOKUP_INTERFACE.to_owned());
+        }
+    }
+}
+
+/// Update the records of the given interfaces, or every interface when `only` is None.
+/// Failures are logged rather than returned so a single bad run doesn't stop the daemon.
+async fn run(updater: &Updater, handle: &Handle, config: &Config, only: Option<&HashSet<String>>) {
+    // Links matching a MAC address or pattern can come and go, so expand patterns on every run
+    let interfaces = match networking::expand_interfaces(handle, config).await {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            error!(error = %e, "Could not list interfaces");
+            return;
+        }
+    };
+    let interfaces = interfaces
+        .iter()
+        .filter(|(name, _)| only.is_none_or(|only| only.contains(*name)));
+
+    if let Err(e) = updater.run(interfaces, false).await {
+        error!(error = %e, "Update failed");
+    }
+    if let Err(e) = updater.save() {
+        error!(error = %e, "Failed to save caches and state");
+    }
+}
+
+/// Map link indices to the names of configured interfaces
+async fn interface_indices(handle: &Handle, config: &Config) -> Result<HashMap<u32, String>, NetworkError> {
+    Ok(get_links(handle)
+        .await?
+        .into_iter()
+        .filter(|link| {
+            let mac = link.mac.as_deref().and_then(|mac| mac.try_into().ok());
+            config.interface_key_for(&link.name, mac).is_some()
+        })
+        .map(|link| (link.index, link.name))
+        .collect())
+}
+
+fn address_event_index(message: NetlinkMessage<RouteNetlinkMessage>) -> Option<u32> {
+    match message.payload {
+        NetlinkPayload::InnerMessage(
+            RouteNetlinkMessage::NewAddress(address) | RouteNetlinkMessage::DelAddress(address),
+        ) => Some(address.header.index),
+        _ => None,
+    }
+}
@@ -1,6 +1,6 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
-use crate::config::{Config, ConfigError};
+use crate::config::{Config, ConfigError, ConfigFormat};
 use colored::Colorize;
 use inquire::{InquireError, prompt_confirmation};
 use miette::{Diagnostic, IntoDiagnostic, Result};
@@ -25,12 +25,8 @@ pub async fn edit(custom_path: Option<&Path>) -> Result<()> {
 }
 
 fn edit_inner(custom_path: Option<&Path>) -> Result<(), EditError> {
-    let mut config = {
-        let load = match custom_path {
-            Some(custom) => Config::load(custom),
-            None => Config::load_default()
-        };
-        match load {
+    let config = {
+        match Config::load_main(custom_path) {
             Err(ConfigError::NotFound { path: _ } | ConfigError::Missing { path: _ }) => {
                 return Err(EditError::ConfigNotFound(custom_path.map(|p| p.to_path_buf())))
             }
@@ -38,37 +34,46 @@ fn edit_inner(custom_path: Option<&Path>) -> Result<(), EditError> {
         }
     }?;
 
-    let mut yaml_text = serde_yaml::to_string(&config).map_err(|source| ConfigError::Yaml{ source })?;
-    loop {
+    // Edit in the format the config is saved in
+    let format = config.format();
+    let extension = format!(".{}", format.extension());
+    let mut text = format.format(&config)?;
+    let edited = loop {
         let new_text = inquire::Editor::new("")
-            .with_file_extension(".yaml")
-            .with_predefined_text(&yaml_text)
+            .with_file_extension(&extension)
+            .with_predefined_text(&text)
             .prompt()?;
 
-        let new_config: Config = match serde_yaml::from_str(&new_text) {
-            Ok(c) => c,
+        match parse_edit(config.path(), &new_text) {
+            Ok(c) => break c,
             Err(e) => {
                 let should_retry = invalid_edit(&e)?;
                 if should_retry {
-                    yaml_text = new_text;
+                    text = new_text;
                     continue;
                 } else {
                     return Err(EditError::Aborted)
                 }
             }
-        };
-        config.cloudflare = new_config.cloudflare;
-        config.interfaces = new_config.interfaces;
-        config.save()?;
-        break;
-    }
+        }
+    };
+    edited.save()?;
     println!(
         "Sucessfully saved edited config to {}.",
-        &config.path().display()
+        &edited.path().display()
     );
     Ok(())
 }
 
+/// Parse the edited text of the config at `path`, replacing the whole config. It is checked as it
+/// will be loaded, but saved as written.
+fn parse_edit(path: &Path, text: &str) -> Result<Config, String> {
+    ConfigFormat::of(path).parse::<Config>(text)
+        .map_err(|e| std::error::Error::source(&e).map_or_else(|| e.to_string(), |source| source.to_string()))
+        .and_then(|c| c.clone().resolve().map(|_| c).map_err(|e| e.to_string()))
+        .map(|c| c.with_path(path))
+}
+
 async fn config_not_found(existing_path: Option<&Path>) -> Result<()> {
     let should_setup = prompt_confirmation(
         "Could not find an existing config path. Would you like to use cfdns setup?",
@@ -82,7 +87,7 @@ async fn config_not_found(existing_path: Option<&Path>) -> Result<()> {
     }
 }
 
-fn invalid_edit(e: &serde_yaml::Error) -> Result<bool, EditError> {
+fn invalid_edit(e: &str) -> Result<bool, EditError> {
     println!("{}", "Edited configuration is invalid!".red());
     println!("{}", e);
     let should_retry = prompt_confirmation("Would you like to retry the edit?")?;
@@ -116,4 +121,48 @@ impl From<InquireError> for EditError {
             others => EditError::Prompt(others)
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "/etc/cfdns/config.yaml";
+
+    /// Parse an edit of a config with one interface record, followed by `extra`
+    fn edit(extra: &str) -> Config {
+        let text = format!(
+            "cloudflare:\n  token: secret\ninterfaces:\n  eth0:\n    records:\n      - domain: home.example.com\n        type: A\n{extra}"
+        );
+        parse_edit(Path::new(PATH), &text).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_config_path() {
+        assert_eq!(edit("").path(), Path::new(PATH));
+    }
+
+    #[test]
+    fn keeps_lookup_providers() {
+        let config = edit("lookup_providers:\n  - url: https://ifconfig.co/ip\n    format: plain\n");
+        assert_eq!(config.lookup_providers.len(), 1);
+        assert_eq!(config.lookup_providers[0].url.as_deref(), Some("https://ifconfig.co/ip"));
+    }
+
+    #[test]
+    fn keeps_lookup_consensus() {
+        let providers = "lookup_providers:\n  - dns: cloudflare\n  - dns: opendns\n";
+        assert!(edit(&format!("{providers}lookup_consensus: true\n")).lookup_consensus);
+    }
+
+    #[test]
+    fn keeps_lookup_cache_secs() {
+        assert_eq!(edit("lookup_cache_secs: 600\n").lookup_cache_secs, Some(600));
+    }
+
+    #[test]
+    fn keeps_top_level_records() {
+        let config = edit("records:\n  - domain: public.example.com\n    type: A\n");
+        assert_eq!(config.records.len(), 1);
+        assert_eq!(config.records[0].domain, "public.example.com");
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, Utc};
+use colored::Colorize;
+use miette::{IntoDiagnostic, Result};
+use tracing::instrument;
+
+use crate::config::{display_domain, domain_to_ascii};
+use crate::state::{self, HistoryEntry};
+
+#[instrument(skip_all, name = "history")]
+pub async fn history(domain: Option<&str>, since: Option<DateTime<Utc>>, json: bool) -> Result<()> {
+    let domain = domain.map(domain_to_ascii).transpose()?;
+    let domain = domain.as_deref();
+    let entries: Vec<HistoryEntry> = state::read_history()?
+        .into_iter()
+        .filter(|entry| domain.is_none_or(|domain| entry.domain.eq_ignore_ascii_case(domain.trim_end_matches('.'))))
+        .filter(|entry| since.is_none_or(|since| entry.at >= since))
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries).into_diagnostic()?);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("No address changes recorded.");
+    }
+    for entry in entries {
+        let old = entry.old.map_or("created".to_string(), |ip| ip.to_string());
+        let source = if entry.web_lookup { " (web lookup)".dimmed().to_string() } else { String::new() };
+        println!(
+            "{}  {}  {} => {}{}",
+            entry.at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+            display_domain(&entry.domain).bold(),
+            old,
+            entry.new.to_string().green(),
+            source,
+        );
+    }
+    Ok(())
+}
+
+/// Parse `--since` as a relative age like `90m`, `12h` or `7d`, a date, or an RFC 3339 timestamp
+pub fn parse_since(input: &str) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+    if let Some(unit) = input.chars().last().filter(char::is_ascii_alphabetic)
+        && let Ok(amount) = input[..input.len() - 1].parse::<i64>()
+    {
+        let age = match unit {
+            's' => TimeDelta::try_seconds(amount),
+            'm' => TimeDelta::try_minutes(amount),
+            'h' => TimeDelta::try_hours(amount),
+            'd' => TimeDelta::try_days(amount),
+            'w' => TimeDelta::try_weeks(amount),
+            _ => None,
+        };
+        return age
+            .map(|age| Utc::now() - age)
+            .ok_or_else(|| format!("invalid age `{input}`, use a number followed by s, m, h, d or w"));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+            .map(|midnight| midnight.with_timezone(&Utc))
+            .ok_or_else(|| format!("invalid date `{input}`"));
+    }
+    DateTime::parse_from_rfc3339(input)
+        .map(|at| at.with_timezone(&Utc))
+        .map_err(|_| format!("invalid time `{input}`, use an age like 7d, a date like 2025-01-31, or an RFC 3339 timestamp"))
+}
@@ -9,4 +9,22 @@ pub use edit::*;
 mod show;
 pub use show::*;
 mod schedule;
-pub use schedule::*;
\ No newline at end of file
+pub use schedule::*;
+mod snapshot;
+pub use snapshot::*;
+mod template;
+pub use template::*;
+mod txt;
+pub use txt::*;
+mod cache;
+pub use cache::*;
+mod status;
+pub use status::*;
+mod history;
+pub use history::*;
+mod prune;
+pub use prune::*;
+#[cfg(target_os = "linux")]
+mod daemon;
+#[cfg(target_os = "linux")]
+pub use daemon::*;
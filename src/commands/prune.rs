@@ -0,0 +1,203 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::BTreeSet;
+use std::path::Path;
+use colored::Colorize;
+use inquire::Confirm;
+use miette::{IntoDiagnostic, Result};
+use regex::Regex;
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    RECORD_CACHE_NAME, ZONE_CACHE_NAME,
+    cache::{Cache, ZoneCache, record_cache_key, record_key},
+    cloudflare::{
+        Clients, DnsApi,
+        dns::{CachedRecord, UpdateError, api_name, list_ip_records, local_owner},
+        zone::{fetch_zone_id, from_api},
+    },
+    config::{Config, INTERFACE_PLACEHOLDER, display_domain},
+    state::{self, PUBLISHED_STATE_NAME, PublishedRecord, ROUND_ROBIN_STATE_NAME, RecordStatus, RoundRobinSet, STATUS_STATE_NAME},
+};
+use super::record_types;
+
+/// A record cfdns created or published whose domain is no longer in the config
+struct Orphan {
+    key: String,
+    zone: String,
+    zone_id: String,
+    record: CachedRecord,
+}
+
+/// Delete the A and AAAA records cfdns created or published on this host for domains that have since
+/// been removed from the config. Records with a comment other than cfdns' marker, or marked by
+/// another host, are left alone.
+#[instrument(skip_all, name = "prune")]
+pub async fn prune(custom_config: Option<&Path>, yes: bool) -> Result<()> {
+    let config = match custom_config {
+        Some(custom) => Config::load(custom),
+        None => Config::load_default(),
+    }?;
+    let mut statuses: Cache<String, RecordStatus> = state::load(STATUS_STATE_NAME)?;
+    let mut published: Cache<String, PublishedRecord> = state::load(PUBLISHED_STATE_NAME)?;
+    let mut round_robin: Cache<String, RoundRobinSet> = state::load(ROUND_ROBIN_STATE_NAME)?;
+    let mut record_cache: Cache<String, CachedRecord> = Cache::load(RECORD_CACHE_NAME)?;
+    let mut zone_cache: ZoneCache = Cache::load(ZONE_CACHE_NAME)?;
+
+    // Records from before cfdns marked the ones it creates are only known from its state, and only
+    // once it actually published them, not when it never found an address
+    let configured = Configured::new(&config);
+    let remembered: BTreeSet<String> = statuses.map.iter()
+        .filter(|(_, status)| status.ip.is_some())
+        .map(|(key, _)| key)
+        .chain(published.map.keys())
+        .filter(|key| !configured.contains(key))
+        .cloned()
+        .collect();
+
+    let mut zones: BTreeSet<String> = config.interfaces.values()
+        .flat_map(|i| &i.records)
+        .chain(&config.records)
+        .map(|r| r.zone.trim_end_matches('.').to_string())
+        .collect();
+    for key in &remembered {
+        let Some((name, _)) = key.rsplit_once('/') else { continue };
+        match zone_for(name, &config, &zone_cache) {
+            Some(zone) => _ = zones.insert(zone),
+            None => warn!(domain = name, "Could not tell which zone the record is in, skipping it"),
+        }
+    }
+
+    let clients = Clients::new(&config)?;
+    let mut orphans = Vec::new();
+    for zone in zones {
+        let client = clients.for_zone(&zone);
+        let zone_id = match config.zone_id(&zone).or_else(|| zone_cache.zone_id(&zone)) {
+            Some(id) => id.to_string(),
+            None => {
+                debug!(zone, "Zone not in cache, querying");
+                let id = fetch_zone_id(client, &zone).await?;
+                zone_cache.insert_zone(zone.clone(), id.clone());
+                id
+            }
+        };
+        let records = list_ip_records(client, &zone_id)
+            .await
+            .map_err(|e| from_api(zone.clone(), e))?;
+        for record in records {
+            let Some(cached) = CachedRecord::from_record(&record) else { continue };
+            let key = record_cache_key(&cached.name, &cached.state.ip);
+            // Markers from before they named their host are only trusted for records this host published
+            let owned = match record.owner() {
+                Some(owner) => Some(owner) == local_owner(),
+                None => (record.is_managed() || record.comment.is_none()) && remembered.contains(&key),
+            };
+            if owned && !configured.contains(&key) {
+                orphans.push(Orphan { key, zone: zone.clone(), zone_id: zone_id.clone(), record: cached });
+            }
+        }
+    }
+    zone_cache.save()?;
+
+    if orphans.is_empty() {
+        println!("No records to prune.");
+    } else {
+        println!("Records no longer in the config:");
+        for orphan in &orphans {
+            let r#type = if orphan.record.state.ip.is_ipv4() { "A" } else { "AAAA" };
+            println!("  {} {}  {}", display_domain(&orphan.record.name).bold(), r#type, orphan.record.state.ip);
+        }
+        let confirmed = yes || Confirm::new(&format!("Delete {} record(s)?", orphans.len()))
+            .with_default(false)
+            .prompt()
+            .into_diagnostic()?;
+        if !confirmed {
+            println!("Nothing was deleted.");
+            return Ok(());
+        }
+    }
+
+    let mut deleted = BTreeSet::new();
+    let mut failure = None;
+    for orphan in &orphans {
+        match clients.for_zone(&orphan.zone).delete_record(&orphan.zone_id, &orphan.record).await {
+            Ok(()) => _ = deleted.insert(&orphan.key),
+            Err(e) => {
+                failure = Some(UpdateError::cloudflare(&orphan.record.name, e));
+                break;
+            }
+        }
+    }
+    // Records already gone from Cloudflare are forgotten too, but not ones still to be deleted
+    let pending: BTreeSet<&String> = orphans.iter().map(|o| &o.key).filter(|key| !deleted.contains(key)).collect();
+    for key in remembered.iter().filter(|key| !pending.contains(key)).chain(deleted.iter().copied()) {
+        statuses.remove(key);
+        published.remove(key);
+        round_robin.remove(key);
+        record_cache.remove(key);
+    }
+    statuses.save()?;
+    published.save()?;
+    round_robin.save()?;
+    record_cache.save()?;
+
+    if !deleted.is_empty() {
+        println!("{} {} record(s)", "Deleted".yellow(), deleted.len());
+    }
+    match failure {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// The record types of every domain in the config, keyed by [`record_key`]
+struct Configured {
+    keys: BTreeSet<String>,
+    /// Domains with a placeholder, matching the name of any link
+    patterns: Vec<(Regex, &'static str)>,
+}
+
+impl Configured {
+    fn new(config: &Config) -> Self {
+        let mut keys = BTreeSet::new();
+        let mut patterns = Vec::new();
+        for record in config.interfaces.values().flat_map(|i| &i.records).chain(&config.records) {
+            for r#type in record_types(record) {
+                let name = api_name(&record.domain, &record.zone);
+                if name.contains(INTERFACE_PLACEHOLDER) {
+                    let parts: Vec<String> = name.to_ascii_lowercase()
+                        .split(INTERFACE_PLACEHOLDER)
+                        .map(regex::escape)
+                        .collect();
+                    patterns.push((Regex::new(&format!("^{}$", parts.join(".+"))).unwrap(), r#type));
+                } else {
+                    keys.insert(record_key(name, r#type));
+                }
+            }
+        }
+        Self { keys, patterns }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        if self.keys.contains(key) {
+            return true;
+        }
+        let Some((name, r#type)) = key.rsplit_once('/') else { return false };
+        self.patterns.iter().any(|(pattern, t)| *t == r#type && pattern.is_match(name))
+    }
+}
+
+/// The longest configured or cached zone containing `name`, spelled as the config spells it
+fn zone_for(name: &str, config: &Config, zone_cache: &ZoneCache) -> Option<String> {
+    config.interfaces.values()
+        .flat_map(|i| &i.records)
+        .chain(&config.records)
+        .map(|r| r.zone.trim_end_matches('.'))
+        .chain(zone_cache.map.keys().map(String::as_str))
+        .filter(|zone| {
+            let zone = zone.to_ascii_lowercase();
+            name == zone || name.ends_with(&format!(".{zone}"))
+        })
+        .max_by_key(|zone| zone.len())
+        .map(str::to_string)
+}
@@ -77,6 +77,15 @@ pub fn disable_systemd_timer() -> Result<(), ScheduleError> {
     Ok(())
 }
 
+/// When the systemd timer next fires, as systemd formats it, or None if the timer isn't active
+pub fn next_scheduled_run() -> Option<String> {
+    let output = Command::new("systemctl")
+        .args(["--user", "show", "cfdns.timer", "--property=NextElapseUSecRealtime", "--value"])
+        .output()
+        .ok()?;
+    let next = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !next.is_empty() && next != "n/a").then_some(next)
+}
 
 #[derive(Debug, Error, Diagnostic)]
 pub enum ScheduleError {
@@ -1,17 +1,15 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
 use crate::ZONE_CACHE_NAME;
-use crate::cache::Cache;
-use crate::cloudflare::make_client;
+use crate::cache::{Cache, ZoneCache};
+use crate::cloudflare::{ApiClient, ClientError, make_client};
 use crate::cloudflare::zone::{ZoneError, fetch_zone_id, guess_zone_from_domain};
-use crate::config::{Cloudflare, Config, ConfigError, Interface, Record, TypeOptions};
-use crate::networking::{NetworkError, list_interfaces};
-use cloudflare::framework::{self, client::async_api::Client};
+use crate::config::{Cloudflare, Config, ConfigError, Interface, Record, Ttl, TypeOptions, check_wildcard, domain_to_ascii, in_zone};
+use crate::networking::{self, NetworkError, list_interfaces};
 use colored::Colorize;
-use inquire::{Confirm, InquireError, Select, Text};
+use inquire::{Confirm, CustomUserError, InquireError, Select, Text, validator::Validation};
 use miette::{Diagnostic, Result};
 use std::collections::HashMap;
-use std::io;
 use std::path::Path;
 use std::process::exit;
 use thiserror::Error;
@@ -26,7 +24,7 @@ fn prompt_overwrite(config: &Config) -> Result<bool, InquireError> {
     .prompt()
 }
 
-fn prompt_invalid_config(err: serde_yaml::Error) -> Result<bool, InquireError> {
+fn prompt_invalid_config(err: &dyn std::error::Error) -> Result<bool, InquireError> {
     Confirm::new(&format!(
         "Your config could not be parsed because: {}\nWould you like to overwrite it? ",
         err
@@ -38,11 +36,20 @@ fn prompt_cloudflare() -> Result<Cloudflare, InquireError> {
         .with_help_message("Token must have Zone=>DNS:Edit permissions")
         .prompt()?;
 
-    Ok(Cloudflare { token })
+    Ok(Cloudflare { token, ..Default::default() })
 }
 
 fn prompt_record() -> Result<Option<Record>, InquireError> {
-    let domain = Text::new("Enter FQDN (blank to continue):").prompt()?;
+    let domain = Text::new("Enter FQDN (blank to continue):")
+        .with_help_message("@ for the zone apex, or start with *. for a wildcard record, like *.home.example.com")
+        .with_validator(|input: &str| Ok(match input.trim() {
+            "" => Validation::Valid,
+            domain => match domain_to_ascii(domain).and_then(|domain| check_wildcard(&domain)) {
+                Ok(()) => Validation::Valid,
+                Err(e) => Validation::Invalid(e.to_string().into()),
+            },
+        }))
+        .prompt()?;
 
     if domain.trim().is_empty() {
         return Ok(None);
@@ -59,38 +66,93 @@ fn prompt_record() -> Result<Option<Record>, InquireError> {
 
     let zone_guess = guess_zone_from_domain(&domain);
 
+    let zone_validator = zone_validator(domain.clone());
     let zone = match zone_guess {
         Some(guess) => Text::new("Enter zone:")
             .with_initial_value(guess)
+            .with_validator(zone_validator)
             .prompt()?,
-        None => Text::new("Enter zone:").prompt()?,
+        None => Text::new("Enter zone:").with_validator(zone_validator).prompt()?,
     };
 
     let web_lookup = Confirm::new("Use web lookup?")
         .with_default(true)
         .prompt()?;
 
+    // Left unset, existing records keep whatever was chosen in the dashboard
+    let proxied = Select::new("Proxy through Cloudflare?", vec!["Keep existing setting", "Proxied", "DNS only"])
+        .with_help_message("new records are DNS only unless proxied")
+        .prompt()?;
+    let proxied = match proxied {
+        "Keep existing setting" => None,
+        "Proxied" => Some(true),
+        "DNS only" => Some(false),
+        _ => unreachable!(),
+    };
+
+    let ttl = Text::new("TTL in seconds (blank for automatic):")
+        .with_help_message("1 for automatic, or 60-86400")
+        .with_validator(|input: &str| Ok(match parse_ttl(input) {
+            Ok(_) => Validation::Valid,
+            Err(e) => Validation::Invalid(e.into()),
+        }))
+        .prompt()?;
+    // Already checked by the validator
+    let ttl = parse_ttl(&ttl).unwrap_or_default();
+
     Ok(Some(Record {
         domain,
         zone,
-        r#type: record_type,
-        web_lookup,
+        zone_id: None,
+        r#type: Some(record_type),
+        web_lookup: Some(web_lookup),
+        ttl,
+        proxied,
+        ipv6_suffix: None,
+        all_ipv6: false,
+        round_robin: false,
+        delete_stale: false,
+        adopt: false,
+        comment: None,
+        tags: Vec::new(),
     }))
 }
 
+fn parse_ttl(input: &str) -> Result<Option<Ttl>, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    let seconds: u32 = input.parse().map_err(|_| "TTL must be a whole number of seconds".to_string())?;
+    Ttl::try_from(seconds).map(Some).map_err(|e| e.to_string())
+}
+
+/// Reject zones that don't contain `domain`
+fn zone_validator(domain: String) -> impl Fn(&str) -> Result<Validation, CustomUserError> + Clone {
+    move |input| Ok(if let (Ok(domain), Ok(zone)) = (domain_to_ascii(&domain), domain_to_ascii(input))
+        && in_zone(&domain, &zone)
+    {
+        Validation::Valid
+    } else {
+        Validation::Invalid(format!("{domain} is not in this zone").into())
+    })
+}
+
 async fn resolve_zone_with_retry(
-    client: &Client,
+    client: &ApiClient,
     record: &mut Record,
 ) -> Result<String, SetupError> {
     loop {
-        match fetch_zone_id(client, &record.zone).await {
+        match fetch_zone_id(client, &domain_to_ascii(&record.zone)?).await {
             Ok(id) => return Ok(id),
             Err(ZoneError::NotFound(_)) => {
                 let prompt = format!(
                     "The zone `{}` for `{}` does not exist or you do not have permissions. Please enter the correct zone:",
                     &record.zone, &record.domain
                 );
-                let new_zone = Text::new(&prompt).prompt()?;
+                let new_zone = Text::new(&prompt)
+                    .with_validator(zone_validator(record.domain.clone()))
+                    .prompt()?;
 
                 record.zone = new_zone;
             }
@@ -121,8 +183,8 @@ async fn setup_inner(custom_config: Option<&Path>) -> Result<(), SetupError> {
         .map(Some);
         match load {
             Err(ConfigError::NotFound { path: _ } | ConfigError::Missing { path: _ }) => Ok(None),
-            Err(ConfigError::Yaml { source }) => {
-                let overwrite = prompt_invalid_config(source)?;
+            Err(e @ (ConfigError::Yaml { .. } | ConfigError::Toml { .. } | ConfigError::JsonParse { .. })) => {
+                let overwrite = prompt_invalid_config(std::error::Error::source(&e).unwrap_or(&e))?;
                 if !overwrite {
                     return Err(SetupError::Cancelled);
                 }
@@ -143,14 +205,10 @@ async fn setup_inner(custom_config: Option<&Path>) -> Result<(), SetupError> {
     let cloudflare = prompt_cloudflare()?;
 
     // Initialize an API client for later usage
-    let client = make_client(cloudflare.token.clone())?;
-
-    // Obtain netlink handle
-    let (conn, handle, _) = rtnetlink::new_connection()?;
-    tokio::spawn(conn);
+    let client = make_client(&cloudflare)?;
 
     // Prompt to select network interfaces
-    let interfaces = list_interfaces(&handle).await?;
+    let interfaces = list_interfaces(&networking::connect()?).await?;
     let selected = inquire::MultiSelect::new(
         "Select each network interface you want to configure (use spacebar to select):",
         interfaces,
@@ -158,7 +216,7 @@ async fn setup_inner(custom_config: Option<&Path>) -> Result<(), SetupError> {
     .prompt()?;
 
     let mut interfaces_config: HashMap<String, Interface> = HashMap::new();
-    let mut zone_cache: Cache<String, String> = Cache::load(ZONE_CACHE_NAME).unwrap();
+    let mut zone_cache: ZoneCache = Cache::load(ZONE_CACHE_NAME).unwrap();
 
     // Iterate selected interfaces to add records
     for iface in selected {
@@ -173,13 +231,14 @@ async fn setup_inner(custom_config: Option<&Path>) -> Result<(), SetupError> {
         println!("Checking for access to the selected Cloudflare Zones...");
         for record in &mut interface_records {
             let id = resolve_zone_with_retry(&client, record).await?;
-            zone_cache.insert(record.zone.clone(), id);
+            zone_cache.insert_zone(domain_to_ascii(&record.zone)?, id);
         }
 
         interfaces_config.insert(
             iface.to_string(),
             Interface {
                 records: interface_records,
+                ..Default::default()
             },
         );
     }
@@ -215,10 +274,8 @@ pub enum SetupError {
     Zone(#[from] ZoneError),
     #[error(transparent)]
     Network(#[from] NetworkError),
-    #[error(transparent)]
-    Netlink(#[from] io::Error),
     #[error("could not connect to Cloudflare API")]
-    Cloudflare(#[from] framework::Error),
+    Cloudflare(#[from] ClientError),
 }
 impl From<InquireError> for SetupError {
     fn from(value: InquireError) -> Self {
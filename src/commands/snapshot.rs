@@ -0,0 +1,66 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::BTreeSet;
+use std::path::Path;
+use colored::Colorize;
+use miette::{IntoDiagnostic, Result};
+use tracing::{debug, info, instrument};
+
+use crate::{
+    ZONE_CACHE_NAME,
+    cache::{Cache, ZoneCache},
+    cloudflare::{Clients, dns::list_all_records, zone::{fetch_zone_id, from_api}},
+    config::Config,
+    snapshot::{Snapshot, SnapshotZone},
+};
+
+#[instrument(skip_all, name = "snapshot")]
+pub async fn snapshot(custom_config: Option<&Path>, output: Option<&Path>) -> Result<()> {
+    let config = match custom_config {
+        Some(custom) => Config::load(custom),
+        None => Config::load_default(),
+    }?;
+
+    let clients = Clients::new(&config)?;
+    let mut zone_cache: ZoneCache = Cache::load(ZONE_CACHE_NAME)?;
+
+    let zones: BTreeSet<&str> = config.interfaces
+        .values()
+        .flat_map(|iface| &iface.records)
+        .chain(&config.records)
+        .map(|record| record.zone.as_str())
+        .collect();
+
+    let mut snapshot = Snapshot::default();
+    for zone in zones {
+        let client = clients.for_zone(zone);
+        let id = match config.zone_id(zone).or_else(|| zone_cache.zone_id(zone)) {
+            Some(id) => id.to_string(),
+            None => {
+                debug!(zone, "Zone not in cache, querying");
+                let id = fetch_zone_id(client, zone).await?;
+                zone_cache.insert_zone(zone, id.clone());
+                id
+            }
+        };
+        let records = list_all_records(client, &id)
+            .await
+            .map_err(|e| from_api(zone.to_string(), e))?;
+        info!(zone, count = records.len(), "Saved zone records");
+
+        snapshot.zones.insert(zone.to_string(), SnapshotZone {
+            id,
+            records: records.into_iter().map(Into::into).collect(),
+        });
+    }
+    zone_cache.save()?;
+
+    match output {
+        Some(path) => {
+            snapshot.save(path)?;
+            println!("{} {}", "Saved snapshot to".green(), path.display());
+        }
+        None => println!("{}", serde_json::to_string_pretty(&snapshot).into_diagnostic()?),
+    }
+    Ok(())
+}
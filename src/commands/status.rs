@@ -0,0 +1,95 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::net::IpAddr;
+use std::path::Path;
+use colored::Colorize;
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::{
+    cache::{Cache, format_age, record_key},
+    cloudflare::dns::api_name,
+    config::{Config, display_domain},
+    networking,
+    state::{self, RecordStatus, RunResult, STATUS_STATE_NAME},
+};
+use super::next_scheduled_run;
+
+#[derive(Serialize)]
+struct Status<'a> {
+    records: Vec<RecordReport<'a>>,
+    /// When the systemd timer next runs `cfdns update`, if it is enabled
+    next_run: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RecordReport<'a> {
+    domain: &'a str,
+    interface: &'a str,
+    r#type: &'static str,
+    /// None if the record hasn't been updated yet
+    #[serde(flatten)]
+    status: Option<&'a RecordStatus>,
+}
+
+#[instrument(skip_all, name = "status")]
+pub async fn status(custom_config: Option<&Path>, json: bool) -> Result<()> {
+    let config = match custom_config {
+        Some(custom) => Config::load(custom),
+        None => Config::load_default(),
+    }?;
+    let statuses: Cache<String, RecordStatus> = state::load(STATUS_STATE_NAME)?;
+    let expanded = networking::expand_interfaces(&networking::connect()?, &config).await?;
+
+    let mut records = Vec::new();
+    let mut interfaces: Vec<_> = expanded.iter().collect();
+    interfaces.sort_by_key(|(name, _)| *name);
+    for (interface, iface) in interfaces {
+        for record in &iface.records {
+            let types = [
+                record.record_type().includes_ipv4().then_some("A"),
+                record.record_type().includes_ipv6().then_some("AAAA"),
+            ];
+            for r#type in types.into_iter().flatten() {
+                let key = record_key(api_name(&record.domain, &record.zone), r#type);
+                records.push(RecordReport { domain: &record.domain, interface, r#type, status: statuses.get(&key) });
+            }
+        }
+    }
+    let status = Status { records, next_run: next_scheduled_run() };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status).into_diagnostic()?);
+    } else {
+        print(&status);
+    }
+    Ok(())
+}
+
+fn print(status: &Status) {
+    for report in &status.records {
+        let heading = format!("{} {} ({})", display_domain(report.domain).bold(), report.r#type, report.interface);
+        let Some(record) = report.status else {
+            println!("{heading}  {}", "never updated".dimmed());
+            continue;
+        };
+        let result = match record.result {
+            RunResult::Updated => "updated".green(),
+            RunResult::Unchanged => "unchanged".yellow(),
+            RunResult::Skipped => "no address".red(),
+            RunResult::Error => "error".red().bold(),
+        };
+        let ip = record.ip.as_ref().map_or("-".to_string(), IpAddr::to_string);
+        println!("{heading}  {result}  {ip}");
+        println!("    last run {}, last changed {}", format_age(record.at), record.updated_at.map_or("never".to_string(), format_age));
+        if let Some(error) = &record.error {
+            println!("    {}", error.red());
+        }
+    }
+
+    match &status.next_run {
+        Some(next) => println!("Next scheduled run: {next}"),
+        None => println!("No scheduled runs. Use `cfdns schedule` to update every 5 minutes."),
+    }
+}
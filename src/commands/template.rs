@@ -0,0 +1,11 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use tracing::instrument;
+use miette::Result;
+use crate::config::Config;
+
+#[instrument(skip_all, name = "template")]
+pub async fn template() -> Result<()> {
+    print!("{}", Config::template()?);
+    Ok(())
+}
@@ -0,0 +1,90 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::path::Path;
+use colored::Colorize;
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+use tracing::{debug, instrument};
+
+use crate::{
+    ZONE_CACHE_NAME,
+    cache::{Cache, ZoneCache},
+    cloudflare::{
+        dns::{delete_dns_record, fetch_txt_records, try_update_txt_record, txt_value},
+        Clients,
+        zone::{fetch_zone_id, from_api, guess_zone_from_domain},
+    },
+    config::{Config, display_domain, domain_to_ascii},
+};
+
+/// Add or remove a TXT record outside of the config, e.g. as an ACME DNS-01 hook
+#[instrument(skip_all, name = "update-txt")]
+pub async fn update_txt(
+    custom_config: Option<&Path>,
+    domain: &str,
+    zone: Option<&str>,
+    value: &str,
+    delete: bool,
+) -> Result<()> {
+    let config = match custom_config {
+        Some(custom) => Config::load(custom),
+        None => Config::load_default(),
+    }?;
+
+    let domain = &domain_to_ascii(domain)?;
+    let zone = match zone {
+        Some(zone) => domain_to_ascii(zone)?,
+        None => {
+            // Service labels such as `_acme-challenge` aren't valid hostnames, so guess from the rest
+            let host: Vec<&str> = domain.split('.').skip_while(|label| label.starts_with('_')).collect();
+            guess_zone_from_domain(&host.join("."))
+                .map(str::to_string)
+                .ok_or_else(|| TxtError::UnknownZone(domain.to_string()))?
+        }
+    };
+    let zone = zone.as_str();
+
+    let clients = Clients::new(&config)?;
+    let client = clients.for_zone(zone);
+    let mut zone_cache: ZoneCache = Cache::load(ZONE_CACHE_NAME)?;
+    let zone_id = match zone_cache.zone_id(zone) {
+        Some(id) => id.to_string(),
+        None => {
+            debug!(zone, "Zone not in cache, querying");
+            let id = fetch_zone_id(client, zone).await?;
+            zone_cache.insert_zone(zone, id.clone());
+            id
+        }
+    };
+    zone_cache.save()?;
+
+    let existing = fetch_txt_records(client, &zone_id, domain)
+        .await
+        .map_err(|e| from_api(zone.to_string(), e))?;
+
+    if delete {
+        // Only the given value, so other TXT records at the name such as SPF survive
+        let matching: Vec<_> = existing
+            .iter()
+            .filter(|record| txt_value(record) == Some(value))
+            .collect();
+        for record in &matching {
+            delete_dns_record(client, &zone_id, record).await?;
+        }
+        println!("{} {} TXT record(s) for {}", "Deleted".yellow(), matching.len(), display_domain(domain).bold());
+        return Ok(());
+    }
+
+    match try_update_txt_record(client, &zone_id, domain, existing, value).await? {
+        Some(_) => println!("{} TXT record for {}", "Added".green(), display_domain(domain).bold()),
+        None => println!("TXT record for {} is already up to date", display_domain(domain).bold()),
+    }
+    Ok(())
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum TxtError {
+    #[error("could not determine the zone for `{0}`")]
+    #[diagnostic(help("pass the zone explicitly with --zone"))]
+    UnknownZone(String),
+}
@@ -1,167 +1,947 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
-use cloudflare::{
-    endpoints::dns::dns::DnsRecord,
-    framework::client::async_api::Client,
-};
 use colored::Colorize;
-use futures::stream::{StreamExt, TryStreamExt};
+use futures::stream::StreamExt;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use miette::{IntoDiagnostic, Result};
-use rtnetlink::Handle;
+use miette::{Diagnostic, IntoDiagnostic, Report, Result};
 use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    path::Path,
-    sync::RwLock, time::Duration,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
+use chrono::Utc;
+use cloudflare::framework::response::ApiFailure;
+use thiserror::Error;
 use tokio::sync::OnceCell;
 use tracing::{debug, info, instrument, warn};
 
 use crate::{
-    CONSOLE_PRINT, ZONE_CACHE_NAME, cache::{AsyncZoneCache, Cache}, cloudflare::{
-        dns::{UpdateError, fetch_ip_records, try_update_record, try_update_record_dry_run},
-        make_client,
-        zone::{ZoneError, fetch_zone_id},
-    }, config::{Config, Interface, Record, TypeOptions}, networking::{NetworkError, best_addresses_by_interface}, weblookup::{LookupError, get_public_ipv4, get_public_ipv6}
+    CONSOLE_PRINT, RECORD_CACHE_NAME, exit::Status, ZONE_CACHE_NAME, cache::{AsyncRecordCache, AsyncZoneCache, Cache, record_cache_key, record_key}, cloudflare::{
+        dns::{CachedRecord, CommentedRecord, RecordSetChanges, RecordSettings, UpdateError, api_name, reconcile_record_set, reconcile_record_set_dry_run, try_update_cached_record, try_update_record, try_update_record_dry_run},
+        ApiClient, Clients, DnsApi,
+        zone::ZoneError,
+    }, config::{Config, Interface, LookupProvider, Record, Ttl, TypeOptions, WEB_LOOKUP_INTERFACE, display_domain, domain_to_ascii}, events::SyslogNotifier, snapshot::Snapshot, state::{self, AsyncLookupState, AsyncPublishedState, AsyncRecordStatus, AsyncRoundRobinState, LOOKUP_STATE_NAME, ROUND_ROBIN_STATE_NAME, LookedUpAddress, PUBLISHED_STATE_NAME, HistoryEntry, PublishedRecord, RecordReport, RecordStatus, RunReport, RunResult, STATUS_STATE_NAME}, networking::{self, AddressSource, Backend, BestAddresses, DEFAULT_INTERFACE, SelectedAddresses, best_addresses_by_interface, best_addresses_by_interfaces, list_interfaces}, weblookup::{LookupError, get_public_ipv4, get_public_ipv6}
 };
 
+/// How `cfdns update` prints its results
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Progress spinners and a line per record
+    #[default]
+    Human,
+    /// A JSON array with the outcome of each record type, printed once the run finishes
+    Json,
+}
+
+/// Every record that failed during an update. Records are processed independently, so one
+/// failure doesn't stop the others.
+#[derive(Error, Debug)]
+#[error("{} of the records failed to update", .failures.len())]
+pub struct RecordsFailed {
+    failures: Vec<Report>,
+}
+
+impl RecordsFailed {
+    /// Ok if nothing failed
+    fn check(failures: Vec<Report>) -> Result<()> {
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(RecordsFailed { failures }.into())
+        }
+    }
+
+    /// Split a failure back into the failures of individual records
+    fn flatten(report: Report) -> Vec<Report> {
+        match report.downcast::<RecordsFailed>() {
+            Ok(failed) => failed.failures,
+            Err(report) => vec![report],
+        }
+    }
+
+    pub fn failures(&self) -> &[Report] {
+        &self.failures
+    }
+}
+
+impl Diagnostic for RecordsFailed {
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(self.failures.iter().map(|report| report.as_ref() as &dyn Diagnostic)))
+    }
+}
+
+/// A run that was stopped because it took longer than `--timeout`
+#[derive(Error, Diagnostic, Debug)]
+#[error("the update did not finish within {secs} seconds")]
+#[diagnostic(help("records that were not processed: {unprocessed}"))]
+pub struct RunTimedOut {
+    secs: u64,
+    unprocessed: String,
+}
+
+/// A run that was stopped by SIGINT or SIGTERM
+#[derive(Error, Diagnostic, Debug)]
+#[error("the update was cancelled by {signal}")]
+#[diagnostic(help("records that were not processed: {unprocessed}"))]
+pub struct RunCancelled {
+    signal: &'static str,
+    unprocessed: String,
+}
+
+/// Resolves with the name of the signal once the process is asked to stop
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => terminate.recv().await,
+            Err(e) => {
+                warn!(error = %e, "Could not listen for SIGTERM");
+                std::future::pending().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<Option<()>>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = terminate => "SIGTERM",
+    }
+}
+
+/// Restricts an update to some of the configured records
+#[derive(Debug, Default)]
+pub struct RecordFilter {
+    /// Domain names or glob patterns such as `*.example.com`. Empty matches every domain.
+    pub domains: Vec<String>,
+    /// Names of the interfaces to update. Empty matches every interface.
+    pub interfaces: Vec<String>,
+    /// Only update A records, including the A half of `Both` records
+    pub ipv4_only: bool,
+    /// Only update AAAA records, including the AAAA half of `Both` records
+    pub ipv6_only: bool,
+    /// Only update records carrying one of these tags. Empty matches every record.
+    pub tags: Vec<String>,
+}
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum FilterError {
+    #[error("no configured record matches {0}")]
+    #[diagnostic(help("check the domains and record types in your config"))]
+    NoMatch(String),
+    #[error("interface `{0}` is not configured")]
+    #[diagnostic(help("the configured interfaces are: {1}"))]
+    UnknownInterface(String, String),
+}
+
+impl RecordFilter {
+    /// Remove the records this filter excludes, and any interfaces left without records
+    pub fn apply(&self, config: &mut Config) -> Result<(), FilterError> {
+        if !self.interfaces.is_empty() {
+            if let Some(unknown) = self.interfaces.iter().find(|name| !config.interfaces.contains_key(*name)) {
+                let mut configured: Vec<_> = config.interfaces.keys().map(String::as_str).collect();
+                configured.sort();
+                return Err(FilterError::UnknownInterface(unknown.clone(), configured.join(", ")));
+            }
+            config.interfaces.retain(|name, _| self.interfaces.contains(name));
+        }
+
+        if self.domains.is_empty() && self.tags.is_empty() && !self.ipv4_only && !self.ipv6_only {
+            return Ok(());
+        }
+        for interface in config.interfaces.values_mut() {
+            interface.records.retain_mut(|record| {
+                self.matches_domain(api_name(&record.domain, &record.zone)) && self.matches_tags(&record.tags) && record.r#type.as_mut().is_some_and(|t| self.narrow_type(t))
+            });
+        }
+        config.interfaces.retain(|_, interface| !interface.records.is_empty());
+        if config.interfaces.is_empty() {
+            return Err(FilterError::NoMatch(self.describe()));
+        }
+        Ok(())
+    }
+
+    /// Drop the excluded address family from a record type, returning false if nothing is left
+    fn narrow_type(&self, r#type: &mut TypeOptions) -> bool {
+        if self.ipv4_only {
+            if !r#type.includes_ipv4() {
+                return false;
+            }
+            *r#type = TypeOptions::A;
+        } else if self.ipv6_only {
+            if !r#type.includes_ipv6() {
+                return false;
+            }
+            *r#type = TypeOptions::AAAA;
+        }
+        true
+    }
+
+    /// The domain and address family restrictions, for error messages
+    fn describe(&self) -> String {
+        let mut parts = self.domains.clone();
+        parts.extend(self.tags.iter().map(|tag| format!("--tag {tag}")));
+        if self.ipv4_only {
+            parts.push("--ipv4-only".to_string());
+        }
+        if self.ipv6_only {
+            parts.push("--ipv6-only".to_string());
+        }
+        parts.join(", ")
+    }
+
+    fn matches_tags(&self, tags: &[String]) -> bool {
+        self.tags.is_empty() || tags.iter().any(|tag| self.tags.contains(tag))
+    }
+
+    fn matches_domain(&self, domain: &str) -> bool {
+        if self.domains.is_empty() {
+            return true;
+        }
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        self.domains.iter().any(|pattern| {
+            // Unicode names on the command line match the punycode form in the config
+            let pattern = domain_to_ascii(pattern).unwrap_or_else(|_| pattern.clone());
+            glob_match(pattern.trim_end_matches('.').to_ascii_lowercase().as_bytes(), domain.as_bytes())
+        })
+    }
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters and `?` any one
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.split_first(), text.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => glob_match(rest, text) || (!text.is_empty() && glob_match(pattern, &text[1..])),
+        (Some((b'?', rest)), Some((_, text))) => glob_match(rest, text),
+        (Some((p, rest)), Some((t, text))) => p == t && glob_match(rest, text),
+        _ => false,
+    }
+}
+
+/// Flags of `cfdns update`
+#[derive(Debug, Default)]
+pub struct UpdateOptions {
+    pub dry_run: bool,
+    /// Compare against records saved by `cfdns snapshot` during a dry run
+    pub from_snapshot: Option<PathBuf>,
+    /// Dry run that only reports whether records are up to date
+    pub check: bool,
+    /// Send records to Cloudflare even if they are up to date
+    pub force: bool,
+    /// Overrides `parallel` from the config
+    pub parallel: Option<usize>,
+    /// Stop processing records once the run has taken this long
+    pub timeout: Option<Duration>,
+    /// How long to wait for another running cfdns before giving up
+    pub wait_lock: Duration,
+    pub output: OutputFormat,
+    pub filter: RecordFilter,
+}
+
+/// Returns [`Status::Changed`] if a record was updated, or [`Status::Success`] if none needed to be.
+/// With `check`, nothing is changed and [`Status::OutOfDate`] is returned if a record would be.
 #[instrument(skip_all, name = "update")]
-pub async fn update(custom_config: Option<&Path>, dry_run: bool) -> Result<()> {
-    let (conn, handle, _) = rtnetlink::new_connection().into_diagnostic()?;
-    tokio::spawn(conn);
-    let ui = Ui::new();
+pub async fn update(custom_config: Option<&Path>, options: UpdateOptions) -> Result<Status> {
+    let UpdateOptions { from_snapshot, check, force, parallel, timeout, wait_lock, output, filter, .. } = options;
+    let dry_run = options.dry_run || check;
+    // Dry runs don't change records or state, so they can run alongside a real update
+    let _lock = if dry_run { None } else { Some(state::lock(wait_lock).await?) };
+    let backend = networking::connect()?;
 
     // Load config
-    let config = match custom_config {
+    let mut config = match custom_config {
         Some(custom) => Config::load(custom),
         None => Config::load_default(),
     }?;
+    config.interfaces = networking::expand_interfaces(&backend, &config).await?;
+    filter.apply(&mut config)?;
+    if parallel.is_some() {
+        config.parallel = parallel;
+        config.validate()?;
+    }
 
-    let client = make_client(config.cloudflare.token.clone()).into_diagnostic()?;
-    let zone_cache: AsyncZoneCache = Cache::load(ZONE_CACHE_NAME)?.into_threadsafe();
+    let snapshot = from_snapshot.as_deref().map(Snapshot::load).transpose()?;
+    let updater = Updater::new(&config, backend, snapshot)?
+        .with_force(force)
+        .with_deadline(timeout)
+        .with_cancel_on_signal();
+    // Save even if a record failed, so its status and anything learned before it are kept
+    let result = updater.run(&config.interfaces, dry_run).await;
+    updater.save()?;
+    let records = updater.records();
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&records).into_diagnostic()?);
+    }
 
-    for (iface_name, Interface { records }) in config.interfaces {
-        info!(interface=iface_name, "Discovering addresses on");
-        ui.start(&iface_name);
+    if check && result.is_ok() {
+        return Ok(print_check(&records));
+    }
 
-        let processor = RecordProcessor::new(&client, &handle, &zone_cache, &iface_name, &ui).await?;
+    if output == OutputFormat::Human && !check {
+        print_failures(&records);
+    }
+    match result {
+        Err(e) => Err(e),
+        Ok(()) if !dry_run && records.iter().any(|r| r.result == RunResult::Updated) => Ok(Status::Changed),
+        Ok(()) => Ok(Status::Success),
+    }
+}
 
-        if dry_run {
-            processor.batch_process_dry_run(records, 8).await?;
+/// List each failed record with its error, so failures aren't lost among the progress output
+fn print_failures(records: &[RecordReport]) {
+    let mut failed: Vec<(&str, Vec<&str>, &str)> = Vec::new();
+    for record in records.iter().filter(|r| r.result == RunResult::Error) {
+        let error = record.error.as_deref().unwrap_or_default();
+        match failed.iter_mut().find(|(domain, _, _)| *domain == record.domain) {
+            Some((_, types, _)) => types.push(&record.r#type),
+            None => failed.push((&record.domain, vec![&record.r#type], error)),
+        }
+    }
+    if failed.is_empty() {
+        return;
+    }
+    failed.sort_by_key(|(domain, _, _)| *domain);
+
+    let failed: Vec<_> = failed.into_iter().map(|(domain, types, error)| (display_domain(domain), types, error)).collect();
+    let width = failed.iter().map(|(domain, _, _)| domain.chars().count()).max().unwrap_or(0);
+    println!();
+    println!("{}", "Failed records:".red().bold());
+    for (domain, types, error) in failed {
+        println!("  {:width$}  {:7}  {}", domain.bold(), types.join(", "), error);
+    }
+}
+
+/// An error and its causes on one line, for summaries and saved state
+fn describe_error(report: &Report) -> String {
+    let causes: Vec<String> = report.chain()
+        .map(|err| match err.downcast_ref::<ApiFailure>() {
+            Some(ApiFailure::Error(status, errors)) if !errors.errors.is_empty() => {
+                let messages: Vec<String> = errors.errors.iter().map(|e| format!("{} ({})", e.message, e.code)).collect();
+                format!("HTTP {}: {}", status, messages.join(", "))
+            }
+            _ => err.to_string().lines().next().unwrap_or_default().to_string(),
+        })
+        .collect();
+    causes.join(": ")
+}
+
+/// Print a single line summarising a check, in the style of monitoring plugins
+fn print_check(records: &[RecordReport]) -> Status {
+    let stale: Vec<String> = records.iter()
+        .filter(|r| r.result == RunResult::Updated)
+        .map(|r| match (r.old, r.new) {
+            (Some(old), Some(new)) => format!("{}/{} {} => {}", r.domain, r.r#type, old, new),
+            (None, Some(new)) => format!("{}/{} missing => {}", r.domain, r.r#type, new),
+            _ => format!("{}/{}", r.domain, r.r#type),
+        })
+        .collect();
+    if stale.is_empty() {
+        println!("OK: all records up to date ({} checked)", records.len());
+        Status::Success
+    } else {
+        println!("OUT OF DATE: {}", stale.join(", "));
+        Status::OutOfDate
+    }
+}
+
+/// State shared between update runs, so a long-lived caller such as the daemon keeps its client
+/// and caches warm between events
+pub struct Updater {
+    clients: Clients,
+    backend: Backend,
+    zone_cache: AsyncZoneCache,
+    record_cache: AsyncRecordCache,
+    published: AsyncPublishedState,
+    status: AsyncRecordStatus,
+    lookups: AsyncLookupState,
+    round_robin: AsyncRoundRobinState,
+    /// Outcomes of the current or last run
+    report: Mutex<Vec<RecordReport>>,
+    skip_unchanged_secs: Option<u64>,
+    parallel: usize,
+    force: bool,
+    deadline: Option<Duration>,
+    cancel_on_signal: bool,
+    ui: Ui,
+    syslog: Option<SyslogNotifier>,
+    snapshot: Option<Snapshot>,
+    lookup_providers: Vec<LookupProvider>,
+    lookup_consensus: bool,
+    lookup_cache_secs: Option<u64>,
+}
+
+impl Updater {
+    pub fn new(config: &Config, backend: Backend, snapshot: Option<Snapshot>) -> Result<Self> {
+        let clients = Clients::new(config)?;
+        let zone_cache: AsyncZoneCache = Cache::load(ZONE_CACHE_NAME)?.into_threadsafe();
+        let record_cache: AsyncRecordCache = Cache::load(RECORD_CACHE_NAME)?.into_threadsafe();
+        let published: AsyncPublishedState = state::load(PUBLISHED_STATE_NAME)?.into_threadsafe();
+        let status: AsyncRecordStatus = state::load(STATUS_STATE_NAME)?.into_threadsafe();
+        let lookups: AsyncLookupState = state::load(LOOKUP_STATE_NAME)?.into_threadsafe();
+        let round_robin: AsyncRoundRobinState = state::load(ROUND_ROBIN_STATE_NAME)?.into_threadsafe();
+        let syslog = if config.log_syslog {
+            match SyslogNotifier::connect(config.syslog.unwrap_or_default()) {
+                Ok(notifier) => Some(notifier),
+                Err(e) => {
+                    warn!(error=%e, "Could not connect to syslog, change events will not be sent");
+                    None
+                }
+            }
         } else {
-            processor.batch_process(records, 8).await?;
+            None
+        };
+
+        Ok(Self {
+            clients,
+            backend,
+            zone_cache,
+            record_cache,
+            published,
+            status,
+            lookups,
+            round_robin,
+            report: Mutex::new(Vec::new()),
+            skip_unchanged_secs: config.skip_unchanged_secs,
+            parallel: config.parallel(),
+            force: false,
+            deadline: None,
+            cancel_on_signal: false,
+            ui: Ui::new(), syslog, snapshot,
+            lookup_providers: config.lookup_providers.clone(),
+            lookup_consensus: config.lookup_consensus,
+            lookup_cache_secs: config.lookup_cache_secs,
+        })
+    }
+
+    /// Process every record of the given interfaces against their current addresses, then write
+    /// the run report unless this is a dry run
+    pub async fn run<'i>(
+        &self,
+        interfaces: impl IntoIterator<Item = (&'i String, &'i Interface)>,
+        dry_run: bool,
+    ) -> Result<()> {
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let api_calls = self.clients.api_calls();
+        self.report.lock().unwrap().clear();
+
+        let interfaces: Vec<_> = interfaces.into_iter().collect();
+        let processing = self.process_interfaces(interfaces.iter().copied(), dry_run);
+        let processing = async {
+            match self.deadline {
+                // Dropping the unfinished records cancels their requests
+                Some(deadline) => match tokio::time::timeout(deadline, processing).await {
+                    Ok(result) => result,
+                    Err(_) => Err(RunTimedOut { secs: deadline.as_secs(), unprocessed: self.unprocessed(&interfaces) }.into()),
+                },
+                None => processing.await,
+            }
+        };
+        let result = tokio::select! {
+            result = processing => result,
+            signal = shutdown_signal(), if self.cancel_on_signal => {
+                warn!(signal, "Stopping the update");
+                Err(RunCancelled { signal, unprocessed: self.unprocessed(&interfaces) }.into())
+            }
+        };
+        if !dry_run {
+            let report = RunReport {
+                started_at,
+                duration_ms: started.elapsed().as_millis() as u64,
+                api_calls: self.clients.api_calls() - api_calls,
+                records: self.records(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            };
+            if let Err(e) = state::write_report(&report) {
+                warn!(error = %e, "Could not write the run report");
+            }
+        }
+        result
+    }
+
+    /// Send every record to Cloudflare, even ones that are already up to date
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Give up on records still being processed once a run has taken this long
+    pub fn with_deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Stop processing records on SIGINT or SIGTERM, so caches and state can still be saved
+    pub fn with_cancel_on_signal(mut self) -> Self {
+        self.cancel_on_signal = true;
+        self
+    }
+
+    /// The records of a run that haven't finished, as `domain/type`
+    fn unprocessed(&self, interfaces: &[(&String, &Interface)]) -> String {
+        let finished = self.records();
+        let unprocessed: Vec<String> = interfaces.iter()
+            .flat_map(|(_, interface)| &interface.records)
+            .flat_map(|record| record_types(record).map(move |t| (record, t)))
+            .filter(|(record, t)| !finished.iter().any(|r| r.domain == record.domain && r.r#type == *t))
+            .map(|(record, t)| format!("{}/{}", record.domain, t))
+            .collect();
+        unprocessed.join(", ")
+    }
+
+    /// The outcome of each record type in the last run
+    pub fn records(&self) -> Vec<RecordReport> {
+        self.report.lock().unwrap().clone()
+    }
+
+    async fn process_interfaces<'i>(
+        &self,
+        interfaces: impl IntoIterator<Item = (&'i String, &'i Interface)>,
+        dry_run: bool,
+    ) -> Result<()> {
+        let interfaces: Vec<_> = interfaces.into_iter().collect();
+
+        info!(interfaces=interfaces.len(), "Discovering addresses");
+        let mut addresses = best_addresses_by_interfaces(
+            &self.backend,
+            interfaces.iter()
+                .filter(|(name, _)| name.as_str() != WEB_LOOKUP_INTERFACE)
+                .map(|(name, iface)| (name.as_str(), *iface)),
+        ).await?;
+        if !dry_run {
+            self.prune_round_robin().await?;
+        }
+
+        let mut failures = Vec::new();
+        for (iface_name, interface) in interfaces {
+            self.ui.start(iface_name);
+
+            // Binding web lookups to the unspecified address lets the default route pick the source
+            let selected = if iface_name == WEB_LOOKUP_INTERFACE {
+                SelectedAddresses { best: (Some(Ipv4Addr::UNSPECIFIED), Some(Ipv6Addr::UNSPECIFIED)), global_ipv6: Vec::new() }
+            } else {
+                addresses.remove(iface_name.as_str()).unwrap_or_default()
+            };
+            let processor = RecordProcessor::new(&self.clients, &self.backend, &self.zone_cache, iface_name, interface, selected.best, &self.ui)
+                .with_global_ipv6(selected.global_ipv6)
+                .with_round_robin(&self.round_robin)
+                .with_record_cache(&self.record_cache)
+                .with_published_state(&self.published, self.skip_unchanged_secs)
+                .with_force(self.force)
+                .with_status(&self.status)
+                .with_report(&self.report)
+                .with_syslog(self.syslog.as_ref())
+                .with_snapshot(self.snapshot.as_ref())
+                .with_lookup_providers(&self.lookup_providers, self.lookup_consensus)
+                .with_lookup_cache(&self.lookups, self.lookup_cache_secs);
+
+            let result = if dry_run {
+                processor.batch_process_dry_run(&interface.records, self.parallel).await
+            } else {
+                processor.batch_process(&interface.records, self.parallel).await
+            };
+            if let Err(e) = result {
+                failures.extend(RecordsFailed::flatten(e));
+            }
         }
+        RecordsFailed::check(failures)
     }
 
-    zone_cache.write().unwrap().save()?;
-    Ok(())
+    /// Drop round-robin members whose link no longer exists, so their addresses leave the set the
+    /// next time the record is published
+    async fn prune_round_robin(&self) -> Result<()> {
+        let links = list_interfaces(&self.backend).await?;
+        for set in self.round_robin.write().unwrap().values_mut() {
+            set.members.retain(|iface, _| {
+                links.contains(iface) || iface == DEFAULT_INTERFACE || iface == WEB_LOOKUP_INTERFACE
+            });
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.zone_cache.write().unwrap().save()?;
+        self.record_cache.write().unwrap().save()?;
+        self.published.write().unwrap().save()?;
+        self.status.write().unwrap().save()?;
+        self.lookups.write().unwrap().save()?;
+        self.round_robin.write().unwrap().save()?;
+        Ok(())
+    }
 }
 
-pub struct RecordProcessor<'a> {
-    client: &'a Client,
-    zone_cache: &'a RwLock<Cache<String, String>>,
+/// The existing A and AAAA records for a domain
+type ExistingRecords = OnceCell<(Option<CachedRecord>, Option<CachedRecord>)>;
+
+pub struct RecordProcessor<'a, A: DnsApi = ApiClient, S: AddressSource = Backend> {
+    clients: &'a Clients<A>,
+    backend: &'a S,
+    zone_cache: &'a AsyncZoneCache,
+    record_cache: Option<&'a AsyncRecordCache>,
+    published: Option<&'a AsyncPublishedState>,
+    skip_unchanged_secs: Option<u64>,
+    force: bool,
+    status: Option<&'a AsyncRecordStatus>,
+    report: Option<&'a Mutex<Vec<RecordReport>>>,
     iface: &'a str,
+    interface: &'a Interface,
     ui: &'a Ui,
+    syslog: Option<&'a SyslogNotifier>,
+    snapshot: Option<&'a Snapshot>,
+    lookup_providers: &'a [LookupProvider],
+    lookup_consensus: bool,
+    lookups: Option<&'a AsyncLookupState>,
+    lookup_cache_secs: Option<u64>,
     ipv4: Option<Ipv4Addr>,
     ipv6: Option<Ipv6Addr>,
+    global_ipv6: Vec<Ipv6Addr>,
+    round_robin: Option<&'a AsyncRoundRobinState>,
     web_v4: OnceCell<Ipv4Addr>,
     web_v6: OnceCell<Ipv6Addr>,
 }
 
-impl<'a> RecordProcessor<'a> {
-    pub async fn new(
-        client: &'a Client,
-        handle: &'a Handle,
+impl<'a, A: DnsApi, S: AddressSource> RecordProcessor<'a, A, S> {
+    pub fn new(
+        clients: &'a Clients<A>,
+        backend: &'a S,
         zone_cache: &'a AsyncZoneCache,
         iface: &'a str,
+        interface: &'a Interface,
+        (ipv4, ipv6): BestAddresses,
         ui: &'a Ui,
-    ) -> Result<Self, NetworkError> {
-        let (ipv4, ipv6) = best_addresses_by_interface(handle, iface).await?;
+    ) -> Self {
         debug!(
             interface = %iface,
             ipv4 = ?ipv4,
             ipv6 = ?ipv6,
             "Best addresses selected"
         );
-        Ok(Self {
-            client,
+        Self {
+            clients,
+            backend,
             zone_cache,
+            record_cache: None,
+            published: None,
+            skip_unchanged_secs: None,
+            force: false,
+            status: None,
+            report: None,
             iface,
+            interface,
             ui,
+            syslog: None,
+            snapshot: None,
+            lookup_providers: &[],
+            lookup_consensus: false,
+            lookups: None,
+            lookup_cache_secs: None,
             ipv4,
             ipv6,
+            global_ipv6: Vec::new(),
+            round_robin: None,
             web_v4: OnceCell::new(),
             web_v6: OnceCell::new(),
-        })
+        }
+    }
+
+    /// Every global IPv6 address of the interface, for records with `all_ipv6`
+    pub fn with_global_ipv6(mut self, global_ipv6: Vec<Ipv6Addr>) -> Self {
+        self.global_ipv6 = global_ipv6;
+        self
+    }
+
+    /// Track the addresses each interface contributes to round-robin records
+    pub fn with_round_robin(mut self, round_robin: &'a AsyncRoundRobinState) -> Self {
+        self.round_robin = Some(round_robin);
+        self
+    }
+
+    /// Update records by their cached IDs instead of looking them up each time
+    pub fn with_record_cache(mut self, record_cache: &'a AsyncRecordCache) -> Self {
+        self.record_cache = Some(record_cache);
+        self
+    }
+
+    /// Record what was published, and skip records published with the same state less than
+    /// `skip_unchanged_secs` ago
+    pub fn with_published_state(mut self, published: &'a AsyncPublishedState, skip_unchanged_secs: Option<u64>) -> Self {
+        self.published = Some(published);
+        self.skip_unchanged_secs = skip_unchanged_secs;
+        self
+    }
+
+    /// Send every record to Cloudflare, even ones that are already up to date
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Save the outcome of each record for `cfdns status`
+    pub fn with_status(mut self, status: &'a AsyncRecordStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Collect the outcome of each record for the run report
+    pub fn with_report(mut self, report: &'a Mutex<Vec<RecordReport>>) -> Self {
+        self.report = Some(report);
+        self
+    }
+
+    /// Send change events to syslog
+    pub fn with_syslog(mut self, syslog: Option<&'a SyslogNotifier>) -> Self {
+        self.syslog = syslog;
+        self
+    }
+
+    /// Read existing records from a saved snapshot instead of Cloudflare during dry runs
+    pub fn with_snapshot(mut self, snapshot: Option<&'a Snapshot>) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Send web lookups to these providers instead of Cloudflare's trace endpoint, in order or all at
+    /// once with `consensus`
+    pub fn with_lookup_providers(mut self, providers: &'a [LookupProvider], consensus: bool) -> Self {
+        self.lookup_providers = providers;
+        self.lookup_consensus = consensus;
+        self
+    }
+
+    /// Save web lookup results, and reuse ones made from the same address less than
+    /// `lookup_cache_secs` ago
+    pub fn with_lookup_cache(mut self, lookups: &'a AsyncLookupState, lookup_cache_secs: Option<u64>) -> Self {
+        self.lookups = Some(lookups);
+        self.lookup_cache_secs = lookup_cache_secs;
+        self
+    }
+
+    /// The public address a recent web lookup from `local` returned
+    fn cached_lookup(&self, local: IpAddr) -> Option<IpAddr> {
+        let (Some(lookups), Some(max_age)) = (self.lookups, self.lookup_cache_secs) else {
+            return None;
+        };
+        let lookups = lookups.read().unwrap();
+        lookups.get(&state::lookup_key(self.iface, &local))
+            .filter(|last| last.local == local && last.age() < max_age)
+            .map(|last| last.public)
+    }
+
+    fn remember_lookup(&self, local: IpAddr, public: IpAddr) {
+        if let Some(lookups) = self.lookups {
+            lookups.write().unwrap().insert(state::lookup_key(self.iface, &local), LookedUpAddress::now(local, public));
+        }
+    }
+
+    /// The settings a record is published with, taking over records cfdns didn't create under `--force`
+    fn settings<'r>(&self, record: &'r Record) -> RecordSettings<'r> {
+        RecordSettings { adopt: record.adopt || self.force, ..record_settings(record) }
+    }
+
+    /// The record's configured `zone_id`, or else the ID of its zone
+    async fn record_zone_id(&self, record: &Record) -> Result<String, ZoneError> {
+        match &record.zone_id {
+            Some(id) => Ok(id.clone()),
+            None => self.get_zone_id(&record.zone).await,
+        }
     }
 
     async fn get_zone_id(&self, zone_name: &str) -> Result<String, ZoneError> {
-        {
-            let cache = self.zone_cache.read().unwrap();
-            if let Some(id) = cache.get(zone_name) {
-                debug!(zone = zone_name, id, "Zone cache hit");
-                return Ok(id.clone());
-            }
+        match self.cached_zone_id(zone_name) {
+            Some(id) => Ok(id),
+            None => self.refresh_zone_id(zone_name).await,
         }
+    }
 
-        // fetch id from Cloudflare
+    fn cached_zone_id(&self, zone_name: &str) -> Option<String> {
+        let cache = self.zone_cache.read().unwrap();
+        let id = cache.zone_id(zone_name)?;
+        debug!(zone = zone_name, id, "Zone cache hit");
+        Some(id.to_string())
+    }
+
+    /// Look the zone up on Cloudflare, replacing any cached ID
+    async fn refresh_zone_id(&self, zone_name: &str) -> Result<String, ZoneError> {
         debug!(zone = zone_name, "Zone not in cache, querying");
-        let id = fetch_zone_id(self.client, zone_name).await?;
+        let id = self.clients.for_zone(zone_name).fetch_zone_id(zone_name).await?;
         // wait for a writer to update cache
         let mut cache = self.zone_cache.write().unwrap();
-        cache.insert(zone_name.to_string(), id.clone());
+        cache.insert_zone(zone_name, id.clone());
         Ok(id)
     }
 
     async fn get_web_ipv4(&self) -> Result<Option<Ipv4Addr>, LookupError> {
         let Some(local_ip) = self.ipv4 else { return Ok(None); };
         let interface = self.iface;
+        let backend = self.backend;
+        let config = self.interface;
+        // `default` isn't a real device, but its address is already on the link holding the default route
+        let device = (self.interface.web_lookup_bind_device && interface != DEFAULT_INTERFACE).then_some(interface);
+        let providers = self.lookup_providers;
+        let consensus = self.lookup_consensus;
+        let cached = self.cached_lookup(IpAddr::V4(local_ip));
         let ip = self.web_v4.get_or_try_init(|| async move {
-                let public = get_public_ipv4(local_ip).await?;
+                if let Some(IpAddr::V4(public)) = cached {
+                    debug!(interface, ipv4=%public, "Using cached web lookup");
+                    return Ok(public);
+                }
+                let public = match get_public_ipv4(local_ip, device, providers, consensus).await {
+                    Err(e) if e.is_bind_failure() => {
+                        warn!(interface, ipv4=%local_ip, "Could not bind web lookup to address, re-querying interface");
+                        match best_addresses_by_interface(backend, interface, config).await {
+                            Ok((Some(fresh), _)) => get_public_ipv4(fresh, device, providers, consensus).await?,
+                            _ => return Err(e),
+                        }
+                    }
+                    other => other?,
+                };
                 debug!(interface, ipv4=%public,"Resolved public IPv4 using web lookup");
                 Ok::<Ipv4Addr, LookupError>(public)
             })
             .await?;
+        if cached.is_none() {
+            self.remember_lookup(IpAddr::V4(local_ip), IpAddr::V4(*ip));
+        }
         Ok(Some(*ip))
     }
 
     async fn get_web_ipv6(&self) -> Result<Option<Ipv6Addr>, LookupError> {
         let Some(local_ip) = self.ipv6 else { return Ok(None); };
         let interface = self.iface;
+        let backend = self.backend;
+        let config = self.interface;
+        // `default` isn't a real device, but its address is already on the link holding the default route
+        let device = (self.interface.web_lookup_bind_device && interface != DEFAULT_INTERFACE).then_some(interface);
+        let providers = self.lookup_providers;
+        let consensus = self.lookup_consensus;
+        let cached = self.cached_lookup(IpAddr::V6(local_ip));
         let ip = self.web_v6.get_or_try_init(|| async move {
-                let public = get_public_ipv6(local_ip).await?;
+                if let Some(IpAddr::V6(public)) = cached {
+                    debug!(interface, ipv6=%public, "Using cached web lookup");
+                    return Ok(public);
+                }
+                let public = match get_public_ipv6(local_ip, device, providers, consensus).await {
+                    Err(e) if e.is_bind_failure() => {
+                        warn!(interface, ipv6=%local_ip, "Could not bind web lookup to address, re-querying interface");
+                        match best_addresses_by_interface(backend, interface, config).await {
+                            Ok((_, Some(fresh))) => get_public_ipv6(fresh, device, providers, consensus).await?,
+                            _ => return Err(e),
+                        }
+                    }
+                    other => other?,
+                };
                 debug!(interface, ipv6=%public,"Resolved public IPv6 using web lookup");
                 Ok::<Ipv6Addr, LookupError>(public)
             })
             .await?;
+        if cached.is_none() {
+            self.remember_lookup(IpAddr::V6(local_ip), IpAddr::V6(*ip));
+        }
         Ok(Some(*ip))
     }
 
+    /// Addresses to publish for the families this record uses. Web lookups are only made for
+    /// families the record needs and the interface actually has an address for.
+    async fn addresses_for(&self, record: &Record) -> Result<(Option<Ipv4Addr>, Option<Ipv6Addr>), LookupError> {
+        let ipv4 = if !record.record_type().includes_ipv4() {
+            None
+        } else if !record.uses_web_lookup() && !self.auto_web_lookup(record, self.ipv4.map(IpAddr::V4)) {
+            self.ipv4
+        } else if self.ipv4.is_none() {
+            debug!(interface=self.iface, domain=record.domain, "No IPv4 on interface, skipping web lookup");
+            None
+        } else {
+            let public = self.get_web_ipv4().await?;
+            if let (Some(local), Some(public)) = (self.ipv4, public) {
+                self.check_cgnat(local, public)?;
+            }
+            public
+        };
+        let ipv6 = if !record.record_type().includes_ipv6() {
+            None
+        } else if !record.uses_web_lookup() && !self.auto_web_lookup(record, self.ipv6.map(IpAddr::V6)) {
+            self.ipv6
+        } else if self.ipv6.is_none() {
+            debug!(interface=self.iface, domain=record.domain, "No IPv6 on interface, skipping web lookup");
+            None
+        } else {
+            self.get_web_ipv6().await?
+        };
+        Ok((ipv4, ipv6.map(|ip| record.ipv6_for(ip))))
+    }
+
+    /// Refuse a looked up IPv4 that can't accept inbound connections: one in the shared address space
+    /// itself, or one behind the interface's own shared or public address, which means another layer
+    /// of NAT. Translation from a private address is ordinary NAT and left alone.
+    fn check_cgnat(&self, local: Ipv4Addr, public: Ipv4Addr) -> Result<(), LookupError> {
+        if self.interface.allow_cgnat {
+            return Ok(());
+        }
+        let nat_layer = local != public && (networking::is_cgnat(&local) || networking::is_global(&IpAddr::V4(local)));
+        if networking::is_cgnat(&public) || nat_layer {
+            return Err(LookupError::BehindCgnat { interface: self.iface.to_string(), local, public });
+        }
+        Ok(())
+    }
+
+    /// Whether a record without `web_lookup` should look its address up anyway, because the interface
+    /// address isn't globally routable and `auto_web_lookup` is on
+    fn auto_web_lookup(&self, record: &Record, local: Option<IpAddr>) -> bool {
+        let Some(local) = local else { return false };
+        if self.interface.auto_web_lookup != Some(true) || networking::is_global(&local) {
+            return false;
+        }
+        info!(interface=self.iface, domain=record.domain, ip=%local, "Interface address is not globally routable, using a web lookup");
+        true
+    }
+
+    /// A warning for records about to publish an address that isn't globally routable, most likely
+    /// because the host is behind NAT. `auto_web_lookup: false` marks such addresses as intended.
+    fn private_address_note(&self, record: &Record, ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>) -> Option<String> {
+        if record.uses_web_lookup() || self.interface.auto_web_lookup.is_some() {
+            return None;
+        }
+        if let Some(shared) = ipv4.filter(networking::is_cgnat) {
+            warn!(interface=self.iface, domain=record.domain, ip=%shared, "Publishing a carrier-grade NAT address");
+            return Some(format!("{shared} is a carrier-grade NAT address that can't accept inbound connections, consider AAAA records or Cloudflare Tunnel"));
+        }
+        let private: Vec<String> = [ipv4.map(IpAddr::V4), ipv6.map(IpAddr::V6)]
+            .into_iter()
+            .flatten()
+            .filter(|ip| !networking::is_global(ip))
+            .map(|ip| ip.to_string())
+            .collect();
+        if private.is_empty() {
+            return None;
+        }
+        let private = private.join(", ");
+        warn!(
+            interface=self.iface,
+            domain=record.domain,
+            addresses=private,
+            "Publishing an address that is not globally routable, enable web_lookup or auto_web_lookup if this host is behind NAT"
+        );
+        Some(format!("{private} is not globally routable, set web_lookup or auto_web_lookup if this host is behind NAT"))
+    }
+
     async fn update_a_record(
         &self,
         ip: Option<Ipv4Addr>,
         zone_id: &str,
         record: &Record,
-        existing: Option<DnsRecord>,
-    ) -> Result<Option<DnsRecord>, UpdateError> {
+        existing: &ExistingRecords,
+    ) -> Result<(Option<IpAddr>, bool), UpdateError> {
         if let Some(ip) = ip {
-            let cf_record = try_update_record(
-                self.client,
-                zone_id,
-                &record.domain,
-                existing,
-                IpAddr::V4(ip),
-            )
-            .await?;
-            Ok(cf_record)
+            self.publish(IpAddr::V4(ip), zone_id, record, existing).await
         } else {
             warn!(
                 interface=self.iface,
                 domain=record.domain,
-                r#type=%record.r#type,
+                r#type=%record.record_type(),
                 "No IPv4 for this record"
             );
-            Ok(None)
+            Ok((None, false))
         }
     }
 
@@ -169,16 +949,16 @@ impl<'a> RecordProcessor<'a> {
         &self,
         ip: Option<Ipv4Addr>,
         record: &Record,
-        existing: Option<DnsRecord>,
+        existing: Option<&CachedRecord>,
     ) -> Result<Option<()>, UpdateError> {
         if let Some(ip) = ip {
-            let updated = try_update_record_dry_run(&record.domain, existing, IpAddr::V4(ip)).await?;
+            let updated = try_update_record_dry_run(&record.domain, existing, IpAddr::V4(ip), self.settings(record), self.force).await?;
             Ok(updated)
         } else {
             warn!(
                 interface=self.iface,
                 domain=record.domain,
-                r#type=%record.r#type,
+                r#type=%record.record_type(),
                 "No IPv4 for this record"
             );
             Ok(None)
@@ -190,43 +970,141 @@ impl<'a> RecordProcessor<'a> {
         ip: Option<Ipv6Addr>,
         zone_id: &str,
         record: &Record,
-        existing: Option<DnsRecord>,
-    ) -> Result<Option<DnsRecord>, UpdateError> {
+        existing: &ExistingRecords,
+    ) -> Result<(Option<IpAddr>, bool), UpdateError> {
         if let Some(ip) = ip {
-            let cf_record = try_update_record(
-                self.client,
-                zone_id,
-                &record.domain,
-                existing,
-                IpAddr::V6(ip),
-            )
-            .await?;
-            Ok(cf_record)
+            self.publish(IpAddr::V6(ip), zone_id, record, existing).await
         } else {
             warn!(
                 interface=self.iface,
                 domain=record.domain,
-                r#type=%record.r#type,
+                r#type=%record.record_type(),
                 "No IPv6 for this record"
             );
-            Ok(None)
+            Ok((None, false))
+        }
+    }
+
+    /// Create or update the record for `ip`, returning the address it replaced and whether it changed.
+    /// Records with a cached ID are updated directly; the rest, and cached IDs that no longer exist,
+    /// are looked up first.
+    async fn publish(
+        &self,
+        ip: IpAddr,
+        zone_id: &str,
+        record: &Record,
+        existing: &ExistingRecords,
+    ) -> Result<(Option<IpAddr>, bool), UpdateError> {
+        let client = self.clients.for_zone(&record.zone);
+        let name = api_name(&record.domain, &record.zone);
+        let settings = self.settings(record);
+        let key = record_cache_key(name, &ip);
+
+        let cached = self.record_cache.and_then(|cache| cache.read().unwrap().get(&key).cloned());
+        if let Some(cached) = cached {
+            check_managed(record, &cached, ip, settings)?;
+            if let Some(updated) = try_update_cached_record(client, zone_id, name, &cached, ip, settings).await? {
+                self.remember(key, CachedRecord::from_record(&updated));
+                let changed = ip != cached.state.ip || settings.differs_from(&cached.state) || settings.metadata_differs(&cached);
+                return Ok((Some(cached.state.ip), changed));
+            }
+            debug!(domain = record.domain, "Cached record no longer exists, looking it up");
+            if let Some(cache) = self.record_cache {
+                cache.write().unwrap().remove(&key);
+            }
+        }
+
+        let (existing_v4, existing_v6) = existing.get_or_try_init(|| async {
+                let (v4, v6) = client.fetch_ip_records(zone_id, name)
+                    .await
+                    .map_err(|e| UpdateError::fetch(&record.domain, e))?;
+                let state = |record: Option<CommentedRecord>| record
+                    .map(|r| CachedRecord::from_record(&r).ok_or(UpdateError::NotAnIpRecord))
+                    .transpose();
+                Ok::<_, UpdateError>((state(v4)?, state(v6)?))
+            })
+            .await?;
+        let existing = if ip.is_ipv4() { existing_v4 } else { existing_v6 }.clone();
+        let old = existing.as_ref().map(|e| e.state.ip);
+        if let Some(existing) = &existing {
+            check_managed(record, existing, ip, settings)?;
+        }
+
+        match try_update_record(client, zone_id, name, existing.clone(), ip, settings, self.force).await? {
+            Some(updated) => {
+                self.remember(key, CachedRecord::from_record(&updated));
+                // A forced update of a record that was already correct doesn't change it
+                let changed = existing.is_none_or(|e| ip != e.state.ip || settings.differs_from(&e.state) || settings.metadata_differs(&e));
+                Ok((old, changed))
+            }
+            None => {
+                self.remember(key, existing);
+                Ok((old, false))
+            }
         }
     }
 
+    /// Save a record that is now known to be published
+    fn remember(&self, key: String, record: Option<CachedRecord>) {
+        let Some(record) = record else { return };
+        if let Some(published) = self.published {
+            published.write().unwrap().insert(key.clone(), PublishedRecord::now(record.state));
+        }
+        if let Some(cache) = self.record_cache {
+            cache.write().unwrap().insert(key, record);
+        }
+    }
+
+    /// Drop a deleted record from the record cache and published state
+    fn forget(&self, key: &str) {
+        if let Some(published) = self.published {
+            published.write().unwrap().remove(key);
+        }
+        if let Some(cache) = self.record_cache {
+            cache.write().unwrap().remove(key);
+        }
+    }
+
+    /// Whether every address of this record was published with the same settings within
+    /// `skip_unchanged_secs`, so Cloudflare doesn't need to be asked
+    fn recently_published(&self, record: &Record, ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>) -> bool {
+        let (Some(published), Some(max_age)) = (self.published, self.skip_unchanged_secs) else {
+            return false;
+        };
+        // Records published as a set are always compared against Cloudflare, to catch extra records
+        if self.force || record.all_ipv6 || record.round_robin {
+            return false;
+        }
+        let published = published.read().unwrap();
+        let name = api_name(&record.domain, &record.zone);
+        let settings = record_settings(record);
+        let ips = [
+            record.record_type().includes_ipv4().then_some(ipv4.map(IpAddr::V4)),
+            record.record_type().includes_ipv6().then_some(ipv6.map(IpAddr::V6)),
+        ];
+        // A missing address still goes through the usual path, which warns about it
+        ips.into_iter().flatten().all(|ip| {
+            ip.and_then(|ip| published.get(&record_cache_key(name, &ip)).map(|last| (ip, last)))
+                .is_some_and(|(ip, last)| {
+                    last.state.ip == ip && !settings.differs_from(&last.state) && last.age() < max_age
+                })
+        })
+    }
+
     async fn update_aaaa_record_dry_run(
         &self,
         ip: Option<Ipv6Addr>,
         record: &Record,
-        existing: Option<DnsRecord>,
+        existing: Option<&CachedRecord>,
     ) -> Result<Option<()>, UpdateError> {
         if let Some(ip) = ip {
-            let updated = try_update_record_dry_run(&record.domain, existing, IpAddr::V6(ip)).await?;
+            let updated = try_update_record_dry_run(&record.domain, existing, IpAddr::V6(ip), self.settings(record), self.force).await?;
             Ok(updated)
         } else {
             warn!(
                 interface=self.iface,
                 domain=record.domain,
-                r#type=%record.r#type,
+                r#type=%record.record_type(),
                 "No IPv6 for this record"
             );
             Ok(None)
@@ -234,106 +1112,377 @@ impl<'a> RecordProcessor<'a> {
     }
 
     pub async fn process(&self, record: &Record) -> Result<()> {
-        info!(domain = record.domain, "Processing {} Record", record.r#type);
+        let started = Instant::now();
+        let result = self.try_process(record).await;
+        if let Err(e) = &result {
+            let errors = record_types(record).map(|t| (t, RunResult::Error, None));
+            self.save_status(record, errors, Some(describe_error(e)));
+        }
+        self.report(record, started, result)
+    }
+
+    /// Process a record, returning the outcome of each record type
+    async fn try_process(&self, record: &Record) -> Result<Vec<TypeOutcome>> {
+        info!(domain = record.domain, "Processing {} Record", record.record_type());
       
         let mut ui_ctx = UiRecordContext::new(self.ui.spinner(&record.domain));
 
-        let ipv4 = if !record.web_lookup { self.ipv4 } else { self.get_web_ipv4().await? };
-        let ipv6 = if !record.web_lookup { self.ipv6 } else { self.get_web_ipv6().await? };
-        let zone_id = self.get_zone_id(&record.zone).await?;
-
-        let (existing_v4, existing_v6) = fetch_ip_records(self.client, &zone_id, &record.domain)
-            .await
-            .into_diagnostic()?;
-
-        match record.r#type {
-            TypeOptions::A => {
-                let cf = self.update_a_record(ipv4, &zone_id, record, existing_v4).await?;
-                ui_ctx.ipv4_result(ipv4, cf.is_some());
-            }
-            TypeOptions::AAAA => {
-                let cf = self.update_aaaa_record(ipv6, &zone_id, record, existing_v6).await?;
-                ui_ctx.ipv6_result(ipv6, cf.is_some());
+        let (ipv4, ipv6) = self.addresses_for(record).await?;
+        if let Some(note) = self.private_address_note(record, ipv4, ipv6) {
+            ui_ctx.note(note);
+        }
+        if self.recently_published(record, ipv4, ipv6) {
+            info!(domain = record.domain, "Skipping record published recently with the same addresses");
+            if record.record_type().includes_ipv4() {
+                ui_ctx.ipv4_result(ipv4.map(IpAddr::V4), ipv4, false);
             }
-            TypeOptions::Both => {
-                let cf4 = self.update_a_record(ipv4, &zone_id, record, existing_v4).await?;
-                let cf6 = self.update_aaaa_record(ipv6, &zone_id, record, existing_v6).await?;
-                ui_ctx.ipv4_result(ipv4, cf4.is_some());
-                ui_ctx.ipv6_result(ipv6, cf6.is_some());
+            if record.record_type().includes_ipv6() {
+                ui_ctx.ipv6_result(ipv6.map(IpAddr::V6), ipv6, false);
             }
+            self.save_status(record, ui_ctx.results(), None);
+            let outcomes = ui_ctx.outcomes().collect();
+            ui_ctx.finish(&record.domain);
+            return Ok(outcomes);
+        }
+        let cached_zone_id = self.cached_zone_id(&record.zone);
+        let zone_id = match (&record.zone_id, &cached_zone_id) {
+            (Some(id), _) | (None, Some(id)) => id.clone(),
+            (None, None) => self.refresh_zone_id(&record.zone).await?,
         };
 
+        match self.publish_record(&zone_id, record, ipv4, ipv6, &mut ui_ctx).await {
+            // The zone may have been deleted and re-created since its ID was cached
+            Err(e) if record.zone_id.is_none() && cached_zone_id.is_some() && e.is_zone_rejected() => {
+                warn!(zone = record.zone, error = %e, "Cached zone ID was rejected, looking the zone up again");
+                let zone_id = self.refresh_zone_id(&record.zone).await?;
+                self.publish_record(&zone_id, record, ipv4, ipv6, &mut ui_ctx).await?;
+            }
+            result => result?,
+        }
+
+        if let Some(syslog) = self.syslog {
+            for (old, new) in ui_ctx.changes() {
+                syslog.record_changed(&record.domain, old, new);
+            }
+        }
+
+        let history: Vec<HistoryEntry> = ui_ctx.changes()
+            .map(|(old, new)| HistoryEntry::now(&record.domain, old, new, record.uses_web_lookup()))
+            .collect();
+        if !history.is_empty()
+            && let Err(e) = state::append_history(&history)
+        {
+            warn!(error = %e, "Could not write to the update history");
+        }
+
+        self.save_status(record, ui_ctx.results(), None);
+        let outcomes = ui_ctx.outcomes().collect();
         ui_ctx.finish(&record.domain);
+        Ok(outcomes)
+    }
+
+    /// Add the outcome of processing a record to the run report
+    fn report(&self, record: &Record, started: Instant, result: Result<Vec<TypeOutcome>>) -> Result<()> {
+        let Some(report) = self.report else { return result.map(|_| ()) };
+        let (outcomes, error) = match &result {
+            Ok(outcomes) => (outcomes.clone(), None),
+            Err(e) => (record_types(record).map(|t| (t, RunResult::Error, None, None)).collect(), Some(describe_error(e))),
+        };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        report.lock().unwrap().extend(outcomes.into_iter().map(|(r#type, result, old, new)| RecordReport {
+            domain: record.domain.clone(),
+            r#type: r#type.to_string(),
+            result,
+            old,
+            new,
+            error: error.clone(),
+            duration_ms,
+        }));
+        result.map(|_| ())
+    }
+
+    /// Save the outcome of each record type for `cfdns status`
+    fn save_status(
+        &self,
+        record: &Record,
+        results: impl Iterator<Item = (&'static str, RunResult, Option<IpAddr>)>,
+        error: Option<String>,
+    ) {
+        let Some(status) = self.status else { return };
+        let name = api_name(&record.domain, &record.zone);
+        let mut status = status.write().unwrap();
+        for (r#type, result, ip) in results {
+            let key = record_key(name, r#type);
+            let entry = RecordStatus::after(status.get(&key), result, ip, error.clone());
+            status.insert(key, entry);
+        }
+    }
+
+    async fn publish_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        ipv4: Option<Ipv4Addr>,
+        ipv6: Option<Ipv6Addr>,
+        ui_ctx: &mut UiRecordContext,
+    ) -> Result<(), UpdateError> {
+        // Looked up at most once, and only for records without a cached ID
+        let existing = ExistingRecords::new();
+
+        if record.record_type().includes_ipv4() {
+            match self.record_set(record, ipv4.map(IpAddr::V4), false, false) {
+                Some(ips) => self.update_record_set(zone_id, record, ips, false, ui_ctx).await?,
+                None => {
+                    let (old_v4, updated) = self.update_a_record(ipv4, zone_id, record, &existing).await?;
+                    ui_ctx.ipv4_result(old_v4, ipv4, updated);
+                }
+            }
+        }
+        if record.record_type().includes_ipv6() {
+            match self.record_set(record, ipv6.map(IpAddr::V6), true, false) {
+                Some(ips) => self.update_record_set(zone_id, record, ips, true, ui_ctx).await?,
+                None => {
+                    let (old_v6, updated) = self.update_aaaa_record(ipv6, zone_id, record, &existing).await?;
+                    ui_ctx.ipv6_result(old_v6, ipv6, updated);
+                }
+            }
+        }
         Ok(())
     }
 
-    pub async fn process_dry_run(&self, record: &Record) -> Result<()> {
-        info!(domain = record.domain, "Processing {} Record (dry-run)", record.r#type);
-        let mut ui_ctx = UiRecordContext::new(self.ui.spinner(&record.domain));
+    /// The addresses of one family of a record published as a set of records, or None if it is
+    /// published as a single record. Dry runs don't save this interface's round-robin addresses.
+    fn record_set(&self, record: &Record, ip: Option<IpAddr>, ipv6: bool, dry_run: bool) -> Option<Vec<IpAddr>> {
+        let own = if ipv6 && record.all_ipv6 { self.ipv6_set(record) } else { ip.into_iter().collect() };
+        if record.round_robin {
+            return Some(self.round_robin_set(record, ipv6, own, dry_run));
+        }
+        if ipv6 && record.all_ipv6 {
+            return Some(own);
+        }
+        // A stale record is deleted like the last member of an emptied set
+        (record.delete_stale && own.is_empty()).then_some(own)
+    }
 
+    /// The AAAA values of a record with `all_ipv6`, best first and without duplicates
+    fn ipv6_set(&self, record: &Record) -> Vec<IpAddr> {
+        let mut ips = Vec::new();
+        for ip in self.global_ipv6.iter().map(|ip| IpAddr::V6(record.ipv6_for(*ip))) {
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+        ips
+    }
 
-        let ipv4 = if !record.web_lookup {
-            self.ipv4
+    /// The addresses of every interface sharing a round-robin record, after replacing this
+    /// interface's addresses with `own`
+    fn round_robin_set(&self, record: &Record, ipv6: bool, own: Vec<IpAddr>, dry_run: bool) -> Vec<IpAddr> {
+        let Some(round_robin) = self.round_robin else { return own };
+        let key = record_key(api_name(&record.domain, &record.zone), if ipv6 { "AAAA" } else { "A" });
+        let mut set = round_robin.read().unwrap().get(&key).cloned().unwrap_or_default();
+        if own.is_empty() {
+            set.members.remove(self.iface);
         } else {
-            self.get_web_ipv4().await?
+            set.members.insert(self.iface.to_string(), own);
+        }
+        let ips = set.addresses();
+        debug!(interface=self.iface, domain=record.domain, members=set.members.len(), "Round-robin record set");
+        if !dry_run {
+            round_robin.write().unwrap().insert(key, set);
+        }
+        ips
+    }
+
+    /// Make the records of one family hold exactly `ips`, one record per address. An empty set
+    /// deletes the records only with `delete_stale`.
+    async fn update_record_set(&self, zone_id: &str, record: &Record, ips: Vec<IpAddr>, ipv6: bool, ui_ctx: &mut UiRecordContext) -> Result<(), UpdateError> {
+        if ips.is_empty() && !record.delete_stale {
+            self.no_set_addresses(record, ipv6, ui_ctx);
+            return Ok(());
+        }
+        let client = self.clients.for_zone(&record.zone);
+        let name = api_name(&record.domain, &record.zone);
+        let settings = self.settings(record);
+        let existing: Vec<CachedRecord> = client.fetch_all_ip_records(zone_id, name)
+            .await
+            .map_err(|e| UpdateError::fetch(&record.domain, e))?
+            .iter()
+            .filter_map(CachedRecord::from_record)
+            .filter(|r| r.state.ip.is_ipv6() == ipv6)
+            .collect();
+        if ips.is_empty() && existing.is_empty() {
+            self.no_set_addresses(record, ipv6, ui_ctx);
+            return Ok(());
+        }
+        let changes = reconcile_record_set(client, zone_id, name, existing, &ips, settings, self.force).await?;
+        for ip in changes.removed.iter().filter(|ip| !ips.contains(ip)) {
+            self.forget(&record_cache_key(name, ip));
+        }
+        ui_ctx.set_result(ipv6, ips, changes);
+        Ok(())
+    }
+
+    /// Like [`RecordProcessor::update_record_set`], without changing anything
+    async fn update_record_set_dry_run(&self, record: &Record, ips: Vec<IpAddr>, ipv6: bool, ui_ctx: &mut UiRecordContext) -> Result<()> {
+        if ips.is_empty() && !record.delete_stale {
+            self.no_set_addresses(record, ipv6, ui_ctx);
+            return Ok(());
+        }
+        let name = api_name(&record.domain, &record.zone);
+        let existing: Vec<CachedRecord> = match self.snapshot {
+            Some(snapshot) => snapshot.ip_record_set(&record.zone, name)?,
+            None => {
+                let zone_id = self.record_zone_id(record).await?;
+                self.clients.for_zone(&record.zone).fetch_all_ip_records(&zone_id, name)
+                    .await
+                    .map_err(|e| UpdateError::fetch(&record.domain, e))?
+                    .iter()
+                    .filter_map(CachedRecord::from_record)
+                    .collect()
+            }
         };
-        let ipv6 = if !record.web_lookup {
-            self.ipv6
+        let existing: Vec<CachedRecord> = existing.into_iter().filter(|r| r.state.ip.is_ipv6() == ipv6).collect();
+        if ips.is_empty() && existing.is_empty() {
+            self.no_set_addresses(record, ipv6, ui_ctx);
+            return Ok(());
+        }
+        let changes = reconcile_record_set_dry_run(&record.domain, existing, &ips, self.settings(record), self.force);
+        ui_ctx.set_result(ipv6, ips, changes);
+        Ok(())
+    }
+
+    fn no_set_addresses(&self, record: &Record, ipv6: bool, ui_ctx: &mut UiRecordContext) {
+        if ipv6 {
+            warn!(interface=self.iface, domain=record.domain, r#type=%record.record_type(), "No IPv6 for this record");
+            ui_ctx.ipv6_result(None, None, false);
         } else {
-            self.get_web_ipv6().await?
-        };
-        let zone_id = self.get_zone_id(&record.zone).await?;
+            warn!(interface=self.iface, domain=record.domain, r#type=%record.record_type(), "No IPv4 for this record");
+            ui_ctx.ipv4_result(None, None, false);
+        }
+    }
 
-        let (existing_v4, existing_v6) = fetch_ip_records(self.client, &zone_id, &record.domain)
-            .await
-            .into_diagnostic()?;
+    pub async fn process_dry_run(&self, record: &Record) -> Result<()> {
+        let started = Instant::now();
+        let result = self.try_process_dry_run(record).await;
+        self.report(record, started, result)
+    }
 
-        match record.r#type {
-            TypeOptions::A => {
-                let cf = self.update_a_record_dry_run(ipv4, record, existing_v4).await?;
-                ui_ctx.ipv4_result(ipv4, cf.is_some());
+    async fn try_process_dry_run(&self, record: &Record) -> Result<Vec<TypeOutcome>> {
+        info!(domain = record.domain, "Processing {} Record (dry-run)", record.record_type());
+        let mut ui_ctx = UiRecordContext::new(self.ui.spinner(&record.domain));
+
+
+        let (ipv4, ipv6) = self.addresses_for(record).await?;
+        if let Some(note) = self.private_address_note(record, ipv4, ipv6) {
+            ui_ctx.note(note);
+        }
+        let (old_v4, old_v6) = match self.snapshot {
+            Some(snapshot) => snapshot.ip_records(&record.zone, api_name(&record.domain, &record.zone))?,
+            None => {
+                let zone_id = self.record_zone_id(record).await?;
+                let (existing_v4, existing_v6) = self.clients.for_zone(&record.zone).fetch_ip_records(&zone_id, api_name(&record.domain, &record.zone))
+                    .await
+                    .map_err(|e| UpdateError::fetch(&record.domain, e))?;
+                // Records published as a set leave records cfdns didn't create alone rather than refusing
+                let settings = self.settings(record);
+                let single = [
+                    (existing_v4.as_ref(), ipv4.map(IpAddr::V4), !record.round_robin),
+                    (existing_v6.as_ref(), ipv6.map(IpAddr::V6), !record.round_robin && !record.all_ipv6),
+                ];
+                for (existing, ip, single) in single {
+                    if let (Some(existing), Some(ip), true) = (existing.and_then(CachedRecord::from_record), ip, single) {
+                        check_managed(record, &existing, ip, settings)?;
+                    }
+                }
+                (
+                    existing_v4.as_ref().and_then(CachedRecord::from_record),
+                    existing_v6.as_ref().and_then(CachedRecord::from_record),
+                )
             }
-            TypeOptions::AAAA => {
-                let cf = self.update_aaaa_record_dry_run(ipv6, record, existing_v6).await?;
-                ui_ctx.ipv6_result(ipv6, cf.is_some());
+        };
+
+        if record.record_type().includes_ipv4() {
+            match self.record_set(record, ipv4.map(IpAddr::V4), false, true) {
+                Some(ips) => self.update_record_set_dry_run(record, ips, false, &mut ui_ctx).await?,
+                None => {
+                    let cf = self.update_a_record_dry_run(ipv4, record, old_v4.as_ref()).await?;
+                    ui_ctx.ipv4_result(old_v4.as_ref().map(|r| r.state.ip), ipv4, cf.is_some());
+                }
             }
-            TypeOptions::Both => {
-                let cf4 = self.update_a_record_dry_run(ipv4, record, existing_v4).await?;
-                let cf6 = self.update_aaaa_record_dry_run(ipv6, record, existing_v6).await?;
-                ui_ctx.ipv4_result(ipv4, cf4.is_some());
-                ui_ctx.ipv6_result(ipv6, cf6.is_some());
+        }
+        if record.record_type().includes_ipv6() {
+            match self.record_set(record, ipv6.map(IpAddr::V6), true, true) {
+                Some(ips) => self.update_record_set_dry_run(record, ips, true, &mut ui_ctx).await?,
+                None => {
+                    let cf = self.update_aaaa_record_dry_run(ipv6, record, old_v6.as_ref()).await?;
+                    ui_ctx.ipv6_result(old_v6.as_ref().map(|r| r.state.ip), ipv6, cf.is_some());
+                }
             }
-        };
+        }
 
+        let outcomes = ui_ctx.outcomes().collect();
         ui_ctx.finish(&record.domain);
-        Ok(())
+        Ok(outcomes)
     }
 
-    pub async fn batch_process(&self, records: Vec<Record>, limit: usize) -> Result<()> {
-        futures::stream::iter(records)
+    /// Process every record, even if some fail, returning [`RecordsFailed`] with each failure
+    pub async fn batch_process(&self, records: &[Record], limit: usize) -> Result<()> {
+        let failures = futures::stream::iter(records)
         .map(|record| {
             async move { 
-                self.process(&record).await
+                self.process(record).await
             }
         })
         .buffer_unordered(limit)
-        .try_collect::<()>()
-        .await
+        .filter_map(|result| async move { result.err() })
+        .collect()
+        .await;
+        RecordsFailed::check(failures)
     }
 
-    pub async fn batch_process_dry_run(&self, records: Vec<Record>, limit: usize) -> Result<()> {
-        futures::stream::iter(records)
+    pub async fn batch_process_dry_run(&self, records: &[Record], limit: usize) -> Result<()> {
+        let failures = futures::stream::iter(records)
         .map(|record| {
             async move { 
-                self.process_dry_run(&record).await
+                self.process_dry_run(record).await
             }
         })
         .buffer_unordered(limit)
-        .try_collect::<()>()
-        .await
+        .filter_map(|result| async move { result.err() })
+        .collect()
+        .await;
+        RecordsFailed::check(failures)
+    }
+}
+
+/// The record types a record is published as
+pub(super) fn record_types(record: &Record) -> impl Iterator<Item = &'static str> {
+    [
+        record.record_type().includes_ipv4().then_some("A"),
+        record.record_type().includes_ipv6().then_some("AAAA"),
+    ].into_iter().flatten()
+}
+
+/// Refuse to point a record cfdns didn't create at a different address, unless it is being adopted
+fn check_managed(record: &Record, existing: &CachedRecord, ip: IpAddr, settings: RecordSettings<'_>) -> Result<(), UpdateError> {
+    if existing.managed || settings.adopt || existing.state.ip == ip {
+        return Ok(());
     }
+    warn!(domain = record.domain, existing = %existing.state.ip, %ip, "Refusing to overwrite a record cfdns didn't create");
+    Err(UpdateError::Unmanaged { domain: record.domain.clone(), existing: existing.state.ip })
 }
 
+/// Settings from the config to apply alongside the record's address
+fn record_settings(record: &Record) -> RecordSettings<'_> {
+    RecordSettings {
+        ttl: record.ttl.map(Ttl::seconds),
+        proxied: record.proxied,
+        adopt: record.adopt,
+        comment: record.comment.as_deref(),
+        tags: &record.tags,
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Ui {
@@ -363,7 +1512,7 @@ impl Ui {
         }
         let pb = self.mp.add(ProgressBar::new_spinner());        
         pb.set_style(Self::style());
-        pb.set_message(domain.to_string());
+        pb.set_message(display_domain(domain).into_owned());
         pb.enable_steady_tick(Duration::from_millis(120));
         pb
     }
@@ -376,18 +1525,25 @@ impl Ui {
     }
 }
 
+/// A record type with its result and its address before and after
+type TypeOutcome = (&'static str, RunResult, Option<IpAddr>, Option<IpAddr>);
+
 #[derive(Debug)]
 enum Outcome {
-    Updated { new: IpAddr },
+    Updated { old: Option<IpAddr>, new: IpAddr },
     NoChange(IpAddr),
     Skipped,
     NotApplicable,
+    /// Published as one record per address
+    Set { ips: Vec<IpAddr>, changes: RecordSetChanges },
 }
 
 pub struct UiRecordContext {
     pb: ProgressBar,
     ipv4: Outcome,
     ipv6: Outcome,
+    /// Shown after the outcome, for things the user should act on
+    note: Option<String>,
 }
 
 impl UiRecordContext {
@@ -396,13 +1552,18 @@ impl UiRecordContext {
             pb,
             ipv4: Outcome::NotApplicable,
             ipv6: Outcome::NotApplicable,
+            note: None,
         }
     }
 
-    pub fn ipv4_result(&mut self, sent: Option<Ipv4Addr>, updated: bool) {
+    pub fn note(&mut self, note: String) {
+        self.note = Some(note);
+    }
+
+    pub fn ipv4_result(&mut self, old: Option<IpAddr>, sent: Option<Ipv4Addr>, updated: bool) {
         self.ipv4 = if let Some(ip) = sent {
             if updated {
-                Outcome::Updated { new: IpAddr::V4(ip) }
+                Outcome::Updated { old, new: IpAddr::V4(ip) }
             } else {
                 Outcome::NoChange( IpAddr::V4(ip) )
             }
@@ -412,10 +1573,10 @@ impl UiRecordContext {
         }
     }
 
-    pub fn ipv6_result(&mut self, sent: Option<Ipv6Addr>, updated: bool) {
+    pub fn ipv6_result(&mut self, old: Option<IpAddr>, sent: Option<Ipv6Addr>, updated: bool) {
         self.ipv6 = if let Some(ip) = sent {
             if updated {
-                Outcome::Updated { new: IpAddr::V6(ip) }
+                Outcome::Updated { old, new: IpAddr::V6(ip) }
             } else {
                 Outcome::NoChange( IpAddr::V6(ip) )
             }
@@ -425,40 +1586,555 @@ impl UiRecordContext {
         }
     }
 
+    pub fn set_result(&mut self, ipv6: bool, ips: Vec<IpAddr>, changes: RecordSetChanges) {
+        let outcome = Outcome::Set { ips, changes };
+        if ipv6 {
+            self.ipv6 = outcome;
+        } else {
+            self.ipv4 = outcome;
+        }
+    }
+
+    /// The result for each record type this record uses, with the address it now has
+    pub fn results(&self) -> impl Iterator<Item = (&'static str, RunResult, Option<IpAddr>)> + '_ {
+        self.outcomes().map(|(r#type, result, _, new)| (r#type, result, new))
+    }
+
+    /// Like [`UiRecordContext::results`], with the address each record type had before
+    pub fn outcomes(&self) -> impl Iterator<Item = TypeOutcome> + '_ {
+        [("A", &self.ipv4), ("AAAA", &self.ipv6)].into_iter().filter_map(|(r#type, outcome)| match outcome {
+            Outcome::Updated { old, new } => Some((r#type, RunResult::Updated, *old, Some(*new))),
+            Outcome::NoChange(ip) => Some((r#type, RunResult::Unchanged, Some(*ip), Some(*ip))),
+            Outcome::Skipped => Some((r#type, RunResult::Skipped, None, None)),
+            Outcome::NotApplicable => None,
+            // Sets are summed up by their best address
+            Outcome::Set { ips, changes } => {
+                let result = if changes.changed { RunResult::Updated } else { RunResult::Unchanged };
+                let old = if changes.changed { changes.removed.first().copied() } else { ips.first().copied() };
+                Some((r#type, result, old, ips.first().copied()))
+            }
+        })
+    }
+
+    /// Every address change in this record as `(old, new)` pairs
+    pub fn changes(&self) -> impl Iterator<Item = (Option<IpAddr>, IpAddr)> + '_ {
+        [&self.ipv4, &self.ipv6].into_iter().flat_map(|outcome| match outcome {
+            Outcome::Updated { old, new } => vec![(*old, *new)],
+            // Each added address replaces a removed one while there are any left
+            Outcome::Set { changes, .. } => changes.added
+                .iter()
+                .enumerate()
+                .map(|(i, new)| (changes.removed.get(i).copied(), *new))
+                .collect(),
+            _ => Vec::new(),
+        })
+    }
+
     pub fn finish(self, domain: &str) {
         self.pb.finish_with_message(format!(
             "{}   {}",
-            domain.bold(),
+            display_domain(domain).bold(),
             self.render()
         ));
     }
 
     fn render(&self) -> String {
         let v4: Option<String> = match &self.ipv4 {
-            Outcome::Updated { new } =>
-                format!("IPv4 updated => {}", new.to_string().green()).into(),
+            Outcome::Updated { old: Some(old), new } =>
+                format!("IPv4 updated {} => {}", old, new.to_string().green()).into(),
+            Outcome::Updated { old: None, new } =>
+                format!("IPv4 created => {}", new.to_string().green()).into(),
             Outcome::NoChange(ip) =>
                 format!("IPv4 unchanged ({})", ip.to_string().yellow()).into(),
             Outcome::Skipped =>
                 "IPv4 not found!".red().to_string().into(),
-            Outcome::NotApplicable => None
+            Outcome::NotApplicable => None,
+            Outcome::Set { ips, changes } => Some(render_set("IPv4", ips, changes)),
         };
 
         let v6: Option<String> = match &self.ipv6 {
-            Outcome::Updated { new } =>
-                format!("IPv6 updated => {}", new.to_string().green()).into(),
+            Outcome::Updated { old: Some(old), new } =>
+                format!("IPv6 updated {} => {}", old, new.to_string().green()).into(),
+            Outcome::Updated { old: None, new } =>
+                format!("IPv6 created => {}", new.to_string().green()).into(),
             Outcome::NoChange(ip) =>
                 format!("IPv6 unchanged ({})", ip.to_string().yellow()).into(),
             Outcome::Skipped =>
                 "IPv6 not found!".red().to_string().into(),
-            Outcome::NotApplicable => None
+            Outcome::NotApplicable => None,
+            Outcome::Set { ips, changes } => Some(render_set("IPv6", ips, changes)),
         };
 
-        match (v4, v6) {
+        let outcome = match (v4, v6) {
             (Some(v4), Some(v6)) => format!("{} {}", v4, v6),
             (Some(v4), None) => format!("{}", v4),
             (None, Some(v6)) => format!("{}", v6),
             _ => format!("No updates performed")
+        };
+        match &self.note {
+            Some(note) => format!("{}   {}", outcome, format!("Warning: {note}").yellow()),
+            None => outcome,
         }
     }
-}
\ No newline at end of file
+}
+/// Describe a family published as a set of records
+fn render_set(family: &str, ips: &[IpAddr], changes: &RecordSetChanges) -> String {
+    let list = |ips: &[IpAddr]| ips.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(", ");
+    if ips.is_empty() {
+        return format!("{family} deleted ({})", list(&changes.removed).red());
+    }
+    if !changes.changed {
+        return format!("{family} unchanged ({})", list(ips).yellow());
+    }
+    let mut out = format!("{family} set => {}", list(ips).green());
+    if !changes.removed.is_empty() {
+        out.push_str(&format!(" (removed {})", list(&changes.removed)));
+    }
+    out
+}
+
+/// A record dropped before it finished was cancelled, so stop its spinner instead of leaving it running
+impl Drop for UiRecordContext {
+    fn drop(&mut self) {
+        if !self.pb.is_finished() {
+            self.pb.abandon_with_message(format!("{}   {}", self.pb.message().bold(), "cancelled".red()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use cloudflare::framework::response::ApiErrors;
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::cloudflare::dns::{RecordState, managed_comment};
+    use crate::config::AddressSelection;
+    use crate::networking::{InterfaceAddress, NetworkError};
+
+    const ZONE_ID: &str = "zone-id";
+
+    /// An A or AAAA record held by [`MockApi`]
+    struct MockRecord {
+        id: String,
+        name: String,
+        ip: IpAddr,
+        ttl: u32,
+        proxied: bool,
+        comment: Option<String>,
+        tags: Vec<String>,
+    }
+
+    impl MockRecord {
+        fn to_commented(&self) -> CommentedRecord {
+            let (r#type, content) = match self.ip {
+                IpAddr::V4(ip) => ("A", ip.to_string()),
+                IpAddr::V6(ip) => ("AAAA", ip.to_string()),
+            };
+            serde_json::from_value(serde_json::json!({
+                "meta": {},
+                "name": self.name,
+                "ttl": self.ttl,
+                "modified_on": "2025-01-01T00:00:00Z",
+                "created_on": "2025-01-01T00:00:00Z",
+                "proxiable": true,
+                "type": r#type,
+                "content": content,
+                "id": self.id,
+                "proxied": self.proxied,
+                "comment": self.comment,
+                "tags": self.tags,
+            }))
+            .unwrap()
+        }
+    }
+
+    /// A single zone kept in memory, counting every request made to it
+    #[derive(Default)]
+    struct MockApi {
+        records: Mutex<Vec<MockRecord>>,
+        calls: AtomicUsize,
+    }
+
+    impl MockApi {
+        fn with_record(self, name: &str, ip: IpAddr, managed: bool) -> Self {
+            let comment = managed.then(|| managed_comment(None));
+            let id = format!("record-{}", self.records.lock().unwrap().len());
+            self.records.lock().unwrap().push(MockRecord { id, name: name.to_string(), ip, ttl: 1, proxied: false, comment, tags: Vec::new() });
+            self
+        }
+
+        fn ips(&self, name: &str) -> Vec<IpAddr> {
+            self.records.lock().unwrap().iter().filter(|r| r.name == name).map(|r| r.ip).collect()
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::Relaxed)
+        }
+
+        fn call(&self) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    impl DnsApi for MockApi {
+        async fn fetch_zone_id(&self, _zone_name: &str) -> Result<String, ZoneError> {
+            self.call();
+            Ok(ZONE_ID.to_string())
+        }
+
+        async fn fetch_ip_records(&self, _zone_id: &str, domain: &str) -> Result<(Option<CommentedRecord>, Option<CommentedRecord>), ApiFailure> {
+            self.call();
+            let records = self.records.lock().unwrap();
+            let first = |ipv6: bool| records.iter().find(|r| r.name == domain && r.ip.is_ipv6() == ipv6).map(MockRecord::to_commented);
+            Ok((first(false), first(true)))
+        }
+
+        async fn create_record(&self, _zone_id: &str, domain: &str, ip: IpAddr, settings: RecordSettings<'_>) -> Result<CommentedRecord, ApiFailure> {
+            self.call();
+            let mut records = self.records.lock().unwrap();
+            let record = MockRecord {
+                id: format!("record-{}", records.len()),
+                name: domain.to_string(),
+                ip,
+                ttl: settings.ttl.unwrap_or(1),
+                proxied: settings.proxied.unwrap_or(false),
+                comment: Some(managed_comment(settings.comment)),
+                tags: settings.tags.to_vec(),
+            };
+            let created = record.to_commented();
+            records.push(record);
+            Ok(created)
+        }
+
+        async fn update_record(&self, _zone_id: &str, record: &CachedRecord, ip: IpAddr, settings: RecordSettings<'_>) -> Result<CommentedRecord, ApiFailure> {
+            self.call();
+            let mut records = self.records.lock().unwrap();
+            let Some(existing) = records.iter_mut().find(|r| r.id == record.id) else {
+                return Err(ApiFailure::Error(StatusCode::NOT_FOUND, ApiErrors::default()));
+            };
+            existing.ip = ip;
+            existing.ttl = settings.ttl.unwrap_or(existing.ttl);
+            existing.proxied = settings.proxied.unwrap_or(existing.proxied);
+            if settings.adopt || record.managed {
+                existing.comment = Some(managed_comment(settings.comment));
+                if !settings.tags.is_empty() {
+                    existing.tags = settings.tags.to_vec();
+                }
+            }
+            Ok(existing.to_commented())
+        }
+
+        async fn fetch_all_ip_records(&self, _zone_id: &str, domain: &str) -> Result<Vec<CommentedRecord>, ApiFailure> {
+            self.call();
+            Ok(self.records.lock().unwrap().iter().filter(|r| r.name == domain).map(MockRecord::to_commented).collect())
+        }
+
+        async fn delete_record(&self, _zone_id: &str, record: &CachedRecord) -> Result<(), ApiFailure> {
+            self.call();
+            self.records.lock().unwrap().retain(|r| r.id != record.id);
+            Ok(())
+        }
+    }
+
+    /// Interfaces without any addresses, for records whose addresses are handed to the processor
+    #[derive(Default)]
+    struct FakeSource {
+        interfaces: Vec<String>,
+        /// Returned as the route source of every interface
+        route: Option<IpAddr>,
+    }
+
+    impl AddressSource for FakeSource {
+        async fn interfaces(&self) -> Result<Vec<String>, NetworkError> {
+            Ok(self.interfaces.clone())
+        }
+
+        async fn addresses(&self) -> Result<Vec<InterfaceAddress>, NetworkError> {
+            Ok(Vec::new())
+        }
+
+        async fn route_source(&self, _interface: &str, _destination: IpAddr) -> Result<Option<IpAddr>, NetworkError> {
+            Ok(self.route)
+        }
+
+        async fn macs(&self) -> Result<HashMap<String, [u8; 6]>, NetworkError> {
+            Ok(HashMap::new())
+        }
+
+        async fn route_interface(&self, _destination: IpAddr) -> Result<Option<String>, NetworkError> {
+            Ok(None)
+        }
+    }
+
+    /// Everything a [`RecordProcessor`] borrows, with empty caches that are never saved
+    struct Fixture {
+        clients: Clients<MockApi>,
+        source: FakeSource,
+        zone_cache: AsyncZoneCache,
+        published: AsyncPublishedState,
+        interface: Interface,
+        ui: Ui,
+    }
+
+    impl Fixture {
+        fn new(api: MockApi) -> Self {
+            let dir = std::env::temp_dir().join(format!("cfdns-test-{}", std::process::id()));
+            Self {
+                clients: api.into(),
+                source: FakeSource::default(),
+                zone_cache: Cache::load_in(&dir, "zones").unwrap().into_threadsafe(),
+                published: Cache::load_in(&dir, "published").unwrap().into_threadsafe(),
+                interface: Interface::default(),
+                ui: Ui::new(),
+            }
+        }
+
+        fn processor(&self, ipv4: Ipv4Addr) -> RecordProcessor<'_, MockApi, FakeSource> {
+            self.processor_with((Some(ipv4), None))
+        }
+
+        fn processor_with(&self, addresses: BestAddresses) -> RecordProcessor<'_, MockApi, FakeSource> {
+            RecordProcessor::new(&self.clients, &self.source, &self.zone_cache, "eth0", &self.interface, addresses, &self.ui)
+                .with_published_state(&self.published, Some(300))
+        }
+
+        fn api(&self) -> &MockApi {
+            self.clients.for_zone("example.com")
+        }
+    }
+
+    fn a_record(domain: &str) -> Record {
+        serde_yaml::from_str(&format!("domain: {domain}\nzone: example.com\ntype: A")).unwrap()
+    }
+
+    const OLD: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+    const NEW: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 2);
+
+    #[tokio::test]
+    async fn creates_missing_record() {
+        let fixture = Fixture::new(MockApi::default());
+        let record = a_record("home.example.com");
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+
+        assert_eq!(fixture.api().ips("home.example.com"), [IpAddr::V4(NEW)]);
+        let outcomes: Vec<_> = ui_ctx.outcomes().collect();
+        assert_eq!(outcomes, [("A", RunResult::Updated, None, Some(IpAddr::V4(NEW)))]);
+        assert_eq!(fixture.api().records.lock().unwrap()[0].comment, Some(managed_comment(None)));
+    }
+
+    #[tokio::test]
+    async fn updates_changed_record() {
+        let fixture = Fixture::new(MockApi::default().with_record("home.example.com", IpAddr::V4(OLD), true));
+        let record = a_record("home.example.com");
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+
+        assert_eq!(fixture.api().ips("home.example.com"), [IpAddr::V4(NEW)]);
+        let outcomes: Vec<_> = ui_ctx.outcomes().collect();
+        assert_eq!(outcomes, [("A", RunResult::Updated, Some(IpAddr::V4(OLD)), Some(IpAddr::V4(NEW)))]);
+        let published = fixture.published.read().unwrap();
+        assert_eq!(published.get(&record_cache_key("home.example.com", &IpAddr::V4(NEW))).unwrap().state.ip, IpAddr::V4(NEW));
+    }
+
+    #[tokio::test]
+    async fn skips_recently_published_record() {
+        let fixture = Fixture::new(MockApi::default().with_record("home.example.com", IpAddr::V4(OLD), true));
+        let record = a_record("home.example.com");
+        let state = RecordState { ip: IpAddr::V4(NEW), ttl: 1, proxied: false };
+        fixture.published.write().unwrap().insert(record_cache_key("home.example.com", &state.ip), PublishedRecord::now(state));
+
+        fixture.processor(NEW).process(&record).await.unwrap();
+
+        assert_eq!(fixture.api().calls(), 0);
+        assert_eq!(fixture.api().ips("home.example.com"), [IpAddr::V4(OLD)]);
+    }
+
+    #[tokio::test]
+    async fn dry_run_changes_nothing() {
+        let fixture = Fixture::new(MockApi::default().with_record("home.example.com", IpAddr::V4(OLD), true));
+        let record = a_record("home.example.com");
+        let report = Mutex::new(Vec::new());
+
+        fixture.processor(NEW).with_report(&report).process_dry_run(&record).await.unwrap();
+
+        assert_eq!(fixture.api().ips("home.example.com"), [IpAddr::V4(OLD)]);
+        let report = report.into_inner().unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].result, RunResult::Updated);
+        assert_eq!((report[0].old, report[0].new), (Some(IpAddr::V4(OLD)), Some(IpAddr::V4(NEW))));
+    }
+
+    #[tokio::test]
+    async fn dry_run_leaves_unmanaged_set_members_like_a_real_run() {
+        let api = MockApi::default()
+            .with_record("home.example.com", IpAddr::V4(NEW), true)
+            .with_record("home.example.com", IpAddr::V4(OLD), false);
+        let fixture = Fixture::new(api);
+        let record: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\nround_robin: true").unwrap();
+        let report = Mutex::new(Vec::new());
+
+        fixture.processor(NEW).with_report(&report).process_dry_run(&record).await.unwrap();
+        let report = report.into_inner().unwrap();
+        assert_eq!(report[0].result, RunResult::Unchanged);
+
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+        assert_eq!(fixture.api().ips("home.example.com"), [IpAddr::V4(NEW), IpAddr::V4(OLD)]);
+    }
+
+    #[tokio::test]
+    async fn dry_run_adopts_set_members_like_a_real_run() {
+        let api = MockApi::default()
+            .with_record("home.example.com", IpAddr::V4(NEW), true)
+            .with_record("home.example.com", IpAddr::V4(OLD), false);
+        let fixture = Fixture::new(api);
+        let record: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\nround_robin: true\nadopt: true").unwrap();
+        let report = Mutex::new(Vec::new());
+
+        fixture.processor(NEW).with_report(&report).process_dry_run(&record).await.unwrap();
+        let report = report.into_inner().unwrap();
+        assert_eq!((report[0].result, report[0].old), (RunResult::Updated, Some(IpAddr::V4(OLD))));
+
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+        assert_eq!(fixture.api().ips("home.example.com"), [IpAddr::V4(NEW)]);
+    }
+
+    /// The result of a dry run of `record` on an interface holding [`NEW`]
+    async fn dry_run_result(fixture: &Fixture, record: &Record) -> RunResult {
+        let report = Mutex::new(Vec::new());
+        fixture.processor(NEW).with_report(&report).process_dry_run(record).await.unwrap();
+        report.into_inner().unwrap()[0].result
+    }
+
+    #[tokio::test]
+    async fn dry_run_sees_comment_changes() {
+        let fixture = Fixture::new(MockApi::default().with_record("home.example.com", IpAddr::V4(NEW), true));
+        let record: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\ncomment: office").unwrap();
+
+        assert_eq!(dry_run_result(&fixture, &record).await, RunResult::Updated);
+
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+        assert_eq!(fixture.api().records.lock().unwrap()[0].comment, Some(managed_comment(Some("office"))));
+        assert_eq!(dry_run_result(&fixture, &record).await, RunResult::Unchanged);
+    }
+
+    #[tokio::test]
+    async fn dry_run_sees_tag_changes() {
+        let fixture = Fixture::new(MockApi::default().with_record("home.example.com", IpAddr::V4(NEW), true));
+        let single: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\ntags: [home]").unwrap();
+        let set: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\ntags: [home]\nround_robin: true").unwrap();
+
+        assert_eq!(dry_run_result(&fixture, &single).await, RunResult::Updated);
+        assert_eq!(dry_run_result(&fixture, &set).await, RunResult::Updated);
+
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+        fixture.processor(NEW).publish_record(ZONE_ID, &single, Some(NEW), None, &mut ui_ctx).await.unwrap();
+        assert_eq!(fixture.api().records.lock().unwrap()[0].tags, ["home"]);
+        assert_eq!(dry_run_result(&fixture, &single).await, RunResult::Unchanged);
+        assert_eq!(dry_run_result(&fixture, &set).await, RunResult::Unchanged);
+    }
+
+    #[tokio::test]
+    async fn leaves_records_of_other_hosts_alone() {
+        let fixture = Fixture::new(MockApi::default().with_record("home.example.com", IpAddr::V4(NEW), true));
+        let foreign = "managed-by=cfdns; owner=other-host";
+        fixture.api().records.lock().unwrap()[0].comment = Some(foreign.to_string());
+        let record: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\ncomment: office").unwrap();
+
+        assert_eq!(dry_run_result(&fixture, &record).await, RunResult::Unchanged);
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+        assert_eq!(fixture.api().records.lock().unwrap()[0].comment.as_deref(), Some(foreign));
+
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+        let moved = fixture.processor(OLD).publish_record(ZONE_ID, &record, Some(OLD), None, &mut ui_ctx).await;
+        assert!(matches!(moved, Err(UpdateError::Unmanaged { .. })));
+        assert_eq!(fixture.api().ips("home.example.com"), [IpAddr::V4(NEW)]);
+
+        let adopted: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\nadopt: true").unwrap();
+        assert_eq!(dry_run_result(&fixture, &adopted).await, RunResult::Updated);
+    }
+
+    /// Answer a single plain text web lookup on the loopback address with `public`
+    fn serve_lookup(public: IpAddr) -> LookupProvider {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            _ = stream.read(&mut request).unwrap();
+            let body = public.to_string();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+        });
+        serde_yaml::from_str(&format!("url: {url}\nformat: plain")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn web_lookup_requeries_interface_after_bind_failure() {
+        let mut fixture = Fixture::new(MockApi::default());
+        // The interface now has the loopback address, replacing one that is on no interface
+        fixture.source = FakeSource { interfaces: vec!["eth0".into()], route: Some(Ipv4Addr::LOCALHOST.into()) };
+        fixture.interface.selection = AddressSelection::Route;
+        let stale = Ipv4Addr::new(192, 0, 2, 1);
+        let public = Ipv4Addr::new(198, 51, 100, 7);
+        let providers = [serve_lookup(public.into())];
+
+        let processor = fixture.processor(stale).with_lookup_providers(&providers, false);
+
+        assert_eq!(processor.get_web_ipv4().await.unwrap(), Some(public));
+    }
+
+    #[tokio::test]
+    async fn web_lookup_skips_family_missing_from_interface() {
+        let fixture = Fixture::new(MockApi::default());
+        // Nothing listens here, so any lookup that is attempted fails the test
+        let providers: [LookupProvider; 1] = [serde_yaml::from_str("url: http://127.0.0.1:9/\nformat: plain").unwrap()];
+        let aaaa: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: AAAA\nweb_lookup: true").unwrap();
+        let a: Record = serde_yaml::from_str("domain: home.example.com\nzone: example.com\ntype: A\nweb_lookup: true").unwrap();
+        let ipv6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+        let ipv4_only = fixture.processor(NEW).with_lookup_providers(&providers, false);
+        assert_eq!(ipv4_only.addresses_for(&aaaa).await.unwrap(), (None, None));
+        let ipv6_only = fixture.processor_with((None, Some(ipv6))).with_lookup_providers(&providers, false);
+        assert_eq!(ipv6_only.addresses_for(&a).await.unwrap(), (None, None));
+
+        let report = Mutex::new(Vec::new());
+        ipv4_only.with_report(&report).process_dry_run(&aaaa).await.unwrap();
+        let report = report.into_inner().unwrap();
+        assert_eq!((report[0].r#type.as_str(), report[0].result), ("AAAA", RunResult::Skipped));
+    }
+
+    #[tokio::test]
+    async fn creates_apex_record_under_zone_name() {
+        let fixture = Fixture::new(MockApi::default());
+        let record = a_record("'@'");
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+
+        assert_eq!(fixture.api().ips("example.com"), [IpAddr::V4(NEW)]);
+    }
+
+    #[tokio::test]
+    async fn updates_apex_record_under_zone_name() {
+        let fixture = Fixture::new(MockApi::default().with_record("example.com", IpAddr::V4(OLD), true));
+        let record = a_record("Example.com.");
+        let mut ui_ctx = UiRecordContext::new(ProgressBar::hidden());
+
+        fixture.processor(NEW).publish_record(ZONE_ID, &record, Some(NEW), None, &mut ui_ctx).await.unwrap();
+
+        assert_eq!(fixture.api().ips("example.com"), [IpAddr::V4(NEW)]);
+        assert_eq!(fixture.api().records.lock().unwrap().len(), 1);
+    }
+}
@@ -1,57 +1,784 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
-use std::fs::{self, File};
+use std::fs;
 use std::io;
+use std::net::{IpAddr, Ipv6Addr};
 use std::path::{Path, PathBuf};
 use std::ops::Not;
 use colored::Colorize;
 use directories::ProjectDirs;
 use miette::Diagnostic;
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::Error as JsonError;
 use thiserror::Error;
+use tracing::warn;
 
 use crate::{APPLICATION, ORGANIZATION, QUALIFIER};
+use crate::cloudflare::zone::guess_zone_from_domain;
 
-const CONFIG_FILE_NAMES: [&str; 2] = ["config.yml", "config.yaml"];
+const CONFIG_FILE_NAMES: [&str; 4] = ["config.yml", "config.yaml", "config.toml", "config.json"];
 
+/// Directory next to the main config whose files add interfaces and records to it
+pub const INCLUDE_DIR_NAME: &str = "config.d";
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// The language a config file is written in, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// YAML unless the file ends in `.toml` or `.json`
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => ConfigFormat::Toml,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConfigFormat::Yaml => "yaml",
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Json => "json",
+        }
+    }
+
+    /// The format of a config file, or None for files that aren't configs at all
+    fn of_file(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        ["yml", "yaml", "toml", "json"].contains(&ext.as_str()).then(|| Self::of(path))
+    }
+
+    /// Parse a config without validating it
+    pub fn parse<T: DeserializeOwned>(self, text: &str) -> Result<T, ConfigError> {
+        Ok(match self {
+            ConfigFormat::Yaml => serde_yaml::from_str(text)?,
+            ConfigFormat::Toml => toml::from_str(text)?,
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|source| ConfigError::JsonParse { source })?,
+        })
+    }
+
+    pub fn format(self, config: &Config) -> Result<String, ConfigError> {
+        Ok(match self {
+            ConfigFormat::Yaml => serde_yaml::to_string(config)?,
+            ConfigFormat::Toml => toml::to_string_pretty(config)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(config)? + "\n",
+        })
+    }
+}
+
+/// Name top-level records are processed under. Link names can't contain spaces, so it never
+/// clashes with a real interface.
+pub const WEB_LOOKUP_INTERFACE: &str = "web lookup";
+
+/// Placeholder in a record domain replaced with the name of the link it is published for
+pub const INTERFACE_PLACEHOLDER: &str = "{interface}";
+
+/// Record domain standing for the zone apex, as in zone files
+pub const APEX: &str = "@";
+
+/// Records processed at once when `parallel` isn't set
+pub const DEFAULT_PARALLEL: usize = 8;
+/// Highest allowed `parallel`, beyond which Cloudflare starts rate limiting
+pub const MAX_PARALLEL: usize = 32;
+
+/// The interface identifier half of an IPv6 address
+const IPV6_SUFFIX_MASK: u128 = u64::MAX as u128;
+
+/// Comments shown above each key in `cfdns template`, matched by key name
+const TEMPLATE_COMMENTS: &[(&str, &str)] = &[
+    ("cloudflare", "Cloudflare API access, the token can be overridden by the CFDNS_TOKEN environment variable"),
+    ("token", "API token with Zone => DNS:Edit permissions"),
+    ("token_file", "Read the API token from this file instead, e.g. a container or systemd secret"),
+    ("environment", "API to talk to: production (default) or custom"),
+    ("api_url", "Base URL of a Cloudflare-compatible API, used with the custom environment"),
+    ("timeout_secs", "Seconds before a request times out (default: 30 for the API, 5 for web lookups)"),
+    ("retry", "Retries for rate limited, server and network errors"),
+    ("max_attempts", "Total attempts per request, including the first (default: 3)"),
+    ("base_delay_ms", "Delay before the first retry in milliseconds, doubling each time (default: 500)"),
+    ("accounts", "Credentials for zones in other Cloudflare accounts, keyed by any name"),
+    ("zones", "Zones managed with this account's token instead of the default one"),
+    ("proxy", "Proxy for Cloudflare API requests, web lookups always connect directly"),
+    ("https", "Proxy URL for HTTPS requests"),
+    ("no_proxy", "Comma separated hosts that bypass the proxy"),
+    ("lookup_providers", "Services used for web lookups instead of Cloudflare's trace endpoint, tried in order until one answers"),
+    ("lookup_consensus", "Ask every lookup provider at once and only accept an address most of them agree on (default: false)"),
+    ("lookup_cache_secs", "Reuse a web lookup result for this many seconds while the interface address stays the same (default: look up every run)"),
+    ("defaults", "Settings for every record that doesn't set them itself: ttl, proxied, web_lookup and type"),
+    ("zone_defaults", "The same settings for the records of one zone, keyed by zone, taking precedence over defaults"),
+    ("url", "URL returning the caller's address"),
+    ("dns", "Ask a DNS resolver instead of a URL: cloudflare (whoami.cloudflare) or opendns (myip.opendns.com)"),
+    ("format", "How to read the response: trace (key=value lines with ip=, the default), plain (just the address) or json (an object with an ip field)"),
+    ("interfaces", "Network interfaces to read addresses from, keyed by interface name, permanent MAC address, a glob like wg* or /regex/ matching several, or default for the link carrying the default route"),
+    ("parallel", "Records processed at the same time, up to 32 (default: 8)"),
+    ("skip_unchanged_secs", "Skip the Cloudflare API for records published with the same address within this many seconds (default: always check)"),
+    ("records", "DNS records published with this interface's addresses, or at the top level, always with the public address from a web lookup"),
+    ("domain", "Fully qualified name of the record"),
+    ("zone", "Cloudflare zone containing the record (default: the registrable domain of the record)"),
+    ("zone_id", "ID of the zone from its Overview page, so the token doesn't need Zone:Read permission"),
+    ("type", "A (IPv4), AAAA (IPv6) or BOTH"),
+    ("web_lookup", "Publish the public address seen by Cloudflare instead of the interface address (default: false)"),
+    ("ipv6_suffix", "Publish the interface's /64 prefix with this interface identifier instead of its own IPv6 address"),
+    ("all_ipv6", "Publish every global IPv6 address of the interface as its own AAAA record instead of only the best one (default: false)"),
+    ("round_robin", "Keep one record per interface publishing this domain instead of letting the last one win (default: false)"),
+    ("delete_stale", "Delete the A or AAAA record when the interface has no address of that family (default: false)"),
+    ("adopt", "Take over an existing record that cfdns didn't create instead of refusing to change it (default: false)"),
+    ("comment", "Shown in the Cloudflare dashboard after cfdns' own managed-by=cfdns marker"),
+    ("tags", "Cloudflare record tags, which `cfdns update --tag` can select records by"),
+    ("ttl", "Record TTL in seconds, 1 for automatic or 60-86400 (default: keep the current TTL, automatic for new records)"),
+    ("proxied", "Serve the record through Cloudflare's proxy (default: keep the current setting, off for new records)"),
+    ("ipv4", "Set to false on IPv6-only interfaces to skip IPv4 entirely, publishing only the AAAA half of BOTH records (default: true)"),
+    ("ipv6", "Set to false on IPv4-only interfaces to skip IPv6 entirely, publishing only the A half of BOTH records (default: true)"),
+    ("address_preference", "Tie-breaker between equally preferred addresses: Standard (default) or Oldest"),
+    ("selection", "How addresses are chosen: score (default) ranks them, route uses the source the kernel picks to reach the internet (Linux only)"),
+    ("ipv6_address_policy", "Whether IPv6 privacy addresses are published: prefer_stable (default), prefer_temporary or require_stable"),
+    ("include_subnets", "Only consider addresses inside these subnets, for each address family listed (default: any)"),
+    ("exclude_subnets", "Never consider addresses inside these subnets, e.g. a ULA block"),
+    ("address_label", "Only use the IPv4 address with this label, e.g. eth0:dyn (Linux only)"),
+    ("auto_web_lookup", "Use a web lookup for records without web_lookup when the interface address isn't globally routable, or false to publish such addresses without a warning"),
+    ("allow_cgnat", "Publish web lookup IPv4 addresses even when the interface is behind carrier-grade or double NAT (default: false)"),
+    ("web_lookup_bind_device", "Also bind web lookups to the interface by name, for policy routing setups (default: false, needs CAP_NET_RAW)"),
+    ("log_syslog", "Send a syslog message whenever a record changes (default: false)"),
+    ("syslog", "Syslog settings used when log_syslog is enabled"),
+    ("facility", "user (default), daemon or local0-local7"),
+    ("severity", "emerg, alert, crit, err, warning, notice (default), info or debug"),
+];
+
+/// Like [`TEMPLATE_DISABLED`], for top-level keys whose name is also used further down
+const TEMPLATE_DISABLED_TOP_LEVEL: &[&str] = &["records"];
+
+/// Opt-in keys that are commented out in `cfdns template`, along with anything nested under them
+const TEMPLATE_DISABLED: &[&str] = &["token_file", "environment", "api_url", "timeout_secs", "retry", "accounts", "proxy", "lookup_providers", "lookup_consensus", "lookup_cache_secs", "defaults", "zone_defaults", "parallel", "skip_unchanged_secs", "zone_id", "ipv6_suffix", "all_ipv6", "round_robin", "delete_stale", "adopt", "comment", "tags", "ttl", "proxied", "ipv4", "ipv6", "address_preference", "selection", "ipv6_address_policy", "include_subnets", "exclude_subnets", "address_label", "auto_web_lookup", "allow_cgnat", "web_lookup_bind_device", "log_syslog", "syslog"];
+
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Config {
     pub cloudflare: Cloudflare,
+    /// Additional Cloudflare credentials for zones in other accounts, keyed by a name of your choosing
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub accounts: HashMap<String, Account>,
+    /// Proxy for Cloudflare API requests. Web lookups always connect directly.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<Proxy>,
+    /// Services used for web lookups, tried in order. Cloudflare's trace endpoint when empty.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lookup_providers: Vec<LookupProvider>,
+    /// Query every lookup provider at once and only accept an address a majority agree on
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<&bool>::not")]
+    pub lookup_consensus: bool,
+    /// Reuse the last web lookup result for an interface address for this many seconds
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lookup_cache_secs: Option<u64>,
+    /// Settings for every record that doesn't set them itself
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub defaults: RecordDefaults,
+    /// Settings for the records of one zone, keyed by zone, taking precedence over `defaults`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub zone_defaults: HashMap<String, RecordDefaults>,
+    #[serde(default)]
     pub interfaces: HashMap<String, Interface>,
+    /// Records that always publish the public address from a web lookup over the default route,
+    /// without reading any interface
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<Record>,
+    /// Records processed at the same time, between 1 and [`MAX_PARALLEL`]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel: Option<usize>,
+    /// Trust the last published state of a record for this many seconds instead of checking Cloudflare
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_unchanged_secs: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<&bool>::not")]
+    pub log_syslog: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syslog: Option<Syslog>,
     #[serde(skip)]
     path: PathBuf
 }
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Cloudflare {
+    /// Overridden by the CFDNS_TOKEN environment variable
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub token: String,
+    /// File to read the token from instead of storing it in the config
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub environment: ApiEnvironment,
+    /// Base URL of the API when using the custom environment
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+    /// Seconds before an API request is abandoned
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub retry: Retry,
+}
+
+/// How failed API requests are retried
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Retry {
+    /// Total attempts per request, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub base_delay_ms: u64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self { max_attempts: 3, base_delay_ms: 500 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Proxy {
+    /// URL of the proxy used for HTTPS requests
+    pub https: String,
+    /// Comma separated hosts to reach without the proxy
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Account {
+    #[serde(flatten)]
+    pub cloudflare: Cloudflare,
+    /// Zones managed with these credentials instead of the default ones
+    pub zones: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiEnvironment {
+    #[default]
+    Production,
+    /// A Cloudflare-compatible API at `api_url`, such as a staging or mock server
+    Custom,
+}
+
+/// A service that replies with the address a request came from, either over HTTP(S) at `url` or
+/// by asking a `dns` resolver
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LookupProvider {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsResolver>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub format: LookupFormat,
+    /// Seconds before giving up on this provider and trying the next one
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+impl Display for LookupProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.url, self.dns) {
+            (Some(url), _) => write!(f, "{url}"),
+            (None, Some(DnsResolver::Cloudflare)) => write!(f, "whoami.cloudflare"),
+            (None, Some(DnsResolver::OpenDns)) => write!(f, "myip.opendns.com"),
+            (None, None) => write!(f, "unset lookup provider"),
+        }
+    }
+}
+
+/// Resolvers that answer a special name with the address the query came from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsResolver {
+    /// `whoami.cloudflare` TXT in the CHAOS class, asked of 1.1.1.1 or 2606:4700:4700::1111
+    Cloudflare,
+    /// `myip.opendns.com` A or AAAA, asked of resolver1.opendns.com
+    OpenDns,
+}
+
+/// How to find the address in a lookup provider's response
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LookupFormat {
+    /// `key=value` lines including `ip=`, as served by `/cdn-cgi/trace`
+    #[default]
+    Trace,
+    /// Nothing but the address, like icanhazip.com or api64.ipify.org
+    Plain,
+    /// A JSON object with an `ip` field, like `https://api64.ipify.org?format=json`
+    Json,
+}
+
+/// Settings for change events sent to syslog when `log_syslog` is enabled
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct Syslog {
+    #[serde(default)]
+    pub facility: SyslogFacility,
+    #[serde(default)]
+    pub severity: SyslogSeverity,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    #[default]
+    User,
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogSeverity {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    #[default]
+    Notice,
+    Info,
+    Debug,
+}
+
+/// Record settings used by records that leave them out
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RecordDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<Ttl>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub web_lookup: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<TypeOptions>,
+}
+
+/// A file in [`INCLUDE_DIR_NAME`], e.g. written by a provisioning tool, whose interfaces and
+/// records are added to the main config
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFragment {
+    #[serde(default)]
+    interfaces: HashMap<String, Interface>,
+    #[serde(default)]
+    records: Vec<Record>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Interface {
     pub records: Vec<Record>,
+    /// Read and publish IPv4 addresses on this interface. Unset means true.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<bool>,
+    /// Read and publish IPv6 addresses on this interface. Unset means true.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<bool>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub address_preference: AddressPreference,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub selection: AddressSelection,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_default")]
+    pub ipv6_address_policy: Ipv6AddressPolicy,
+    /// Only addresses inside one of these are used. Only restricts the address families it lists.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include_subnets: Vec<Subnet>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude_subnets: Vec<Subnet>,
+    /// Only IPv4 addresses with this label are used, since IPv6 addresses don't have labels
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address_label: Option<String>,
+    /// Look up the public address when the interface address isn't globally routable, for records
+    /// without `web_lookup`. Unset warns about such addresses, false publishes them quietly.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_web_lookup: Option<bool>,
+    /// Publish web lookup addresses that can't accept inbound connections because of another layer of NAT
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<&bool>::not")]
+    pub allow_cgnat: bool,
+    /// Bind web lookups to this interface by name (SO_BINDTODEVICE) as well as by address
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<&bool>::not")]
+    pub web_lookup_bind_device: bool,
+}
+
+/// Parse an interface key written as a MAC address, like `52:54:00:12:34:56` or `52-54-00-12-34-56`
+pub fn parse_mac(key: &str) -> Option<[u8; 6]> {
+    let mut mac = [0; 6];
+    let mut octets = key.split([':', '-']);
+    for byte in &mut mac {
+        let octet = octets.next()?;
+        if octet.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(octet, 16).ok()?;
+    }
+    octets.next().is_none().then_some(mac)
+}
+
+/// Check that a wildcard in a record's domain is the whole leftmost label, as in
+/// `*.home.example.com`. Cloudflare only treats a leading `*` label as a wildcard.
+pub fn check_wildcard(domain: &str) -> Result<(), ConfigError> {
+    let rest = domain.strip_prefix("*.").unwrap_or(domain);
+    if rest.contains('*') || rest.trim_end_matches('.').is_empty() {
+        return Err(ConfigError::InvalidWildcard(domain.to_string()));
+    }
+    Ok(())
+}
+
+/// The ASCII form of a domain for the Cloudflare API, with each Unicode label converted to
+/// punycode. ASCII labels such as `*` or `{interface}` are kept as written.
+pub fn domain_to_ascii(domain: &str) -> Result<String, ConfigError> {
+    if domain.is_ascii() {
+        return Ok(domain.to_owned());
+    }
+    let labels = domain.split('.')
+        .map(|label| match label.is_ascii() {
+            true => Ok(label.to_owned()),
+            false => idna::domain_to_ascii(label).map_err(|_| ConfigError::InvalidDomain(domain.to_owned())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(labels.join("."))
+}
+
+/// A domain as Cloudflare spells record names, see [`Config::normalize_domains`]
+fn normalize_domain(domain: &str) -> Result<String, ConfigError> {
+    let domain: String = domain.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(domain_to_ascii(domain.trim_end_matches('.'))?.to_ascii_lowercase())
+}
+
+/// The Unicode form of a domain for output, with punycode labels decoded
+pub fn display_domain(domain: &str) -> Cow<'_, str> {
+    if !domain.to_ascii_lowercase().contains("xn--") {
+        return Cow::Borrowed(domain);
+    }
+    match idna::domain_to_unicode(domain) {
+        (unicode, Ok(())) => Cow::Owned(unicode),
+        _ => Cow::Borrowed(domain),
+    }
+}
+
+/// Whether `domain` is the zone apex, `@`, or a name below `zone`, ignoring case and trailing dots
+pub fn in_zone(domain: &str, zone: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    domain == APEX || domain == zone || domain.ends_with(&format!(".{zone}"))
+}
+
+/// Compile an interface key that matches several links: `/regex/`, or a glob using `*` and `?`.
+/// Plain interface names aren't patterns and return None.
+pub fn interface_pattern(key: &str) -> Result<Option<Regex>, regex::Error> {
+    let pattern = if let Some(regex) = key.strip_prefix('/').and_then(|k| k.strip_suffix('/')) {
+        regex.to_owned()
+    } else if key.contains(['*', '?']) {
+        key.chars()
+            .map(|c| match c {
+                '*' => ".*".to_owned(),
+                '?' => ".".to_owned(),
+                c => regex::escape(&c.to_string()),
+            })
+            .collect()
+    } else {
+        return Ok(None);
+    };
+    // Anchored so `wg*` doesn't also match `swg0`
+    Regex::new(&format!("^(?:{pattern})$")).map(Some)
+}
+
+impl Interface {
+    pub fn ipv4_enabled(&self) -> bool {
+        self.ipv4 != Some(false)
+    }
+
+    pub fn ipv6_enabled(&self) -> bool {
+        self.ipv6 != Some(false)
+    }
+
+    /// Whether `include_subnets` and `exclude_subnets` let an address be selected
+    pub fn allows_address(&self, ip: &IpAddr) -> bool {
+        let mut includes = self.include_subnets.iter().filter(|s| s.address.is_ipv4() == ip.is_ipv4()).peekable();
+        let included = includes.peek().is_none() || includes.any(|s| s.contains(ip));
+        included && !self.exclude_subnets.iter().any(|s| s.contains(ip))
+    }
+}
+
+/// An address prefix in CIDR notation, such as `fd00::/8`. A bare address is a single-address subnet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Subnet {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = InvalidSubnet;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let (address, prefix_len) = match text.split_once('/') {
+            Some((address, len)) => (address, Some(len)),
+            None => (text.as_str(), None),
+        };
+        let address: IpAddr = address.trim().parse().map_err(|_| InvalidSubnet(text.clone()))?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.trim().parse().ok().filter(|len| *len <= max).ok_or_else(|| InvalidSubnet(text.clone()))?,
+            None => max,
+        };
+        Ok(Subnet { address, prefix_len })
+    }
+}
+
+impl From<Subnet> for String {
+    fn from(subnet: Subnet) -> Self {
+        format!("{}/{}", subnet.address, subnet.prefix_len)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid subnet `{0}`: use CIDR notation such as 192.168.0.0/16 or fd00::/8")]
+pub struct InvalidSubnet(String);
+
+/// How to choose between addresses that are otherwise equally preferred
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddressPreference {
+    /// Use the first address reported by the kernel
+    #[default]
+    Standard,
+    /// Use the longest-lived address, reducing churn when SLAAC adds new addresses
+    Oldest,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How an interface's address is chosen
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressSelection {
+    /// Rank every address on the interface and take the best one
+    #[default]
+    Score,
+    /// Ask the kernel which source address it would use to reach the internet through the interface,
+    /// scoring addresses only for a family without a route
+    Route,
+}
+
+/// Whether temporary IPv6 addresses from SLAAC privacy extensions may be published. They rotate
+/// every day or so, which breaks AAAA records with long TTLs.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ipv6AddressPolicy {
+    /// Use a temporary address only when there is no stable one
+    #[default]
+    PreferStable,
+    /// Use a temporary address when there is one, e.g. for outbound-only hosts
+    PreferTemporary,
+    /// Never use a temporary address
+    RequireStable,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Record {
     pub domain: String,
+    /// Inferred from the domain at load time when left out
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub zone: String,
-    pub r#type: TypeOptions,
+    /// ID of the zone, so tokens scoped to one zone don't need Zone:Read to look it up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_id: Option<String>,
+    /// Taken from `defaults` when left out, see [`Record::record_type`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<TypeOptions>,
+    /// Taken from `defaults` when left out, see [`Record::uses_web_lookup`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub web_lookup: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<Ttl>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    /// Interface identifier combined with the /64 prefix of the interface's IPv6 address, for
+    /// hosts with a static suffix in a delegated prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6_suffix: Option<Ipv6Addr>,
+    /// Publish every usable global IPv6 address of the interface as its own AAAA record, adding and
+    /// deleting records to match, instead of only the best address
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<&bool>::not")]
+    pub all_ipv6: bool,
+    /// Share the record with every other interface publishing it, keeping one record per
+    /// interface address instead of letting the last interface processed win
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<&bool>::not")]
+    pub round_robin: bool,
+    /// Delete the record of an address family when the interface has no address of that family,
+    /// instead of leaving it pointing at the last one
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<&bool>::not")]
+    pub delete_stale: bool,
+    /// Take over an existing record cfdns didn't create instead of refusing to change it
     #[serde(default)]
     #[serde(skip_serializing_if = "<&bool>::not")]
-    pub web_lookup: bool
+    pub adopt: bool,
+    /// Written to the Cloudflare record's comment after cfdns' own marker, such as which machine
+    /// publishes it. `{interface}` is replaced like in the domain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Cloudflare record tags such as `env:home`, replacing the record's tags on every update
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Record {
+    /// The record types to publish. Validation guarantees a loaded record has a type.
+    pub fn record_type(&self) -> TypeOptions {
+        self.r#type.unwrap_or(TypeOptions::Both)
+    }
+
+    pub fn uses_web_lookup(&self) -> bool {
+        self.web_lookup.unwrap_or(false)
+    }
+
+    /// Fill in the settings the record leaves out
+    fn apply_defaults(&mut self, defaults: &RecordDefaults) {
+        self.ttl = self.ttl.or(defaults.ttl);
+        self.proxied = self.proxied.or(defaults.proxied);
+        self.web_lookup = self.web_lookup.or(defaults.web_lookup);
+        self.r#type = self.r#type.or(defaults.r#type);
+    }
+
+    /// The AAAA value for an address of the interface, replacing its host part with `ipv6_suffix`
+    pub fn ipv6_for(&self, address: Ipv6Addr) -> Ipv6Addr {
+        match self.ipv6_suffix {
+            Some(suffix) => {
+                let prefix = u128::from(address) & !IPV6_SUFFIX_MASK;
+                Ipv6Addr::from(prefix | (u128::from(suffix) & IPV6_SUFFIX_MASK))
+            }
+            None => address,
+        }
+    }
+}
+
+/// A record TTL in seconds, where 1 lets Cloudflare choose automatically
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct Ttl(u32);
+
+impl Ttl {
+    pub const AUTO: Ttl = Ttl(1);
+    const MIN: u32 = 60;
+    const MAX: u32 = 86400;
+
+    pub fn seconds(self) -> u32 {
+        self.0
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+impl TryFrom<u32> for Ttl {
+    type Error = InvalidTtl;
+
+    fn try_from(seconds: u32) -> Result<Self, Self::Error> {
+        if seconds == Self::AUTO.0 || (Self::MIN..=Self::MAX).contains(&seconds) {
+            Ok(Ttl(seconds))
+        } else {
+            Err(InvalidTtl(seconds))
+        }
+    }
+}
+
+impl From<Ttl> for u32 {
+    fn from(ttl: Ttl) -> Self {
+        ttl.0
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid TTL {0}: use 1 for automatic or a value between 60 and 86400 seconds")]
+pub struct InvalidTtl(u32);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum TypeOptions {
     A,
     AAAA,
     Both
 }
+impl TypeOptions {
+    pub fn includes_ipv4(&self) -> bool {
+        matches!(self, Self::A | Self::Both)
+    }
+
+    pub fn includes_ipv6(&self) -> bool {
+        matches!(self, Self::AAAA | Self::Both)
+    }
+
+    /// The part of this type left when only the allowed families can be published, if any
+    pub fn restrict(self, ipv4: bool, ipv6: bool) -> Option<Self> {
+        match (self.includes_ipv4() && ipv4, self.includes_ipv6() && ipv6) {
+            (true, true) => Some(Self::Both),
+            (true, false) => Some(Self::A),
+            (false, true) => Some(Self::AAAA),
+            (false, false) => None,
+        }
+    }
+}
+
 impl Display for TypeOptions {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -64,8 +791,24 @@ impl Display for TypeOptions {
 
 impl Config {
     pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
-        let path = path.as_ref();
-        Config::load_from_path(path).map_err(|e| match e {
+        Config::load_with(path.as_ref(), false)
+    }
+
+    pub fn load_default() -> Result<Self, ConfigError> {
+        Config::load_default_with(false)
+    }
+
+    /// Load only the main config file as written, without the files in [`INCLUDE_DIR_NAME`],
+    /// inferred zones or defaults, so it can be edited and saved back the same way
+    pub fn load_main(custom_path: Option<&Path>) -> Result<Self, ConfigError> {
+        match custom_path {
+            Some(path) => Config::load_with(path, true),
+            None => Config::load_default_with(true),
+        }
+    }
+
+    fn load_with(path: &Path, main_only: bool) -> Result<Self, ConfigError> {
+        Config::load_from_path(path, main_only).map_err(|e| match e {
             // Reclassify not found as an explicit Missing for user specified paths
             ConfigError::File { source, .. } if source.kind() == io::ErrorKind::NotFound => {
                 ConfigError::Missing { path: path.to_path_buf() }
@@ -74,10 +817,10 @@ impl Config {
         })
     }
 
-    pub fn load_default() -> Result<Self, ConfigError> {
+    fn load_default_with(main_only: bool) -> Result<Self, ConfigError> {
         let resolved_path = resolve_default_path()?;
 
-        match Config::load_from_path(&resolved_path) {
+        match Config::load_from_path(&resolved_path, main_only) {
             Ok(config) => Ok(config),
             Err(ConfigError::File { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
                 // “NotFound” means no config file in any default location
@@ -87,20 +830,282 @@ impl Config {
         }
     }
 
-    fn load_from_path(path: &Path) -> Result<Self, ConfigError> {
-        let file = File::open(path)
+    fn load_from_path(path: &Path, main_only: bool) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path)
             .map_err(|source| ConfigError::File { path: path.to_path_buf(), source })?;
 
-        let mut config: Config = serde_yaml::from_reader(file)?;
+        let mut config: Config = ConfigFormat::of(path).parse(&text)?;
         config.path = path.to_path_buf();
+        if main_only {
+            config.clone().resolve()?;
+            return Ok(config);
+        }
+        config.include_fragments()?;
+        config.resolve()?;
         Ok(config)
     }
 
+    /// Turn a config as written into the one records are processed with: domains normalized,
+    /// zones inferred and defaults filled in, then validated
+    pub fn resolve(&mut self) -> Result<(), ConfigError> {
+        self.normalize_domains()?;
+        self.infer_zones()?;
+        let zone_defaults = self.zone_defaults.iter()
+            .map(|(zone, defaults)| Ok((normalize_domain(zone)?, defaults)))
+            .collect::<Result<HashMap<_, _>, ConfigError>>()?;
+        // A record's own settings win over its zone's, which win over the global ones
+        for record in self.interfaces.values_mut().flat_map(|i| &mut i.records).chain(&mut self.records) {
+            if let Some(defaults) = zone_defaults.get(&record.zone) {
+                record.apply_defaults(defaults);
+            }
+            record.apply_defaults(&self.defaults);
+        }
+        for zone in zone_defaults.keys() {
+            if !self.interfaces.values().flat_map(|i| &i.records).chain(&self.records).any(|r| r.zone == *zone) {
+                warn!(zone, "`zone_defaults` sets a zone no record is in");
+            }
+        }
+        self.validate()
+    }
+
+    /// Add the interfaces and records of every config file in [`INCLUDE_DIR_NAME`], in file name
+    /// order. Records for an interface that is already configured are appended to it, keeping
+    /// the settings of the file that configured it first.
+    fn include_fragments(&mut self) -> Result<(), ConfigError> {
+        let dir = self.path.with_file_name(INCLUDE_DIR_NAME);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(source) => return Err(ConfigError::File { path: dir, source }),
+        };
+        let mut paths = entries
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|source| ConfigError::File { path: dir.clone(), source })?;
+        paths.sort();
+
+        for path in paths {
+            let Some(format) = ConfigFormat::of_file(&path) else { continue };
+            let text = fs::read_to_string(&path)
+                .map_err(|source| ConfigError::File { path: path.clone(), source })?;
+            let fragment: ConfigFragment = format.parse(&text)
+                .map_err(|source| ConfigError::Fragment { path: path.clone(), source: Box::new(source) })?;
+            for (name, interface) in fragment.interfaces {
+                match self.interfaces.get_mut(&name) {
+                    Some(existing) => existing.records.extend(interface.records),
+                    None => _ = self.interfaces.insert(name, interface),
+                }
+            }
+            self.records.extend(fragment.records);
+        }
+        Ok(())
+    }
+
+    /// The interface that configures a link: the one with its exact name, then one keyed by its
+    /// permanent MAC address, or else the first pattern in sorted order that matches its name
+    pub fn interface_key_for(&self, link: &str, mac: Option<&[u8; 6]>) -> Option<&String> {
+        if let Some((key, _)) = self.interfaces.get_key_value(link) {
+            return Some(key);
+        }
+        if let Some(mac) = mac
+            && let Some(key) = self.interfaces.keys().find(|key| parse_mac(key).as_ref() == Some(mac))
+        {
+            return Some(key);
+        }
+        let mut patterns: Vec<_> = self.interfaces.keys()
+            .filter_map(|key| Some((key, interface_pattern(key).ok()??)))
+            .collect();
+        patterns.sort_by(|a, b| a.0.cmp(b.0));
+        patterns.into_iter().find(|(_, pattern)| pattern.is_match(link)).map(|(key, _)| key)
+    }
+
+    /// The interfaces with each MAC address or pattern replaced by a copy for every link it
+    /// configures, `{interface}` in record domains filled in with the link name and `@` with the
+    /// zone. Top-level records are added under [`WEB_LOOKUP_INTERFACE`].
+    pub fn expanded_interfaces(&self, links: &[String], macs: &HashMap<String, [u8; 6]>) -> HashMap<String, Interface> {
+        let mut expanded: HashMap<String, Interface> = self.interfaces.iter()
+            .filter(|(key, _)| parse_mac(key).is_none() && !matches!(interface_pattern(key), Ok(Some(_))))
+            .map(|(key, interface)| (key.clone(), interface.clone()))
+            .collect();
+        for link in links {
+            if let Some(key) = self.interface_key_for(link, macs.get(link))
+                && key != link
+            {
+                expanded.insert(link.clone(), self.interfaces[key].clone());
+            }
+        }
+
+        if !self.records.is_empty() {
+            let records = self.records.iter().cloned().map(|record| Record { web_lookup: Some(true), ..record }).collect();
+            expanded.insert(WEB_LOOKUP_INTERFACE.to_owned(), Interface { records, ..Default::default() });
+        }
+
+        for (name, interface) in &mut expanded {
+            let (ipv4, ipv6) = (interface.ipv4_enabled(), interface.ipv6_enabled());
+            for record in &mut interface.records {
+                if record.domain == APEX {
+                    record.domain = record.zone.trim_end_matches('.').to_owned();
+                }
+                record.domain = record.domain.replace(INTERFACE_PLACEHOLDER, name);
+                record.comment = record.comment.as_ref().map(|c| c.replace(INTERFACE_PLACEHOLDER, name));
+                // Validation guarantees every record keeps at least one family
+                let r#type = record.record_type();
+                record.r#type = Some(r#type.restrict(ipv4, ipv6).unwrap_or(r#type));
+            }
+        }
+        expanded
+    }
+
+    /// Write record domains and zones the way Cloudflare returns record names: whitespace
+    /// removed, no trailing dot, lowercase, and internationalized names in punycode
+    fn normalize_domains(&mut self) -> Result<(), ConfigError> {
+        let mut spellings: HashMap<String, String> = HashMap::new();
+        for record in self.interfaces.values_mut().flat_map(|i| &mut i.records).chain(&mut self.records) {
+            let domain = normalize_domain(&record.domain)?;
+            match spellings.get(&domain) {
+                Some(other) if *other != record.domain => {
+                    warn!(domain, "`{}` and `{other}` are the same name spelled differently", record.domain);
+                }
+                Some(_) => {}
+                None => _ = spellings.insert(domain.clone(), record.domain.clone()),
+            }
+            record.domain = domain;
+            record.zone = normalize_domain(&record.zone)?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the zone of records that leave it out with the registrable domain of their name,
+    /// like `example.co.uk` for `nas.home.example.co.uk`
+    fn infer_zones(&mut self) -> Result<(), ConfigError> {
+        let records = self.interfaces.values_mut().flat_map(|i| &mut i.records).chain(&mut self.records);
+        for record in records.filter(|r| r.zone.is_empty()) {
+            // Any link name works, it is below the zone either way
+            let domain = record.domain.replace(INTERFACE_PLACEHOLDER, "link");
+            record.zone = guess_zone_from_domain(&domain)
+                .ok_or_else(|| ConfigError::UnknownZone(record.domain.clone()))?
+                .to_owned();
+        }
+        Ok(())
+    }
+
+    /// The `zone_id` configured for a zone by any of its records
+    pub fn zone_id(&self, zone: &str) -> Option<&str> {
+        self.interfaces.values()
+            .flat_map(|i| &i.records)
+            .chain(&self.records)
+            .find(|r| r.zone == zone && r.zone_id.is_some())
+            .and_then(|r| r.zone_id.as_deref())
+    }
+
+    /// Check constraints between fields that serde can't express
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let credentials = std::iter::once(&self.cloudflare).chain(self.accounts.values().map(|a| &a.cloudflare));
+        for cloudflare in credentials {
+            if !cloudflare.token.is_empty() && cloudflare.token_file.is_some() {
+                return Err(ConfigError::ConflictingToken);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for zone in self.accounts.values().flat_map(|a| &a.zones) {
+            if !seen.insert(zone) {
+                return Err(ConfigError::DuplicateAccountZone(zone.clone()));
+            }
+        }
+
+        for provider in &self.lookup_providers {
+            match (&provider.url, provider.dns) {
+                (Some(_), Some(_)) | (None, None) => return Err(ConfigError::LookupProviderKind),
+                (Some(url), None) => {
+                    if let Err(source) = url::Url::parse(url) {
+                        return Err(ConfigError::InvalidLookupUrl { url: url.clone(), source });
+                    }
+                }
+                (None, Some(_)) => {}
+            }
+        }
+        if self.lookup_consensus && self.lookup_providers.len() < 2 {
+            return Err(ConfigError::TooFewConsensusProviders(self.lookup_providers.len()));
+        }
+
+        for key in self.interfaces.keys() {
+            if let Err(source) = interface_pattern(key) {
+                return Err(ConfigError::InvalidInterfacePattern { pattern: key.clone(), source });
+            }
+        }
+
+        if let Some(record) = self.interfaces.values().flat_map(|i| &i.records).chain(&self.records).find(|r| r.r#type.is_none()) {
+            return Err(ConfigError::MissingType(record.domain.clone()));
+        }
+
+        for (name, interface) in &self.interfaces {
+            if !interface.ipv4_enabled() && !interface.ipv6_enabled() {
+                return Err(ConfigError::NoAddressFamilies(name.clone()));
+            }
+            for record in &interface.records {
+                if record.record_type().restrict(interface.ipv4_enabled(), interface.ipv6_enabled()).is_none() {
+                    return Err(ConfigError::DisabledAddressFamily {
+                        domain: record.domain.clone(),
+                        r#type: record.record_type(),
+                        interface: name.clone(),
+                    });
+                }
+            }
+        }
+
+        for record in self.interfaces.values().flat_map(|i| &i.records).chain(&self.records) {
+            check_wildcard(&record.domain)?;
+            if let Some(id) = &record.zone_id
+                && self.zone_id(&record.zone).is_some_and(|other| other != id)
+            {
+                return Err(ConfigError::ConflictingZoneId(record.zone.clone()));
+            }
+            if !in_zone(&record.domain, &record.zone) {
+                return Err(ConfigError::DomainOutsideZone { domain: record.domain.clone(), zone: record.zone.clone() });
+            }
+            if record.all_ipv6 && !record.record_type().includes_ipv6() {
+                return Err(ConfigError::AllIpv6WithoutAaaa(record.domain.clone()));
+            }
+            // Top-level records always use a web lookup
+            let web_lookup = record.uses_web_lookup() || self.records.iter().any(|r| std::ptr::eq(r, record));
+            if record.all_ipv6 && web_lookup {
+                return Err(ConfigError::AllIpv6WithWebLookup(record.domain.clone()));
+            }
+            let shared = self.interfaces.values().flat_map(|i| &i.records).chain(&self.records)
+                .filter(|r| r.zone == record.zone && r.domain == record.domain);
+            if shared.clone().any(|r| r.round_robin) && !shared.clone().all(|r| r.round_robin) {
+                return Err(ConfigError::MixedRoundRobin(record.domain.clone()));
+            }
+            if let Some(suffix) = record.ipv6_suffix
+                && u128::from(suffix) & !IPV6_SUFFIX_MASK != 0
+            {
+                return Err(ConfigError::InvalidIpv6Suffix { domain: record.domain.clone(), suffix });
+            }
+        }
+
+        if let Some(parallel) = self.parallel
+            && !(1..=MAX_PARALLEL).contains(&parallel)
+        {
+            return Err(ConfigError::InvalidParallel(parallel));
+        }
+        Ok(())
+    }
+
+    /// How many records to process at the same time
+    pub fn parallel(&self) -> usize {
+        self.parallel.unwrap_or(DEFAULT_PARALLEL)
+    }
+
      /// Create a new, empty config at a specific path.
     pub fn new_at_path(path: impl AsRef<Path>) -> Self {
-        let mut new = Self::default();
-        new.path = path.as_ref().to_path_buf();
-        new
+        Self::default().with_path(path)
+    }
+
+    /// Save this config to `path` instead of the file it was loaded from
+    pub fn with_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = path.as_ref().to_path_buf();
+        self
     }
 
     /// Create a new empty config using the default config path.
@@ -109,11 +1114,15 @@ impl Config {
         Ok(Self::new_at_path(path))
     }
 
+    /// Write the config in the format of its file extension
     pub fn save(&self) -> Result<(), ConfigError> {
-        let file = File::create(&self.path)
-            .map_err(|source| ConfigError::File { path: self.path.clone(), source })?;
-        serde_yaml::to_writer(file, self)?;
-        Ok(())
+        let text = self.format().format(self)?;
+        fs::write(&self.path, text)
+            .map_err(|source| ConfigError::File { path: self.path.clone(), source })
+    }
+
+    pub fn format(&self) -> ConfigFormat {
+        ConfigFormat::of(&self.path)
     }
 
     pub fn path(&self) -> &Path {
@@ -131,16 +1140,168 @@ impl Config {
         for (iface_name, iface) in &self.interfaces {
             println!("{} {}", "DNS Records for".bold(), iface_name.bold().white());
             for (index, record) in iface.records.iter().enumerate() {
-                let record_type = match record.r#type {
+                let record_type = match record.record_type() {
                     TypeOptions::A => "A".red(),
                     TypeOptions::AAAA => "AAAA".green(),
                     TypeOptions::Both => "A / AAAA".yellow(),
                 };
-                println!("      {}. {} {}", index + 1, record.domain, record_type);
-                println!("          Zone: {}  |  Web Lookup: {}", record.zone, if record.web_lookup { "Enabled" } else { "Disabled"} );
+                println!("      {}. {} {}", index + 1, display_domain(&record.domain), record_type);
+                println!("          Zone: {}  |  Web Lookup: {}", display_domain(&record.zone), if record.uses_web_lookup() { "Enabled" } else { "Disabled"} );
             }
         }
+        if !self.records.is_empty() {
+            println!("{}", "DNS Records from Web Lookup".bold());
+            for (index, record) in self.records.iter().enumerate() {
+                let record_type = match record.record_type() {
+                    TypeOptions::A => "A".red(),
+                    TypeOptions::AAAA => "AAAA".green(),
+                    TypeOptions::Both => "A / AAAA".yellow(),
+                };
+                println!("      {}. {} {}", index + 1, display_domain(&record.domain), record_type);
+                println!("          Zone: {}", display_domain(&record.zone));
+            }
+        }
+    }
+    /// An example config with every available field, annotated with comments.
+    /// The example is built without `..Default::default()` so new fields can't be left out.
+    pub fn template() -> Result<String, ConfigError> {
+        let example = Config {
+            cloudflare: Cloudflare {
+                token: "YOUR_API_TOKEN".to_string(),
+                token_file: Some(PathBuf::from("/run/secrets/cf_token")),
+                environment: ApiEnvironment::Custom,
+                api_url: Some("https://api.cloudflare.com/client/v4/".to_string()),
+                timeout_secs: Some(10),
+                retry: Retry { max_attempts: 5, base_delay_ms: 1000 },
+            },
+            accounts: HashMap::from([(
+                "work".to_string(),
+                Account {
+                    cloudflare: Cloudflare {
+                        token: "OTHER_API_TOKEN".to_string(),
+                        token_file: None,
+                        environment: ApiEnvironment::Production,
+                        api_url: None,
+                        timeout_secs: None,
+                        retry: Retry::default(),
+                    },
+                    zones: vec!["example.org".to_string()],
+                },
+            )]),
+            proxy: Some(Proxy {
+                https: "http://proxy.example.com:3128".to_string(),
+                no_proxy: Some("localhost,127.0.0.1".to_string()),
+            }),
+            lookup_providers: vec![
+                LookupProvider {
+                    url: Some("https://api64.ipify.org".to_string()),
+                    dns: None,
+                    format: LookupFormat::Plain,
+                    timeout_secs: Some(3),
+                },
+                LookupProvider {
+                    url: None,
+                    dns: Some(DnsResolver::Cloudflare),
+                    format: LookupFormat::Trace,
+                    timeout_secs: None,
+                },
+            ],
+            lookup_consensus: true,
+            lookup_cache_secs: Some(300),
+            defaults: RecordDefaults {
+                ttl: Some(Ttl::AUTO),
+                proxied: Some(false),
+                web_lookup: Some(true),
+                r#type: Some(TypeOptions::A),
+            },
+            zone_defaults: HashMap::from([(
+                "example.org".to_string(),
+                RecordDefaults { proxied: Some(true), ..Default::default() },
+            )]),
+            interfaces: HashMap::from([(
+                "eth0".to_string(),
+                Interface {
+                    records: vec![Record {
+                        domain: "home.example.com".to_string(),
+                        zone: "example.com".to_string(),
+                        zone_id: Some("023e105f4ecef8ad9ca31a8372d0c353".to_string()),
+                        r#type: Some(TypeOptions::Both),
+                        web_lookup: Some(true),
+                        ttl: Some(Ttl::AUTO),
+                        proxied: Some(true),
+                        ipv6_suffix: Some(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 1, 2)),
+                        all_ipv6: true,
+                        round_robin: true,
+                        delete_stale: true,
+                        adopt: true,
+                        comment: Some("nas, {interface}".to_string()),
+                        tags: vec!["env:home".to_string(), "host:nas".to_string()],
+                    }],
+                    ipv4: Some(true),
+                    ipv6: Some(true),
+                    address_preference: AddressPreference::Oldest,
+                    selection: AddressSelection::Route,
+                    ipv6_address_policy: Ipv6AddressPolicy::RequireStable,
+                    include_subnets: vec![Subnet::try_from("2001:db8::/32".to_string()).unwrap()],
+                    exclude_subnets: vec![Subnet::try_from("fd00::/8".to_string()).unwrap()],
+                    address_label: Some("eth0:dyn".to_string()),
+                    auto_web_lookup: Some(true),
+                    allow_cgnat: true,
+                    web_lookup_bind_device: true,
+                },
+            )]),
+            records: vec![Record {
+                domain: "public.example.com".to_string(),
+                zone: "example.com".to_string(),
+                zone_id: None,
+                r#type: Some(TypeOptions::A),
+                web_lookup: None,
+                ttl: None,
+                proxied: None,
+                ipv6_suffix: None,
+                all_ipv6: false,
+                round_robin: false,
+                delete_stale: false,
+                adopt: false,
+                comment: None,
+                tags: Vec::new(),
+            }],
+            parallel: Some(DEFAULT_PARALLEL),
+            skip_unchanged_secs: Some(3600),
+            log_syslog: true,
+            syslog: Some(Syslog {
+                facility: SyslogFacility::User,
+                severity: SyslogSeverity::Notice,
+            }),
+            path: PathBuf::new(),
+        };
+        let yaml = serde_yaml::to_string(&example)?;
+
+        let mut out = String::new();
+        let mut disabled_indent: Option<usize> = None;
+        for line in yaml.lines() {
+            let trimmed = line.trim_start();
+            let indent = line.len() - trimmed.len();
+            let key = trimmed.trim_start_matches("- ").split(':').next().unwrap_or_default();
+
+            // Sequences are written at the same indent as their key, so list items stay disabled
+            if disabled_indent.is_some_and(|d| indent < d || (indent == d && !trimmed.starts_with("- "))) {
+                disabled_indent = None;
+            }
+            let top_level_disabled = indent == 0 && TEMPLATE_DISABLED_TOP_LEVEL.contains(&key);
+            if disabled_indent.is_none() && (TEMPLATE_DISABLED.contains(&key) || top_level_disabled) {
+                disabled_indent = Some(indent);
+            }
+            let prefix = if disabled_indent.is_some() { "# " } else { "" };
+
+            if let Some((_, comment)) = TEMPLATE_COMMENTS.iter().find(|(k, _)| *k == key) {
+                out.push_str(&format!("{:indent$}# {comment}\n", ""));
+            }
+            out.push_str(&format!("{:indent$}{prefix}{trimmed}\n", ""));
+        }
+        Ok(out)
     }
+
     pub fn print_json(&self) -> Result<(), ConfigError> {
         let pretty_json = serde_json::to_string_pretty(self)?;
         println!("{pretty_json}");
@@ -149,6 +1310,10 @@ impl Config {
 }
 
 
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
 pub fn ensure_config_dir() -> Result<PathBuf, ConfigError> {
     let base = ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
         .ok_or(ConfigError::HomeDirNotFound)?;
@@ -186,6 +1351,17 @@ pub enum ConfigError {
     #[error("failed to parse configuration")]
     #[diagnostic(help("check your config for any syntax errors"))]
     Yaml { #[from] source: serde_yaml::Error },
+    #[error("failed to parse configuration")]
+    #[diagnostic(help("check your config for any syntax errors"))]
+    Toml { #[from] source: toml::de::Error },
+    #[error("failed to parse configuration")]
+    #[diagnostic(help("check your config for any syntax errors"))]
+    JsonParse { #[source] source: JsonError },
+    #[error("failed to parse {}", path.display())]
+    #[diagnostic(help("files in config.d can only contain `interfaces` and `records`"))]
+    Fragment { path: PathBuf, #[source] source: Box<ConfigError> },
+    #[error("failed to format configuration as TOML")]
+    TomlFormat { #[from] source: toml::ser::Error },
     #[error("failed to format configuration as JSON")]
     #[diagnostic(help("check your config for any syntax errors"))]
     Json { #[from] source: JsonError },
@@ -194,4 +1370,61 @@ pub enum ConfigError {
     NotFound { path: PathBuf },
     #[error("configuration file not found at {path}")]
     Missing { path: PathBuf },
+    #[error("both `token` and `token_file` are set for the same credentials")]
+    #[diagnostic(help("remove one of them from your config"))]
+    ConflictingToken,
+    #[error("zone `{0}` is assigned to more than one account")]
+    #[diagnostic(help("each zone can only be listed under one entry in `accounts`"))]
+    DuplicateAccountZone(String),
+    #[error("`parallel` is {0}, but must be between 1 and {MAX_PARALLEL}")]
+    #[diagnostic(help("more records at once only trips Cloudflare's rate limits, which slows updates down"))]
+    InvalidParallel(usize),
+    #[error("lookup provider URL `{url}` is not valid")]
+    #[diagnostic(help("use a full URL such as https://api64.ipify.org"))]
+    InvalidLookupUrl { url: String, #[source] source: url::ParseError },
+    #[error("{0} sets `all_ipv6`, but isn't an AAAA or BOTH record")]
+    #[diagnostic(help("change the record type to AAAA or BOTH, or remove `all_ipv6`"))]
+    AllIpv6WithoutAaaa(String),
+    #[error("{0} sets both `all_ipv6` and `web_lookup`")]
+    #[diagnostic(help("a web lookup only finds one public address, so `all_ipv6` needs the interface's own addresses"))]
+    AllIpv6WithWebLookup(String),
+    #[error("{0} sets `round_robin` on some interfaces but not others")]
+    #[diagnostic(help("set `round_robin: true` on every record for the domain, or on none of them"))]
+    MixedRoundRobin(String),
+    #[error("{domain} is not in zone `{zone}`")]
+    #[diagnostic(help("the domain must be the zone itself or end with `.{zone}`; check for a typo in either"))]
+    DomainOutsideZone { domain: String, zone: String },
+    #[error("records in zone `{0}` set different `zone_id`s")]
+    #[diagnostic(help("a zone has one ID, shown on its Overview page in the Cloudflare dashboard"))]
+    ConflictingZoneId(String),
+    #[error("{0} has no `type`")]
+    #[diagnostic(help("set `type` to A, AAAA or BOTH on the record, or for every record in `defaults`"))]
+    MissingType(String),
+    #[error("`{0}` is not a valid internationalized domain name")]
+    #[diagnostic(help("check the name for characters that aren't allowed in hostnames"))]
+    InvalidDomain(String),
+    #[error("could not tell which zone {0} is in")]
+    #[diagnostic(help("add `zone:` to the record, e.g. `zone: example.com`"))]
+    UnknownZone(String),
+    #[error("`{0}` uses `*` somewhere other than a leading wildcard label")]
+    #[diagnostic(help("a wildcard record must start with `*.`, like *.home.example.com"))]
+    InvalidWildcard(String),
+    #[error("interface `{0}` has both `ipv4` and `ipv6` disabled")]
+    #[diagnostic(help("enable at least one address family, or remove the interface"))]
+    NoAddressFamilies(String),
+    #[error("{domain} is an {type} record, but interface `{interface}` disables that address family")]
+    #[diagnostic(help("change the record type, or move the record to an interface with that family enabled"))]
+    DisabledAddressFamily { domain: String, r#type: TypeOptions, interface: String },
+    #[error("interface pattern `{pattern}` is not a valid regular expression")]
+    #[diagnostic(help("wrap a regular expression in slashes, like /wg[0-9]+/, or use a glob such as wg*"))]
+    InvalidInterfacePattern { pattern: String, #[source] source: regex::Error },
+    #[error("`ipv6_suffix` {suffix} of {domain} sets bits in the prefix")]
+    #[diagnostic(help("the suffix is the last 64 bits of the address, e.g. ::1:2"))]
+    InvalidIpv6Suffix { domain: String, suffix: Ipv6Addr },
+    #[error("every entry in `lookup_providers` needs either a `url` or a `dns` resolver")]
+    #[diagnostic(help("set exactly one of them for each provider"))]
+    LookupProviderKind,
+    #[error("`lookup_consensus` needs at least 2 `lookup_providers`, but {0} are configured")]
+    #[diagnostic(help("add more providers, ideally 3 or more run by different operators"))]
+    TooFewConsensusProviders(usize),
 }
\ No newline at end of file
@@ -0,0 +1,67 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::net::IpAddr;
+use std::process;
+use std::sync::Mutex;
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+use tracing::warn;
+
+use crate::APPLICATION;
+use crate::config::{Syslog, SyslogFacility, SyslogSeverity};
+
+/// Sends a one-line message to the local syslog daemon for every record change.
+pub struct SyslogNotifier {
+    logger: Mutex<Logger<LoggerBackend, Formatter3164>>,
+    severity: SyslogSeverity,
+}
+
+impl SyslogNotifier {
+    pub fn connect(options: Syslog) -> Result<Self, syslog::Error> {
+        let formatter = Formatter3164 {
+            facility: facility(options.facility),
+            hostname: None,
+            process: APPLICATION.to_string(),
+            pid: process::id(),
+        };
+        let logger = syslog::unix(formatter)?;
+        Ok(Self {
+            logger: Mutex::new(logger),
+            severity: options.severity,
+        })
+    }
+
+    pub fn record_changed(&self, domain: &str, old: Option<IpAddr>, new: IpAddr) {
+        let old = old.map(|ip| ip.to_string()).unwrap_or_else(|| "none".to_string());
+        let message = format!("record changed domain={domain} old={old} new={new}");
+
+        let mut logger = self.logger.lock().unwrap();
+        let sent = match self.severity {
+            SyslogSeverity::Emerg => logger.emerg(message),
+            SyslogSeverity::Alert => logger.alert(message),
+            SyslogSeverity::Crit => logger.crit(message),
+            SyslogSeverity::Err => logger.err(message),
+            SyslogSeverity::Warning => logger.warning(message),
+            SyslogSeverity::Notice => logger.notice(message),
+            SyslogSeverity::Info => logger.info(message),
+            SyslogSeverity::Debug => logger.debug(message),
+        };
+        if let Err(e) = sent {
+            warn!(domain, error=%e, "Failed to send change event to syslog");
+        }
+    }
+}
+
+fn facility(facility: SyslogFacility) -> Facility {
+    match facility {
+        SyslogFacility::User => Facility::LOG_USER,
+        SyslogFacility::Daemon => Facility::LOG_DAEMON,
+        SyslogFacility::Local0 => Facility::LOG_LOCAL0,
+        SyslogFacility::Local1 => Facility::LOG_LOCAL1,
+        SyslogFacility::Local2 => Facility::LOG_LOCAL2,
+        SyslogFacility::Local3 => Facility::LOG_LOCAL3,
+        SyslogFacility::Local4 => Facility::LOG_LOCAL4,
+        SyslogFacility::Local5 => Facility::LOG_LOCAL5,
+        SyslogFacility::Local6 => Facility::LOG_LOCAL6,
+        SyslogFacility::Local7 => Facility::LOG_LOCAL7,
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::process::ExitCode;
+use cloudflare::framework::response::ApiFailure;
+use miette::Report;
+use reqwest::StatusCode;
+
+use crate::{
+    cloudflare::{ClientError, dns::UpdateError, zone::ZoneError},
+    commands::{FilterError, RecordsFailed, RunCancelled, RunTimedOut},
+    config::ConfigError,
+    networking::NetworkError,
+    state::LockError,
+    weblookup::LookupError,
+};
+
+/// Exit codes shared by every command so scripts and monitoring can act on the kind of failure.
+/// Codes 10 and above are reserved for commands that report changes rather than failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success = 0,
+    Error = 1,
+    Config = 2,
+    Auth = 3,
+    Network = 4,
+    /// Some records were rejected, for reasons other than configuration, credentials or the network
+    RecordsFailed = 5,
+    /// `update --timeout` stopped the run before every record was processed
+    TimedOut = 6,
+    /// `update` was stopped by SIGINT or SIGTERM
+    Cancelled = 7,
+    /// Another `update` or the daemon is already running
+    Locked = 8,
+    /// `update` changed at least one record
+    Changed = 10,
+    /// `update --check` found at least one record that is out of date
+    OutOfDate = 11,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+impl Status {
+    /// Classify a failure by the first recognised error in its source chain. Configuration,
+    /// credential and network problems take precedence over records failing because of them.
+    pub fn from_report(report: &Report) -> Self {
+        if report.downcast_ref::<RunTimedOut>().is_some() {
+            return Status::TimedOut;
+        }
+        if report.downcast_ref::<RunCancelled>().is_some() {
+            return Status::Cancelled;
+        }
+        if let Some(LockError::AlreadyRunning { .. }) = report.downcast_ref() {
+            return Status::Locked;
+        }
+        let Some(failed) = report.downcast_ref::<RecordsFailed>() else {
+            return Self::classify(report);
+        };
+        let statuses: Vec<_> = failed.failures().iter().map(Self::classify).collect();
+        [Status::Config, Status::Auth, Status::Network]
+            .into_iter()
+            .find(|status| statuses.contains(status))
+            .unwrap_or(Status::RecordsFailed)
+    }
+
+    fn classify(report: &Report) -> Self {
+        for err in report.chain() {
+            if err.is::<ConfigError>() || err.is::<FilterError>() {
+                return Status::Config;
+            }
+            if let Some(ClientError::MissingToken | ClientError::TokenFile { .. } | ClientError::MissingApiUrl | ClientError::InvalidApiUrl { .. } | ClientError::InvalidProxy { .. }) = err.downcast_ref() {
+                return Status::Config;
+            }
+            if let Some(e) = err.downcast_ref::<ZoneError>() {
+                match e {
+                    ZoneError::AccessDenied(_) => return Status::Auth,
+                    ZoneError::Api(_, code) if is_auth_status(*code) => return Status::Auth,
+                    ZoneError::Invalid(..) | ZoneError::Timeout(..) => return Status::Network,
+                    _ => continue,
+                }
+            }
+            if let Some(UpdateError::Timeout { .. }) = err.downcast_ref() {
+                return Status::Network;
+            }
+            if let Some(e) = err.downcast_ref::<ApiFailure>() {
+                match e {
+                    ApiFailure::Error(code, _) if is_auth_status(code.as_u16()) => return Status::Auth,
+                    ApiFailure::Invalid(_) => return Status::Network,
+                    _ => continue,
+                }
+            }
+            if err.is::<NetworkError>() {
+                return Status::Network;
+            }
+            #[cfg(target_os = "linux")]
+            if err.is::<rtnetlink::Error>() {
+                return Status::Network;
+            }
+            if let Some(e) = err.downcast_ref::<LookupError>() {
+                match e {
+                    LookupError::ClientCreation(_) | LookupError::Connection(_) | LookupError::Timeout(_)
+                    | LookupError::DnsConnection(_) | LookupError::DnsTimeout | LookupError::AllProvidersFailed(_) => {
+                        return Status::Network;
+                    }
+                    _ => continue,
+                }
+            }
+        }
+        Status::Error
+    }
+}
+
+fn is_auth_status(code: u16) -> bool {
+    code == StatusCode::UNAUTHORIZED.as_u16() || code == StatusCode::FORBIDDEN.as_u16()
+}
@@ -0,0 +1,65 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use nix::ifaddrs::getifaddrs;
+
+use crate::networking::{AddressSource, InterfaceAddress, NetworkError};
+
+/// Address discovery through getifaddrs, for platforms without netlink.
+/// getifaddrs doesn't report address lifetimes, flags or labels, so every address is treated as a
+/// non-permanent, non-temporary, usable one with an unknown creation time.
+#[derive(Debug, Clone, Copy)]
+pub struct IfAddrs;
+
+impl AddressSource for IfAddrs {
+    async fn interfaces(&self) -> Result<Vec<String>, NetworkError> {
+        // Each interface appears once per address family
+        let names: BTreeSet<String> = getifaddrs()
+            .map_err(NetworkError::Interfaces)?
+            .map(|ifaddr| ifaddr.interface_name)
+            .collect();
+        Ok(names.into_iter().collect())
+    }
+
+    async fn macs(&self) -> Result<HashMap<String, [u8; 6]>, NetworkError> {
+        // The link-layer entry holds the current MAC address, which is the permanent one unless it
+        // has been changed
+        Ok(getifaddrs()
+            .map_err(NetworkError::Interfaces)?
+            .filter_map(|ifaddr| Some((ifaddr.interface_name, ifaddr.address?.as_link_addr()?.addr()?)))
+            .collect())
+    }
+
+    async fn addresses(&self) -> Result<Vec<InterfaceAddress>, NetworkError> {
+        Ok(getifaddrs()
+            .map_err(NetworkError::Interfaces)?
+            .filter_map(|ifaddr| {
+                let storage = ifaddr.address?;
+                let address = if let Some(v4) = storage.as_sockaddr_in() {
+                    IpAddr::V4(v4.ip())
+                } else {
+                    IpAddr::V6(storage.as_sockaddr_in6()?.ip())
+                };
+                Some(InterfaceAddress {
+                    interface: ifaddr.interface_name,
+                    address,
+                    permanent: false,
+                    temporary: false,
+                    deprecated: false,
+                    tentative: false,
+                    label: None,
+                    created: u32::MAX,
+                })
+            })
+            .collect())
+    }
+
+    async fn route_source(&self, _interface: &str, _destination: IpAddr) -> Result<Option<IpAddr>, NetworkError> {
+        Err(NetworkError::RouteSelectionUnsupported)
+    }
+
+    async fn route_interface(&self, _destination: IpAddr) -> Result<Option<String>, NetworkError> {
+        Err(NetworkError::DefaultInterfaceUnsupported)
+    }
+}
@@ -1,6 +1,5 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
-#![feature(ip)]
 use clap::Parser;
 use clap::Subcommand;
 use tracing::level_filters::LevelFilter;
@@ -9,22 +8,36 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use std::env;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::OnceLock;
 use miette::Result;
+use exit::Status;
 
 mod cloudflare;
 mod commands;
 mod cache;
+#[cfg(target_os = "linux")]
 mod netlink;
+#[cfg(not(target_os = "linux"))]
+mod ifaddrs;
 mod networking;
+mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
 mod config;
+mod events;
+mod exit;
 mod weblookup;
 
 pub const QUALIFIER: &str = "systems.lyon";
 pub const ORGANIZATION: &str = "Lyon Systems";
 pub const APPLICATION: &str = "cfdns";
 pub const ZONE_CACHE_NAME: &str = "zones";
+pub const RECORD_CACHE_NAME: &str = "records";
 pub static CONSOLE_PRINT: OnceLock<bool> = OnceLock::new();
+/// Base URL from `--api-url`, overriding every configured Cloudflare environment
+pub static API_URL_OVERRIDE: OnceLock<String> = OnceLock::new();
 
 
 #[derive(Parser, Debug)]
@@ -42,6 +55,10 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count, global=true)]
     pub verbose: u8,
 
+    /// Send Cloudflare API requests to this base URL instead of the configured environment
+    #[arg(long, value_name = "URL", global = true)]
+    api_url: Option<String>,
+
     /// Subcommands for specific operations
     #[command(subcommand)]
     command: Commands,
@@ -54,6 +71,65 @@ enum Commands {
         /// Perform a dry run without making changes
         #[arg(short, long, help = "Simulate the update without making actual changes.")]
         dry_run: bool,
+        /// Compare against a saved snapshot instead of live Cloudflare records
+        #[arg(long, value_name = "FILE", requires = "dry_run", help = "Dry run against records saved by `cfdns snapshot`.")]
+        from_snapshot: Option<PathBuf>,
+        /// Re-send records that are already up to date
+        #[arg(short, long, help = "Send every record to Cloudflare, even if it is already up to date, and take over records cfdns didn't create.")]
+        force: bool,
+        /// Records processed at the same time
+        #[arg(long, value_name = "N", help = "Process up to N records at the same time, overriding `parallel` in the config (1-32).")]
+        parallel: Option<usize>,
+        /// Deadline for the whole run
+        #[arg(long, value_name = "SECS", value_parser = parse_secs, help = "Stop and exit with 6 if the run takes longer than this many seconds.")]
+        timeout: Option<std::time::Duration>,
+        /// Wait for another running cfdns
+        #[arg(long, value_name = "SECS", value_parser = parse_secs, default_value = "0", help = "Wait up to this many seconds for another running cfdns to finish instead of exiting with 8.")]
+        wait_lock: std::time::Duration,
+        /// Only check whether records are up to date, for monitoring
+        #[arg(long, conflicts_with_all = ["dry_run", "force", "output"], help = "Print one status line and exit with 11 if any record is out of date, without changing anything.")]
+        check: bool,
+        /// Only update records with these domains
+        #[arg(long = "domain", value_name = "DOMAIN", help = "Only update this domain. Accepts globs like `*.example.com` and can be repeated.")]
+        domains: Vec<String>,
+        /// Only update records of these interfaces
+        #[arg(short, long = "interface", value_name = "INTERFACE", help = "Only update records of this interface. Can be repeated.")]
+        interfaces: Vec<String>,
+        /// Only update records with these tags
+        #[arg(long = "tag", value_name = "TAG", help = "Only update records with this tag, such as `env:home`. Can be repeated.")]
+        tags: Vec<String>,
+        /// Only update IPv4 records
+        #[arg(long, conflicts_with = "ipv6_only", help = "Only update A records, skipping the AAAA half of records with type BOTH.")]
+        ipv4_only: bool,
+        /// Only update IPv6 records
+        #[arg(long, help = "Only update AAAA records, skipping the A half of records with type BOTH.")]
+        ipv6_only: bool,
+        /// Format of the results
+        #[arg(short, long, value_enum, default_value_t, help = "Print results for people, or as a JSON array for scripts.")]
+        output: commands::OutputFormat,
+    },
+
+    /// Set or delete a TXT record, e.g. for ACME DNS-01 challenges
+    UpdateTxt {
+        /// Name of the TXT record
+        #[arg(long, help = "Fully qualified name of the TXT record.")]
+        domain: String,
+        /// Value to publish
+        #[arg(long, help = "Value to add, or with --delete, the value of the records to remove.")]
+        value: String,
+        /// Zone containing the record
+        #[arg(long, help = "Zone containing the record. Guessed from the domain if omitted.")]
+        zone: Option<String>,
+        /// Delete the record instead of setting it
+        #[arg(long, help = "Delete the TXT records holding --value instead of adding one.")]
+        delete: bool,
+    },
+
+    /// Save the current Cloudflare records for all configured zones
+    Snapshot {
+        /// File to write the snapshot to
+        #[arg(short, long, value_name = "FILE", help = "Write the snapshot to a file instead of stdout.")]
+        output: Option<PathBuf>,
     },
 
     /// Show the current DNS configuration
@@ -66,6 +142,33 @@ enum Commands {
         reveal: bool
     },
 
+    /// Show the outcome of the last update of each record
+    Status {
+        /// Output in JSON format
+        #[arg(short, long, help = "Display record status in JSON format.")]
+        json: bool,
+    },
+
+    /// List the address changes cfdns has made
+    History {
+        /// Only show changes to this domain
+        #[arg(long, help = "Only show changes to this domain.")]
+        domain: Option<String>,
+        /// Only show changes after this time
+        #[arg(long, value_parser = commands::parse_since, help = "Only show changes since an age like 7d, a date like 2025-01-31, or an RFC 3339 timestamp.")]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        /// Output in JSON format
+        #[arg(short, long, help = "Display history in JSON format.")]
+        json: bool,
+    },
+
+    /// Delete records cfdns published for domains no longer in the config
+    Prune {
+        /// Delete without asking
+        #[arg(short, long, help = "Delete the records without asking for confirmation.")]
+        yes: bool,
+    },
+
     /// Schedule DNS updates using systemd timers
     Schedule {
         /// Disable systemd timer and unschedule updates
@@ -73,26 +176,69 @@ enum Commands {
         off: bool
     },
 
+    /// Run continuously, updating records as soon as interface addresses change
+    #[cfg(target_os = "linux")]
+    Daemon,
+
     /// Setup initial configuration for cfdns
     Setup,
 
+    /// Print a commented example configuration
+    Template,
+
+    /// Inspect or clear the cached Cloudflare zone and record IDs
+    Cache {
+        #[command(subcommand)]
+        action: commands::CacheAction,
+    },
+
     /// Opens your default editor to configure cfdns
     Edit
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let args = Cli::parse();
+    if let Commands::Update { output: commands::OutputFormat::Json, .. } | Commands::Update { check: true, .. } = args.command {
+        // Keep stdout free for the JSON results or status line
+        _ = CONSOLE_PRINT.set(false);
+    }
     init_tracing(args.verbose);
-    match args.command {
-        Commands::Update { dry_run } => commands::update(args.config.as_deref(), dry_run).await?,
-        Commands::Setup {  } => commands::setup(args.config.as_deref()).await?,
-        Commands::Schedule { off } => commands::schedule(off).await?,
-        Commands::Edit {  } => commands::edit(args.config.as_deref()).await?,
-        Commands::Show { json, reveal } => commands::show(args.config, json, reveal).await?
+    if let Some(url) = args.api_url {
+        API_URL_OVERRIDE.set(url).unwrap();
+    }
+    let result: Result<Status> = match args.command {
+        Commands::Update { dry_run, from_snapshot, force, parallel, timeout, wait_lock, check, domains, interfaces, tags, ipv4_only, ipv6_only, output } => {
+            let filter = commands::RecordFilter { domains, interfaces, ipv4_only, ipv6_only, tags };
+            let options = commands::UpdateOptions { dry_run, from_snapshot, check, force, parallel, timeout, wait_lock, output, filter };
+            commands::update(args.config.as_deref(), options).await
+        }
+        Commands::UpdateTxt { domain, value, zone, delete } => commands::update_txt(args.config.as_deref(), &domain, zone.as_deref(), &value, delete).await.map(|()| Status::Success),
+        Commands::Snapshot { output } => commands::snapshot(args.config.as_deref(), output.as_deref()).await.map(|()| Status::Success),
+        Commands::Setup => commands::setup(args.config.as_deref()).await.map(|()| Status::Success),
+        Commands::Template => commands::template().await.map(|()| Status::Success),
+        Commands::Cache { action } => commands::cache(action).await.map(|()| Status::Success),
+        #[cfg(target_os = "linux")]
+        Commands::Daemon => commands::daemon(args.config.as_deref()).await.map(|()| Status::Success),
+        Commands::Status { json } => commands::status(args.config.as_deref(), json).await.map(|()| Status::Success),
+        Commands::History { domain, since, json } => commands::history(domain.as_deref(), since, json).await.map(|()| Status::Success),
+        Commands::Prune { yes } => commands::prune(args.config.as_deref(), yes).await.map(|()| Status::Success),
+        Commands::Schedule { off } => commands::schedule(off).await.map(|()| Status::Success),
+        Commands::Edit => commands::edit(args.config.as_deref()).await.map(|()| Status::Success),
+        Commands::Show { json, reveal } => commands::show(args.config, json, reveal).await.map(|()| Status::Success),
     };
 
-    Ok(())
+    match result {
+        Ok(status) => status.into(),
+        Err(report) => {
+            eprintln!("Error: {report:?}");
+            Status::from_report(&report).into()
+        }
+    }
+}
+
+fn parse_secs(secs: &str) -> Result<std::time::Duration, std::num::ParseIntError> {
+    secs.parse().map(std::time::Duration::from_secs)
 }
 
 pub fn running_under_systemd() -> bool {
@@ -1,9 +1,13 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use futures::{TryStream, TryStreamExt};
-use rtnetlink::{Error, Handle, packet_route::{address::AddressMessage, link::{LinkAttribute, LinkFlags, LinkMessage}}};
-use tracing::{instrument, warn};
+use rtnetlink::{Error, Handle, RouteMessageBuilder, packet_route::{address::{AddressAttribute, AddressFlags, AddressMessage}, link::{LinkAttribute, LinkFlags, LinkMessage}, route::{RouteAddress, RouteAttribute}}};
+use tracing::{debug, instrument, warn};
+
+use crate::networking::{AddressSource, InterfaceAddress, NetworkError};
 
 #[derive(Debug)]
 pub struct Link {
@@ -44,15 +48,6 @@ pub async fn get_links(handle: &Handle) -> Result<Vec<Link>, rtnetlink::Error> {
     Ok(links)
 }
 
-#[instrument]
-pub async fn get_link_by_name(handle: &Handle, name: &str) -> Result<Option<Link>, rtnetlink::Error> {
-    let mut messages = handle.link().get().match_name(name.to_owned()).execute();
-    if let Some(message) = messages.try_next().await? {
-        return Ok(link_from_message(message))
-    }
-    Ok(None)
-}
-
 fn link_from_message(link: LinkMessage) -> Option<Link> {
     let index = link.header.index;
         let flags = link.header.flags;
@@ -86,6 +81,127 @@ fn link_from_message(link: LinkMessage) -> Option<Link> {
         }.into()
 }
 
-pub fn get_addrs_by_link(handle: &Handle, link_index: u32) -> impl TryStream<Ok = AddressMessage, Error = Error> {
-    return handle.address().get().set_link_index_filter(link_index).execute();
-}
\ No newline at end of file
+pub fn get_addrs(handle: &Handle) -> impl TryStream<Ok = AddressMessage, Error = Error> {
+    handle.address().get().execute()
+}
+
+impl AddressSource for Handle {
+    async fn interfaces(&self) -> Result<Vec<String>, NetworkError> {
+        Ok(get_links(self)
+            .await?
+            .into_iter()
+            .map(|link| link.name)
+            .collect())
+    }
+
+    async fn macs(&self) -> Result<HashMap<String, [u8; 6]>, NetworkError> {
+        Ok(get_links(self)
+            .await?
+            .into_iter()
+            .filter_map(|link| Some((link.name, link.mac?.try_into().ok()?)))
+            .collect())
+    }
+
+    async fn addresses(&self) -> Result<Vec<InterfaceAddress>, NetworkError> {
+        let names: HashMap<u32, String> = get_links(self)
+            .await?
+            .into_iter()
+            .map(|link| (link.index, link.name))
+            .collect();
+
+        let mut addresses = Vec::new();
+        let mut addr_stream = get_addrs(self);
+        while let Some(addr) = addr_stream.try_next().await? {
+            let index = addr.header.index;
+            let Some(interface) = names.get(&index) else {
+                continue;
+            };
+
+            let mut flags: Option<AddressFlags> = None;
+            let mut address: Option<IpAddr> = None;
+            // Creation time in hundredths of a second since boot
+            let mut created = u32::MAX;
+            let mut label = None;
+
+            for attr in addr.attributes {
+                match attr {
+                    AddressAttribute::Flags(f) => flags = Some(f),
+                    AddressAttribute::Address(a) => address = Some(a),
+                    AddressAttribute::CacheInfo(info) => created = info.cstamp,
+                    AddressAttribute::Label(l) => label = Some(l),
+                    _ => {}
+                }
+            }
+
+            let Some(address) = address else {
+                warn!(link.index = index, "skipping address: missing IP");
+                continue;
+            };
+
+            addresses.push(InterfaceAddress {
+                interface: interface.clone(),
+                address,
+                permanent: flags.is_some_and(|f| f.contains(AddressFlags::Permanent)),
+                // IFA_F_TEMPORARY shares its bit with IFA_F_SECONDARY, which only applies to IPv4
+                temporary: address.is_ipv6() && flags.is_some_and(|f| f.contains(AddressFlags::Secondary)),
+                deprecated: flags.is_some_and(|f| f.contains(AddressFlags::Deprecated)),
+                tentative: flags.is_some_and(|f| f.intersects(AddressFlags::Tentative | AddressFlags::Dadfailed)),
+                label,
+                created,
+            });
+        }
+        Ok(addresses)
+    }
+
+    async fn route_source(&self, interface: &str, destination: IpAddr) -> Result<Option<IpAddr>, NetworkError> {
+        let Some(link) = get_links(self).await?.into_iter().find(|link| link.name == interface) else {
+            return Err(NetworkError::InvalidInterface(interface.to_string()));
+        };
+        Ok(route_get(self, destination, Some(link.index)).await?.and_then(|attributes| {
+            attributes.into_iter().find_map(|attr| match attr {
+                RouteAttribute::PrefSource(RouteAddress::Inet(v4)) => Some(IpAddr::V4(v4)),
+                RouteAttribute::PrefSource(RouteAddress::Inet6(v6)) => Some(IpAddr::V6(v6)),
+                _ => None,
+            })
+        }))
+    }
+
+    async fn route_interface(&self, destination: IpAddr) -> Result<Option<String>, NetworkError> {
+        let Some(index) = route_get(self, destination, None).await?.and_then(|attributes| {
+            attributes.into_iter().find_map(|attr| match attr {
+                RouteAttribute::Oif(index) => Some(index),
+                _ => None,
+            })
+        }) else {
+            return Ok(None);
+        };
+        Ok(get_links(self).await?.into_iter().find(|link| link.index == index).map(|link| link.name))
+    }
+}
+
+/// Ask the kernel how it would route to `destination`, like `ip route get <destination> [oif <index>]`,
+/// returning the attributes of the chosen route or None if it is unreachable
+async fn route_get(handle: &Handle, destination: IpAddr, oif: Option<u32>) -> Result<Option<Vec<RouteAttribute>>, NetworkError> {
+    let message = match (destination, oif) {
+        (IpAddr::V4(v4), None) => RouteMessageBuilder::<Ipv4Addr>::new().destination_prefix(v4, 32).build(),
+        (IpAddr::V4(v4), Some(index)) => RouteMessageBuilder::<Ipv4Addr>::new()
+            .destination_prefix(v4, 32)
+            .output_interface(index)
+            .build(),
+        (IpAddr::V6(v6), None) => RouteMessageBuilder::<Ipv6Addr>::new().destination_prefix(v6, 128).build(),
+        (IpAddr::V6(v6), Some(index)) => RouteMessageBuilder::<Ipv6Addr>::new()
+            .destination_prefix(v6, 128)
+            .output_interface(index)
+            .build(),
+    };
+    let mut routes = handle.route().get(message).execute();
+    match routes.try_next().await {
+        Ok(route) => Ok(route.map(|route| route.attributes)),
+        // The kernel answers with an error when the destination is unreachable
+        Err(Error::NetlinkError(e)) => {
+            debug!(%destination, error = %e, "No route to destination");
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,29 +1,113 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-use futures::TryStreamExt;
 use miette::Diagnostic;
-use rtnetlink::{
-    Handle,
-    packet_route::address::{AddressAttribute, AddressFlags},
-};
 use thiserror::Error;
 use tracing::{debug, instrument, warn};
 
-use crate::netlink::{get_addrs_by_link, get_link_by_name, get_links};
+use crate::config::{AddressPreference, AddressSelection, Config, Interface, Ipv6AddressPolicy, parse_mac};
 
-pub async fn list_interfaces(handle: &Handle) -> Result<Vec<String>, NetworkError> {
-    Ok(get_links(handle)
-        .await?
-        .into_iter()
-        .map(|link| link.name)
-        .collect())
+/// The platform's default address source: netlink on Linux, getifaddrs elsewhere
+#[cfg(target_os = "linux")]
+pub type Backend = rtnetlink::Handle;
+#[cfg(not(target_os = "linux"))]
+pub type Backend = crate::ifaddrs::IfAddrs;
+
+/// Open the default address source for this platform
+#[cfg(target_os = "linux")]
+pub fn connect() -> Result<Backend, NetworkError> {
+    let (conn, handle, _) = rtnetlink::new_connection().map_err(NetworkError::Connection)?;
+    tokio::spawn(conn);
+    Ok(handle)
+}
+
+/// Open the default address source for this platform
+#[cfg(not(target_os = "linux"))]
+pub fn connect() -> Result<Backend, NetworkError> {
+    Ok(crate::ifaddrs::IfAddrs)
+}
+
+/// An address assigned to an interface
+#[derive(Debug)]
+pub struct InterfaceAddress {
+    pub interface: String,
+    pub address: IpAddr,
+    /// Statically configured rather than autoconfigured or temporary
+    pub permanent: bool,
+    /// An IPv6 privacy extensions address, which is replaced every day or so
+    pub temporary: bool,
+    /// Past its preferred lifetime, usually because the prefix is being renumbered
+    pub deprecated: bool,
+    /// Still going through duplicate address detection, or failed it
+    pub tentative: bool,
+    /// The IPv4 address label, such as `eth0:dyn`
+    pub label: Option<String>,
+    /// Creation time in hundredths of a second since boot, or `u32::MAX` when unknown
+    pub created: u32,
+}
+
+/// Destinations of the route lookups made by `selection: route`
+const ROUTE_PROBE_V4: Ipv4Addr = Ipv4Addr::new(1, 1, 1, 1);
+const ROUTE_PROBE_V6: Ipv6Addr = Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111);
+
+/// A source of interfaces and their addresses, implemented per platform
+pub trait AddressSource {
+    /// Names of every interface on the system
+    fn interfaces(&self) -> impl Future<Output = Result<Vec<String>, NetworkError>> + Send;
+    /// Every address on the system, across all interfaces
+    fn addresses(&self) -> impl Future<Output = Result<Vec<InterfaceAddress>, NetworkError>> + Send;
+    /// The source address the kernel would choose to reach `destination` through an interface, or
+    /// None if there is no route
+    fn route_source(&self, interface: &str, destination: IpAddr) -> impl Future<Output = Result<Option<IpAddr>, NetworkError>> + Send;
+    /// The permanent MAC address of each interface that has one
+    fn macs(&self) -> impl Future<Output = Result<HashMap<String, [u8; 6]>, NetworkError>> + Send;
+    /// The interface the kernel would route traffic for `destination` through, or None if there is
+    /// no route
+    fn route_interface(&self, destination: IpAddr) -> impl Future<Output = Result<Option<String>, NetworkError>> + Send;
+}
+
+/// Interface name that resolves to whichever link carries the default route, separately per family
+pub const DEFAULT_INTERFACE: &str = "default";
+
+/// The links an interface's IPv4 and IPv6 addresses are read from, which only differ for `default`.
+/// Disabled address families aren't read from any link.
+async fn resolve_links(source: &impl AddressSource, interface: &str, config: &Interface) -> Result<(Option<String>, Option<String>), NetworkError> {
+    let (v4, v6) = if interface != DEFAULT_INTERFACE {
+        (Some(interface.to_owned()), Some(interface.to_owned()))
+    } else {
+        let v4 = if config.ipv4_enabled() { source.route_interface(ROUTE_PROBE_V4.into()).await? } else { None };
+        let v6 = if config.ipv6_enabled() { source.route_interface(ROUTE_PROBE_V6.into()).await? } else { None };
+        debug!(ipv4 = ?v4, ipv6 = ?v6, "Resolved default interface");
+        (v4, v6)
+    };
+    Ok((v4.filter(|_| config.ipv4_enabled()), v6.filter(|_| config.ipv6_enabled())))
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub async fn list_interfaces(source: &impl AddressSource) -> Result<Vec<String>, NetworkError> {
+    source.interfaces().await
+}
+
+/// Resolve the configured interfaces against the links currently on the system, expanding MAC
+/// addresses and patterns into the links they match
+pub async fn expand_interfaces(source: &impl AddressSource, config: &Config) -> Result<HashMap<String, Interface>, NetworkError> {
+    let links = source.interfaces().await?;
+    let macs = source.macs().await?;
+    for key in config.interfaces.keys() {
+        if let Some(mac) = parse_mac(key)
+            && !macs.values().any(|m| *m == mac)
+        {
+            warn!(interface = key, "No interface has this MAC address");
+        }
+    }
+    Ok(config.expanded_interfaces(&links, &macs))
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 enum Preference {
     Highest,
+    Higher,
     High,
     Mid,
     Low,
@@ -32,64 +116,145 @@ enum Preference {
 
 #[derive(Error, Debug, Diagnostic)]
 pub enum NetworkError {
+    #[cfg(target_os = "linux")]
     #[error(transparent)]
     Netlink(#[from] rtnetlink::Error),
+    #[cfg(not(target_os = "linux"))]
+    #[error("failed to read interface addresses")]
+    Interfaces(#[source] nix::Error),
+    #[cfg(target_os = "linux")]
+    #[error("failed to open a netlink connection")]
+    Connection(#[source] std::io::Error),
     #[error("interface `{0}` not found")]
     InvalidInterface(String),
+    #[cfg(target_os = "linux")]
+    #[error("failed to subscribe to netlink address events")]
+    Subscribe(#[source] std::io::Error),
+    #[cfg(target_os = "linux")]
+    #[error("netlink event stream closed unexpectedly")]
+    EventsClosed,
+    #[cfg(not(target_os = "linux"))]
+    #[error("`selection: route` is only supported on Linux")]
+    #[diagnostic(help("remove `selection` from the interface to score its addresses instead"))]
+    RouteSelectionUnsupported,
+    #[cfg(not(target_os = "linux"))]
+    #[error("the `default` interface is only supported on Linux")]
+    #[diagnostic(help("name the interface that carries your default route instead"))]
+    DefaultInterfaceUnsupported,
 }
 
-#[instrument]
+/// The best IPv4 and IPv6 address found on an interface
+pub type BestAddresses = (Option<Ipv4Addr>, Option<Ipv6Addr>);
+
+/// The addresses selected on an interface
+#[derive(Debug, Default, Clone)]
+pub struct SelectedAddresses {
+    pub best: BestAddresses,
+    /// Every usable global IPv6 address, best first, for records that publish all of them.
+    /// Temporary addresses are only included with `ipv6_address_policy: prefer_temporary`.
+    pub global_ipv6: Vec<Ipv6Addr>,
+}
+
+#[instrument(skip(source, config))]
 pub async fn best_addresses_by_interface(
-    handle: &Handle,
+    source: &impl AddressSource,
     interface: &str,
-) -> Result<(Option<Ipv4Addr>, Option<Ipv6Addr>), NetworkError> {
-    let link = get_link_by_name(&handle, interface)
-        .await?
-        .ok_or_else(|| NetworkError::InvalidInterface(interface.to_owned()))?;
-    debug!(link=%link);
-    // let mut r = handle.route().get(
-    //     RouteMessageBuilder::<Ipv4Addr>::new()
-    //         .input_interface(link.index)
-    //         .output_interface(link.index)
-    //         .scope(RouteScope::Universe)
-    //         .build()
-    // ).execute();
-    // while let Some(r2) = r.try_next().await.unwrap() {
-    //     // println!("{:?}", r2);
-    // }
-
-    let mut addresses = Vec::new();
-    let mut addr_stream = get_addrs_by_link(&handle, link.index);
-
-    while let Some(addr) = addr_stream.try_next().await? {
-        let mut flags: Option<AddressFlags> = None;
-        let mut address: Option<IpAddr> = None;
-
-        for attr in addr.attributes {
-            match attr {
-                AddressAttribute::Flags(f) => flags = Some(f),
-                AddressAttribute::Address(a) => address = Some(a),
-                _ => {}
+    config: &Interface,
+) -> Result<BestAddresses, NetworkError> {
+    let mut best = best_addresses_by_interfaces(source, [(interface, config)]).await?;
+    Ok(best.remove(interface).unwrap_or_default().best)
+}
+
+/// Select the best addresses for several interfaces from a single address dump
+#[instrument(skip_all)]
+pub async fn best_addresses_by_interfaces<'a>(
+    source: &impl AddressSource,
+    interfaces: impl IntoIterator<Item = (&'a str, &'a Interface)>,
+) -> Result<HashMap<&'a str, SelectedAddresses>, NetworkError> {
+    let names = source.interfaces().await?;
+
+    let mut wanted = HashMap::new();
+    for (interface, config) in interfaces {
+        if interface != DEFAULT_INTERFACE && !names.iter().any(|name| name == interface) {
+            return Err(NetworkError::InvalidInterface(interface.to_owned()));
+        }
+        debug!(interface);
+        let links = resolve_links(source, interface, config).await?;
+        wanted.insert(interface, (config, links, Vec::new(), Vec::new()));
+    }
+
+    for addr in source.addresses().await? {
+        // An address can belong to both a named interface and `default`
+        for (config, (v4_link, v6_link), addresses, global_ipv6) in wanted.values_mut() {
+            let link = if addr.address.is_ipv4() { v4_link } else { v6_link };
+            if link.as_ref() != Some(&addr.interface) {
+                continue;
+            }
+            if let Some(label) = &config.address_label
+                && addr.address.is_ipv4()
+                && addr.label.as_ref() != Some(label)
+            {
+                continue;
             }
+            if !config.allows_address(&addr.address) {
+                debug!(interface = addr.interface, address = %addr.address, "Address excluded by subnet filters");
+                continue;
+            }
+            let preference = compute_preference(&addr, config.ipv6_address_policy);
+            if let IpAddr::V6(v6) = addr.address
+                && preference != Preference::Invalid
+                && classify::is_global_v6(&v6)
+                && (!addr.temporary || config.ipv6_address_policy == Ipv6AddressPolicy::PreferTemporary)
+            {
+                global_ipv6.push((v6, preference.clone(), addr.created));
+            }
+            addresses.push((addr.address, preference, addr.created));
         }
+    }
 
-        let Some(address) = address else {
-            warn!(link.index, link.name, "skipping address: missing IP");
-            continue;
+    let mut best = HashMap::new();
+    for (interface, (config, (v4_link, v6_link), addresses, mut global_ipv6)) in wanted {
+        let (scored_v4, scored_v6) = select_best(addresses, config.address_preference);
+        let selected = match config.selection {
+            AddressSelection::Score => (scored_v4, scored_v6),
+            AddressSelection::Route => {
+                // Fall back to scoring for a family the kernel has no route or source for
+                let v4 = match v4_link {
+                    Some(link) => match source.route_source(&link, ROUTE_PROBE_V4.into()).await? {
+                        Some(IpAddr::V4(v4)) => Some(v4),
+                        _ => scored_v4,
+                    },
+                    None => None,
+                };
+                let v6 = match v6_link {
+                    Some(link) => match source.route_source(&link, ROUTE_PROBE_V6.into()).await? {
+                        Some(IpAddr::V6(v6)) => Some(v6),
+                        _ => scored_v6,
+                    },
+                    None => None,
+                };
+                debug!(interface, ipv4 = ?v4, ipv6 = ?v6, "Selected route source addresses");
+                (v4, v6)
+            }
         };
-
-        let preference = compute_preference(&flags, &address);
-
-        addresses.push((address, preference));
+        global_ipv6.sort_by(|a, b| a.1.cmp(&b.1).then(a.2.cmp(&b.2)));
+        let global_ipv6 = global_ipv6.into_iter().map(|(v6, _, _)| v6).collect();
+        best.insert(interface, SelectedAddresses { best: selected, global_ipv6 });
     }
+    Ok(best)
+}
 
+fn select_best(mut addresses: Vec<(IpAddr, Preference, u32)>, tie_breaker: AddressPreference) -> BestAddresses {
     // Sort by descending preference: High > Mid > Low
-    addresses.sort_by(|a, b| a.1.cmp(&b.1));
+    match tie_breaker {
+        AddressPreference::Standard => addresses.sort_by(|a, b| a.1.cmp(&b.1)),
+        AddressPreference::Oldest => addresses.sort_by(|a, b| a.1.cmp(&b.1).then(a.2.cmp(&b.2))),
+    }
 
     let mut best_ipv4 = None;
     let mut best_ipv6 = None;
 
-    for (address, pref) in addresses {
+    for (address, pref, _) in addresses {
         if pref == Preference::Invalid {
             continue;
         }
@@ -104,11 +269,18 @@ pub async fn best_addresses_by_interface(
         }
     }
 
-    Ok((best_ipv4, best_ipv6))
+    (best_ipv4, best_ipv6)
 }
 
-fn compute_preference(flags: &Option<AddressFlags>, addr: &IpAddr) -> Preference {
-    match addr {
+fn compute_preference(addr: &InterfaceAddress, policy: Ipv6AddressPolicy) -> Preference {
+    // Publishing these would race renumbering, or an address that may never become usable
+    if addr.deprecated || addr.tentative {
+        return Preference::Invalid;
+    }
+    if addr.temporary && policy == Ipv6AddressPolicy::RequireStable {
+        return Preference::Invalid;
+    }
+    match &addr.address {
         IpAddr::V4(v4) => {
             if v4.is_loopback() {
                 Preference::Invalid
@@ -116,7 +288,7 @@ fn compute_preference(flags: &Option<AddressFlags>, addr: &IpAddr) -> Preference
                 Preference::Invalid
             } else if v4.is_private() {
                 Preference::Mid
-            } else if v4.is_global() {
+            } else if classify::is_global_v4(v4) {
                 Preference::High
             } else {
                 Preference::Low
@@ -127,11 +299,16 @@ fn compute_preference(flags: &Option<AddressFlags>, addr: &IpAddr) -> Preference
                 Preference::Invalid
             } else if v6.is_unicast_link_local() {
                 Preference::Invalid
-            } else if v6.is_unique_local() {
+            } else if classify::is_unique_local(v6) {
                 Preference::Mid
-            } else if v6.is_global() {
-                match flags {
-                    Some(f) if f.contains(AddressFlags::Permanent) => Preference::Highest,
+            } else if classify::is_global_v6(v6) {
+                // Static addresses first, then stable SLAAC, then privacy addresses, unless the policy
+                // puts privacy addresses first
+                match (policy, addr.permanent, addr.temporary) {
+                    (Ipv6AddressPolicy::PreferTemporary, _, true) => Preference::Highest,
+                    (Ipv6AddressPolicy::PreferTemporary, true, _) => Preference::Higher,
+                    (_, true, _) => Preference::Highest,
+                    (_, _, false) => Preference::Higher,
                     _ => Preference::High,
                 }
             } else {
@@ -140,3 +317,83 @@ fn compute_preference(flags: &Option<AddressFlags>, addr: &IpAddr) -> Preference
         }
     }
 }
+
+/// Whether an address can be reached from the internet, unlike private, shared or reserved ones
+pub fn is_global(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => classify::is_global_v4(v4),
+        IpAddr::V6(v6) => classify::is_global_v6(v6),
+    }
+}
+
+/// Whether an address is in the shared address space used by carrier-grade NAT, 100.64.0.0/10
+pub fn is_cgnat(ip: &Ipv4Addr) -> bool {
+    classify::is_cgnat(ip)
+}
+
+/// Address range checks that are still unstable in std, following the IANA special-purpose registries
+mod classify {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    /// Shared address space used by carrier-grade NAT, 100.64.0.0/10
+    pub fn is_cgnat(ip: &Ipv4Addr) -> bool {
+        let [a, b, ..] = ip.octets();
+        a == 100 && (b & 0b1100_0000) == 0b0100_0000
+    }
+
+    pub fn is_global_v4(ip: &Ipv4Addr) -> bool {
+        let octets = ip.octets();
+        !(octets[0] == 0 // "this network", 0.0.0.0/8
+            || ip.is_private()
+            || is_cgnat(ip)
+            || ip.is_loopback()
+            || ip.is_link_local()
+            // IETF protocol assignments, 192.0.0.0/24, except the globally routable .9 and .10
+            || (octets[..3] == [192, 0, 0] && octets[3] != 9 && octets[3] != 10)
+            || ip.is_documentation()
+            // Benchmarking, 198.18.0.0/15
+            || (octets[0] == 198 && (octets[1] & 0xfe) == 18)
+            // Reserved, 240.0.0.0/4
+            || (octets[0] & 0xf0) == 240
+            || ip.is_broadcast())
+    }
+
+    /// Unique local addresses, fc00::/7
+    pub fn is_unique_local(ip: &Ipv6Addr) -> bool {
+        (ip.segments()[0] & 0xfe00) == 0xfc00
+    }
+
+    pub fn is_global_v6(ip: &Ipv6Addr) -> bool {
+        let segments = ip.segments();
+        !(ip.is_unspecified()
+            || ip.is_loopback()
+            // IPv4-mapped, ::ffff:0:0/96
+            || matches!(segments, [0, 0, 0, 0, 0, 0xffff, _, _])
+            // IPv4/IPv6 translation, 64:ff9b:1::/48
+            || matches!(segments, [0x64, 0xff9b, 1, _, _, _, _, _])
+            // Discard-only, 100::/64
+            || matches!(segments, [0x100, 0, 0, 0, _, _, _, _])
+            // IETF protocol assignments, 2001::/23, apart from the globally routable exceptions
+            || (matches!(segments, [0x2001, b, _, _, _, _, _, _] if b < 0x200)
+                && !(
+                    // Port control protocol anycast and TURN anycast, 2001:1::1 and 2001:1::2
+                    u128::from_be_bytes(ip.octets()) == 0x2001_0001_0000_0000_0000_0000_0000_0001
+                    || u128::from_be_bytes(ip.octets()) == 0x2001_0001_0000_0000_0000_0000_0000_0002
+                    // AMT, 2001:3::/32
+                    || matches!(segments, [0x2001, 3, _, _, _, _, _, _])
+                    // AS112-v6, 2001:4:112::/48
+                    || matches!(segments, [0x2001, 4, 0x112, _, _, _, _, _])
+                    // ORCHIDv2, 2001:20::/28, and DRIP, 2001:30::/28
+                    || matches!(segments, [0x2001, b, _, _, _, _, _, _] if (0x20..=0x3f).contains(&b))
+                ))
+            // 6to4, 2002::/16
+            || segments[0] == 0x2002
+            // Documentation, 2001:db8::/32 and 3fff::/20
+            || matches!(segments, [0x2001, 0xdb8, _, _, _, _, _, _])
+            || matches!(segments, [0x3fff, 0..=0x0fff, _, _, _, _, _, _])
+            // Segment routing SIDs, 5f00::/16
+            || segments[0] == 0x5f00
+            || is_unique_local(ip)
+            || ip.is_unicast_link_local())
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use cloudflare::endpoints::dns::dns::DnsContent;
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::cloudflare::dns::{CachedRecord, CommentedRecord, RecordState};
+
+/// A saved copy of the Cloudflare records in each configured zone, keyed by zone name
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Snapshot {
+    pub zones: HashMap<String, SnapshotZone>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotZone {
+    pub id: String,
+    pub records: Vec<SnapshotRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotRecord {
+    pub id: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub content: DnsContent,
+    pub ttl: u32,
+    pub proxied: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl From<CommentedRecord> for SnapshotRecord {
+    fn from(CommentedRecord { record, comment, tags }: CommentedRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            content: record.content,
+            ttl: record.ttl,
+            proxied: record.proxied,
+            comment,
+            tags,
+        }
+    }
+}
+
+impl SnapshotRecord {
+    /// The record as [`CachedRecord::from_record`] would read it, if it is an A or AAAA record
+    fn cached(&self) -> Option<CachedRecord> {
+        let ip = match self.content {
+            DnsContent::A { content } => IpAddr::V4(content),
+            DnsContent::AAAA { content } => IpAddr::V6(content),
+            _ => return None,
+        };
+        let state = RecordState { ip, ttl: self.ttl, proxied: self.proxied };
+        Some(CachedRecord::new(self.id.clone(), self.name.clone(), state, self.comment.clone(), self.tags.clone()))
+    }
+}
+
+impl Snapshot {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|source| SnapshotError::File { path: path.to_path_buf(), source })?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|source| SnapshotError::File { path: path.to_path_buf(), source })?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Existing A and AAAA records for a domain, mirroring `fetch_ip_records`
+    pub fn ip_records(&self, zone: &str, domain: &str) -> Result<(Option<CachedRecord>, Option<CachedRecord>), SnapshotError> {
+        let mut v4: Option<CachedRecord> = None;
+        let mut v6: Option<CachedRecord> = None;
+        for record in self.ip_record_set(zone, domain)? {
+            let found = if record.state.ip.is_ipv6() { &mut v6 } else { &mut v4 };
+            // A record cfdns created wins over others with the same name and type
+            if found.as_ref().is_none_or(|r| !r.managed) {
+                *found = Some(record);
+            }
+        }
+        Ok((v4, v6))
+    }
+
+    /// Every existing A and AAAA record for a domain, mirroring `fetch_all_ip_records`
+    pub fn ip_record_set(&self, zone: &str, domain: &str) -> Result<Vec<CachedRecord>, SnapshotError> {
+        let zone = self.zones.get(zone).ok_or_else(|| SnapshotError::MissingZone(zone.to_string()))?;
+        Ok(zone.records
+            .iter()
+            .filter(|r| r.name == domain)
+            .filter_map(SnapshotRecord::cached)
+            .collect())
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum SnapshotError {
+    #[error("unable to open snapshot file at {path}")]
+    #[diagnostic(help("ensure the path exists and you have permission to access it"))]
+    File { path: PathBuf, #[source] source: io::Error },
+    #[error("failed to parse snapshot")]
+    #[diagnostic(help("snapshots should be created with `cfdns snapshot`"))]
+    Json { #[from] source: serde_json::Error },
+    #[error("zone `{0}` is not present in the snapshot")]
+    #[diagnostic(help("take a new snapshot after adding zones to your config"))]
+    MissingZone(String),
+}
@@ -0,0 +1,134 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+//! SQLite storage for caches, state and the history log, enabled with the `sqlite` feature.
+//! Each cache or state directory gets one database, and every save runs in a transaction so an
+//! interrupted write leaves the previous contents in place.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use miette::{IntoDiagnostic, Result};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+
+use crate::state::HistoryEntry;
+
+pub const DATABASE_FILE_NAME: &str = "cfdns.sqlite3";
+
+/// How long to wait for another cfdns process to finish writing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS caches (
+        name TEXT PRIMARY KEY,
+        version INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS entries (
+        cache TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (cache, key)
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        at INTEGER NOT NULL,
+        domain TEXT NOT NULL,
+        entry TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_domain ON history (domain, at);
+";
+
+pub fn database_path(dir: &Path) -> PathBuf {
+    dir.join(DATABASE_FILE_NAME)
+}
+
+fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path).into_diagnostic()?;
+    conn.busy_timeout(BUSY_TIMEOUT).into_diagnostic()?;
+    conn.execute_batch(SCHEMA).into_diagnostic()?;
+    Ok(conn)
+}
+
+/// The version and raw entries of a cache, or None if it has never been saved to the database
+pub fn load_cache(path: &Path, name: &str) -> Result<Option<(u32, HashMap<String, serde_json::Value>)>> {
+    let conn = open(path)?;
+    let version: Option<u32> = conn
+        .query_row("SELECT version FROM caches WHERE name = ?1", [name], |row| row.get(0))
+        .optional()
+        .into_diagnostic()?;
+    let Some(version) = version else {
+        return Ok(None);
+    };
+
+    let mut statement = conn.prepare("SELECT key, value FROM entries WHERE cache = ?1").into_diagnostic()?;
+    let rows = statement
+        .query_map([name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .into_diagnostic()?;
+    let mut entries = HashMap::new();
+    for row in rows {
+        let (key, value) = row.into_diagnostic()?;
+        // Unreadable values are left for the cache to drop, like entries that fail to migrate
+        entries.insert(key, serde_json::from_str(&value).unwrap_or(serde_json::Value::Null));
+    }
+    Ok(Some((version, entries)))
+}
+
+/// Replace every entry of a cache in one transaction
+pub fn save_cache<K: Serialize, V: Serialize>(path: &Path, name: &str, version: u32, map: &HashMap<K, V>) -> Result<()> {
+    let mut conn = open(path)?;
+    let tx = conn.transaction().into_diagnostic()?;
+    tx.execute("DELETE FROM entries WHERE cache = ?1", [name]).into_diagnostic()?;
+    {
+        let mut insert = tx
+            .prepare("INSERT INTO entries (cache, key, value) VALUES (?1, ?2, ?3)")
+            .into_diagnostic()?;
+        for (key, value) in map {
+            let key = match serde_json::to_value(key).into_diagnostic()? {
+                serde_json::Value::String(key) => key,
+                other => other.to_string(),
+            };
+            let value = serde_json::to_string(value).into_diagnostic()?;
+            insert.execute(params![name, key, value]).into_diagnostic()?;
+        }
+    }
+    tx.execute(
+        "INSERT INTO caches (name, version) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET version = ?2",
+        params![name, version],
+    )
+    .into_diagnostic()?;
+    tx.commit().into_diagnostic()
+}
+
+pub fn append_history(dir: &Path, entries: &[HistoryEntry]) -> Result<()> {
+    let mut conn = open(&database_path(dir))?;
+    let tx = conn.transaction().into_diagnostic()?;
+    {
+        let mut insert = tx
+            .prepare("INSERT INTO history (at, domain, entry) VALUES (?1, ?2, ?3)")
+            .into_diagnostic()?;
+        for entry in entries {
+            let text = serde_json::to_string(entry).into_diagnostic()?;
+            insert
+                .execute(params![entry.at.timestamp_millis(), entry.domain, text])
+                .into_diagnostic()?;
+        }
+    }
+    tx.commit().into_diagnostic()
+}
+
+/// Every entry in the history table, oldest first
+pub fn read_history(dir: &Path) -> Result<Vec<HistoryEntry>> {
+    let conn = open(&database_path(dir))?;
+    let mut statement = conn.prepare("SELECT entry FROM history ORDER BY at, id").into_diagnostic()?;
+    let rows = statement
+        .query_map([], |row| row.get::<_, String>(0))
+        .into_diagnostic()?;
+    let mut entries = Vec::new();
+    for row in rows {
+        let text = row.into_diagnostic()?;
+        match serde_json::from_str(&text) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!(error = %e, "Skipping unreadable history entry"),
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,355 @@
+// Copyright 2025 Matthew Lyon
+// SPDX-License-Identifier: Apache-2.0
+//! Run state that has to outlive a cache cleanup, such as what was last published for each record.
+//! It is stored like a cache, but under the state directory instead.
+use std::collections::BTreeMap;
+use std::fs::{self, File, TryLockError};
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use directories::ProjectDirs;
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::{APPLICATION, ORGANIZATION, QUALIFIER};
+use crate::cache::{AsyncCache, Cache, CacheEntry, cache_dir, unix_now};
+use crate::cloudflare::dns::RecordState;
+
+pub const PUBLISHED_STATE_NAME: &str = "published";
+pub const STATUS_STATE_NAME: &str = "status";
+pub const LOOKUP_STATE_NAME: &str = "lookups";
+pub const ROUND_ROBIN_STATE_NAME: &str = "round_robin";
+pub const HISTORY_FILE_NAME: &str = "history.jsonl";
+pub const REPORT_FILE_NAME: &str = "last-run.json";
+pub const LOCK_FILE_NAME: &str = "cfdns.lock";
+
+/// The last published state of each record, keyed by [`crate::cache::record_cache_key`]
+pub type AsyncPublishedState = AsyncCache<String, PublishedRecord>;
+/// The outcome of the last update of each record, keyed by [`crate::cache::record_key`]
+pub type AsyncRecordStatus = AsyncCache<String, RecordStatus>;
+/// The last web lookup result of each interface and address family, keyed by [`lookup_key`]
+pub type AsyncLookupState = AsyncCache<String, LookedUpAddress>;
+/// The members of each round-robin record, keyed by [`crate::cache::record_key`]
+pub type AsyncRoundRobinState = AsyncCache<String, RoundRobinSet>;
+
+/// Directory holding run state: `$XDG_STATE_HOME/cfdns` on Linux, or the local data directory on
+/// platforms without a separate state directory
+pub fn state_dir() -> Result<PathBuf> {
+    let base = ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION).ok_or(miette::miette!("No project dirs"))?;
+    Ok(base.state_dir().unwrap_or(base.data_local_dir()).to_path_buf())
+}
+
+/// Load a state file, moving it out of the cache directory if an older version saved it there
+pub fn load<K, V>(name: &str) -> Result<Cache<K, V>>
+where
+    K: Serialize + DeserializeOwned + Eq + Hash,
+    V: CacheEntry,
+{
+    let dir = state_dir()?;
+    let file_name = format!("{}.json", name);
+    let legacy = cache_dir()?.join(&file_name);
+    if legacy.exists() && !dir.join(&file_name).exists() {
+        debug!(from = %legacy.display(), to = %dir.display(), "Moving {} state out of the cache directory", name);
+        if let Err(e) = fs::create_dir_all(&dir).and_then(|_| fs::rename(&legacy, dir.join(&file_name))) {
+            warn!(error = %e, "Could not move {} state out of the cache directory", name);
+        }
+    }
+    Cache::load_in(&dir, name)
+}
+
+/// A record as cfdns last published it, or confirmed it was already published
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PublishedRecord {
+    #[serde(flatten)]
+    pub state: RecordState,
+    /// Unix time in seconds
+    pub at: u64,
+}
+
+impl CacheEntry for PublishedRecord {
+    const VERSION: u32 = 1;
+}
+
+impl PublishedRecord {
+    pub fn now(state: RecordState) -> Self {
+        Self { state, at: unix_now() }
+    }
+
+    /// Seconds since the record was published
+    pub fn age(&self) -> u64 {
+        unix_now().saturating_sub(self.at)
+    }
+}
+
+/// The public address a web lookup returned for an interface address
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LookedUpAddress {
+    /// The interface address the lookup was sent from
+    pub local: IpAddr,
+    pub public: IpAddr,
+    /// Unix time in seconds
+    pub at: u64,
+}
+
+impl CacheEntry for LookedUpAddress {
+    const VERSION: u32 = 1;
+}
+
+impl LookedUpAddress {
+    pub fn now(local: IpAddr, public: IpAddr) -> Self {
+        Self { local, public, at: unix_now() }
+    }
+
+    /// Seconds since the lookup was made
+    pub fn age(&self) -> u64 {
+        unix_now().saturating_sub(self.at)
+    }
+}
+
+/// Key for the lookups of one address family on an interface, such as `eth0/AAAA`
+pub fn lookup_key(interface: &str, local: &IpAddr) -> String {
+    format!("{}/{}", interface, if local.is_ipv4() { "A" } else { "AAAA" })
+}
+
+/// The addresses each interface contributes to a round-robin record
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoundRobinSet {
+    pub members: BTreeMap<String, Vec<IpAddr>>,
+}
+
+impl CacheEntry for RoundRobinSet {
+    const VERSION: u32 = 1;
+}
+
+impl RoundRobinSet {
+    /// Every member's addresses, sorted and without duplicates
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        let mut ips: Vec<IpAddr> = self.members.values().flatten().copied().collect();
+        ips.sort();
+        ips.dedup();
+        ips
+    }
+}
+
+/// What happened to a record the last time `cfdns update` processed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordStatus {
+    pub result: RunResult,
+    /// Unix time in seconds of the run that produced `result`
+    pub at: u64,
+    /// Address published for the record as of the last successful run
+    pub ip: Option<IpAddr>,
+    /// Unix time in seconds of the last change cfdns made to the record
+    pub updated_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunResult {
+    Updated,
+    Unchanged,
+    /// No address was found for the record
+    Skipped,
+    Error,
+}
+
+impl CacheEntry for RecordStatus {
+    const VERSION: u32 = 1;
+}
+
+impl RecordStatus {
+    /// The status after a run, keeping the address and update time from `previous` when the run
+    /// didn't replace them
+    pub fn after(previous: Option<&Self>, result: RunResult, ip: Option<IpAddr>, error: Option<String>) -> Self {
+        let at = unix_now();
+        Self {
+            result,
+            at,
+            ip: ip.or(previous.and_then(|p| p.ip)),
+            updated_at: if result == RunResult::Updated { Some(at) } else { previous.and_then(|p| p.updated_at) },
+            error,
+        }
+    }
+}
+
+/// An address change made by cfdns, as stored in the history log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub at: DateTime<Utc>,
+    pub domain: String,
+    /// None when the record was created
+    pub old: Option<IpAddr>,
+    pub new: IpAddr,
+    /// Whether the new address came from a web lookup rather than the interface
+    pub web_lookup: bool,
+}
+
+impl HistoryEntry {
+    pub fn now(domain: &str, old: Option<IpAddr>, new: IpAddr, web_lookup: bool) -> Self {
+        Self { at: Utc::now(), domain: domain.to_string(), old, new, web_lookup }
+    }
+}
+
+/// Append entries to the history log, which holds one JSON object per line and is never rewritten
+pub fn append_history(entries: &[HistoryEntry]) -> Result<()> {
+    let dir = state_dir()?;
+    fs::create_dir_all(&dir).into_diagnostic()?;
+    #[cfg(feature = "sqlite")]
+    return crate::sqlite::append_history(&dir, entries);
+
+    #[cfg(not(feature = "sqlite"))]
+    append_history_file(&dir, entries)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn append_history_file(dir: &std::path::Path, entries: &[HistoryEntry]) -> Result<()> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&serde_json::to_string(entry).into_diagnostic()?);
+        lines.push('\n');
+    }
+    // A single write per batch keeps lines from concurrent updates intact
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(HISTORY_FILE_NAME))
+        .into_diagnostic()?;
+    file.write_all(lines.as_bytes()).into_diagnostic()
+}
+
+/// Every entry in the history log, oldest first. Lines that can't be read are skipped.
+pub fn read_history() -> Result<Vec<HistoryEntry>> {
+    let dir = state_dir()?;
+    let file_entries = read_history_file(&dir.join(HISTORY_FILE_NAME))?;
+    // Entries logged before switching to the database stay in the file
+    #[cfg(feature = "sqlite")]
+    if crate::sqlite::database_path(&dir).exists() {
+        let mut entries = file_entries;
+        entries.extend(crate::sqlite::read_history(&dir)?);
+        entries.sort_by_key(|entry| entry.at);
+        return Ok(entries);
+    }
+    Ok(file_entries)
+}
+
+fn read_history_file(path: &std::path::Path) -> Result<Vec<HistoryEntry>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).into_diagnostic(),
+    };
+
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.into_diagnostic()?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!(line = number + 1, error = %e, "Skipping unreadable history entry"),
+        }
+    }
+    Ok(entries)
+}
+
+/// The outcome of one `cfdns update` run, written to [`REPORT_FILE_NAME`] for other tools to read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Requests sent to the Cloudflare API, including retries
+    pub api_calls: u64,
+    pub records: Vec<RecordReport>,
+    /// Why the run stopped early, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of one record type within a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordReport {
+    pub domain: String,
+    pub r#type: String,
+    pub result: RunResult,
+    /// The address before the run, if cfdns looked it up
+    pub old: Option<IpAddr>,
+    pub new: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time spent on the whole record, shared by its A and AAAA entries
+    pub duration_ms: u64,
+}
+
+/// Replace the report of the previous run. The report is written to a temporary file first, so
+/// readers never see a partial one.
+pub fn write_report(report: &RunReport) -> Result<()> {
+    let dir = state_dir()?;
+    fs::create_dir_all(&dir).into_diagnostic()?;
+    let path = dir.join(REPORT_FILE_NAME);
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, serde_json::to_string_pretty(report).into_diagnostic()?).into_diagnostic()?;
+    fs::rename(&temp, &path).into_diagnostic()
+}
+
+/// Held while an update or the daemon runs, so two of them don't write the same state or race
+/// each other's API calls. Released when dropped, or by the OS if cfdns dies.
+pub struct RunLock {
+    _file: File,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum LockError {
+    #[error("cfdns is already running (pid {pid})")]
+    #[diagnostic(help("wait for it to finish, or pass --wait-lock to `cfdns update` to wait for it"))]
+    AlreadyRunning { pid: String },
+    #[error("unable to lock {path}")]
+    File { path: PathBuf, #[source] source: io::Error },
+}
+
+/// Take the run lock, waiting up to `wait` for another instance to finish
+pub async fn lock(wait: Duration) -> Result<RunLock> {
+    let dir = state_dir()?;
+    let path = dir.join(LOCK_FILE_NAME);
+    let file_error = |source| LockError::File { path: path.clone(), source };
+    fs::create_dir_all(&dir).map_err(file_error)?;
+    let mut file = File::options().read(true).write(true).create(true).truncate(false).open(&path).map_err(file_error)?;
+
+    let deadline = Instant::now() + wait;
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                if !waiting {
+                    info!(wait_secs = wait.as_secs(), "Waiting for another cfdns to finish");
+                    waiting = true;
+                }
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                _ = file.read_to_string(&mut pid);
+                let pid = if pid.trim().is_empty() { "unknown".to_string() } else { pid.trim().to_string() };
+                return Err(LockError::AlreadyRunning { pid }.into());
+            }
+            Err(TryLockError::Error(source)) => return Err(file_error(source).into()),
+        }
+    }
+
+    // Only informational, so a failure to record the pid doesn't matter
+    _ = file.set_len(0)
+        .and_then(|_| file.rewind())
+        .and_then(|_| write!(file, "{}", std::process::id()));
+    Ok(RunLock { _file: file })
+}
@@ -1,27 +1,138 @@
 // Copyright 2025 Matthew Lyon
 // SPDX-License-Identifier: Apache-2.0
-use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr};
+use std::error::Error;
+use std::io;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
 use miette::Diagnostic;
 use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::config::{DnsResolver, LookupFormat, LookupProvider};
 
 const CLOUDFLARE_TRACE_URL: &str = "https://cloudflare.com/cdn-cgi/trace";
+/// Seconds before a lookup is abandoned when its provider has no `timeout_secs`
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
 static USER_AGENT: &str = concat!(
     "CFDNS",
     "/",
     env!("CARGO_PKG_VERSION"),
 );
 
-pub async fn get_public_ip(interface_ip: IpAddr) -> Result<IpAddr, LookupError> {
-    let client = reqwest::ClientBuilder::new()
+/// Look up the public address of an interface, trying each provider in order until one answers, or
+/// Cloudflare's trace endpoint when none are configured. When `device` is set the lookup is also
+/// bound to the interface by name, falling back to only binding the address if that isn't permitted.
+/// With `consensus`, every provider is asked at once instead and an address is only accepted when
+/// most of them agree on it.
+pub async fn get_public_ip(interface_ip: IpAddr, device: Option<&str>, providers: &[LookupProvider], consensus: bool) -> Result<IpAddr, LookupError> {
+    let default = [LookupProvider {
+        url: Some(CLOUDFLARE_TRACE_URL.to_string()),
+        dns: None,
+        format: LookupFormat::Trace,
+        timeout_secs: None,
+    }];
+    let providers = if providers.is_empty() { &default[..] } else { providers };
+    if consensus {
+        return lookup_by_consensus(interface_ip, device, providers).await;
+    }
+
+    let mut failures = Vec::new();
+    for provider in providers {
+        match lookup_with_provider(interface_ip, device, provider).await {
+            Ok(ip) => return Ok(ip),
+            // The address is gone, so every other provider would fail the same way
+            Err(e) if e.is_bind_failure() => return Err(e),
+            Err(e) => {
+                warn!(provider = %provider, error = %e, "Web lookup provider failed");
+                failures.push(ProviderError { provider: provider.to_string(), source: e });
+            }
+        }
+    }
+    if failures.len() == 1 {
+        return Err(failures.remove(0).source);
+    }
+    Err(LookupError::AllProvidersFailed(failures))
+}
+
+/// Ask every provider at once and accept the address returned by more than half of them, so a single
+/// misbehaving service can't publish a wrong address
+async fn lookup_by_consensus(interface_ip: IpAddr, device: Option<&str>, providers: &[LookupProvider]) -> Result<IpAddr, LookupError> {
+    let results = futures::future::join_all(
+        providers.iter().map(|provider| lookup_with_provider(interface_ip, device, provider)),
+    ).await;
+
+    let mut votes: Vec<(IpAddr, usize)> = Vec::new();
+    let mut failures = Vec::new();
+    for (provider, result) in providers.iter().zip(results) {
+        match result {
+            Ok(ip) => match votes.iter_mut().find(|(voted, _)| *voted == ip) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((ip, 1)),
+            },
+            Err(e) if e.is_bind_failure() => return Err(e),
+            Err(e) => {
+                warn!(provider = %provider, error = %e, "Web lookup provider failed");
+                failures.push(ProviderError { provider: provider.to_string(), source: e });
+            }
+        }
+    }
+
+    let needed = providers.len() / 2 + 1;
+    if let Some((ip, count)) = votes.iter().max_by_key(|(_, count)| *count)
+        && *count >= needed
+    {
+        debug!(ip = %ip, agreed = count, providers = providers.len(), "Web lookup providers agreed");
+        return Ok(*ip);
+    }
+    let answers: Vec<String> = votes.iter().map(|(ip, count)| format!("{ip} ({count})")).collect();
+    Err(LookupError::NoConsensus {
+        answers: if answers.is_empty() { "none".to_string() } else { answers.join(", ") },
+        needed,
+        total: providers.len(),
+        failures,
+    })
+}
+
+async fn lookup_with_provider(interface_ip: IpAddr, device: Option<&str>, provider: &LookupProvider) -> Result<IpAddr, LookupError> {
+    if let Some(device) = device {
+        match lookup(interface_ip, Some(device), provider).await {
+            Err(e) if e.is_permission_denied() => {
+                warn!(device, "Binding web lookup to the interface requires CAP_NET_RAW, falling back to the interface address");
+            }
+            other => return other,
+        }
+    }
+    lookup(interface_ip, None, provider).await
+}
+
+async fn lookup(interface_ip: IpAddr, device: Option<&str>, provider: &LookupProvider) -> Result<IpAddr, LookupError> {
+    let timeout = Duration::from_secs(provider.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+    match (&provider.url, provider.dns) {
+        (_, Some(resolver)) => lookup_dns(interface_ip, device, resolver, timeout).await,
+        (Some(url), None) => lookup_http(interface_ip, device, url, provider.format, timeout).await,
+        (None, None) => unreachable!("lookup providers are validated with the config"),
+    }
+}
+
+async fn lookup_http(interface_ip: IpAddr, device: Option<&str>, url: &str, format: LookupFormat, timeout: Duration) -> Result<IpAddr, LookupError> {
+    let builder = reqwest::ClientBuilder::new()
         .user_agent(USER_AGENT)
         .no_proxy()
         .local_address(interface_ip)
-        .timeout(std::time::Duration::from_secs(5))
+        .timeout(timeout);
+    #[cfg(target_os = "linux")]
+    let builder = match device {
+        Some(device) => builder.interface(device),
+        None => builder,
+    };
+    #[cfg(not(target_os = "linux"))]
+    let _ = device;
+    let client = builder
         .build()
         .map_err(LookupError::ClientCreation)?;
 
     let response = client
-        .get(CLOUDFLARE_TRACE_URL)
+        .get(url)
         .send()
         .await
         .map_err(|e| {
@@ -35,13 +146,17 @@ pub async fn get_public_ip(interface_ip: IpAddr) -> Result<IpAddr, LookupError>
         })?;
     
     let text = response.text().await?;
-    let ip = extract_ip_from_trace(&text)?;
+    let ip = match format {
+        LookupFormat::Trace => extract_ip_from_trace(&text)?,
+        LookupFormat::Plain => extract_ip_from_plain(&text)?,
+        LookupFormat::Json => extract_ip_from_json(&text)?,
+    };
     Ok(ip)
 
 }
 
-pub async fn get_public_ipv6(interface_ip: Ipv6Addr) -> Result<Ipv6Addr, LookupError> {
-    let ip = get_public_ip(IpAddr::V6(interface_ip)).await?;
+pub async fn get_public_ipv6(interface_ip: Ipv6Addr, device: Option<&str>, providers: &[LookupProvider], consensus: bool) -> Result<Ipv6Addr, LookupError> {
+    let ip = get_public_ip(IpAddr::V6(interface_ip), device, providers, consensus).await?;
     match ip {
         IpAddr::V6(v6) => Ok(v6),
         IpAddr::V4(_) => Err(LookupError::WrongIpVersion {
@@ -51,8 +166,8 @@ pub async fn get_public_ipv6(interface_ip: Ipv6Addr) -> Result<Ipv6Addr, LookupE
     }
 }
 
-pub async fn get_public_ipv4(interface_ip: Ipv4Addr) -> Result<Ipv4Addr, LookupError> {
-    let ip = get_public_ip(IpAddr::V4(interface_ip)).await?;
+pub async fn get_public_ipv4(interface_ip: Ipv4Addr, device: Option<&str>, providers: &[LookupProvider], consensus: bool) -> Result<Ipv4Addr, LookupError> {
+    let ip = get_public_ip(IpAddr::V4(interface_ip), device, providers, consensus).await?;
     match ip {
         IpAddr::V4(v4) => Ok(v4),
         IpAddr::V6(_) => Err(LookupError::WrongIpVersion {
@@ -63,6 +178,122 @@ pub async fn get_public_ipv4(interface_ip: Ipv4Addr) -> Result<Ipv4Addr, LookupE
 }
 
 
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_TXT: u16 = 16;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_CLASS_CH: u16 = 3;
+
+/// Ask a resolver which address the query came from, over UDP from the interface address
+async fn lookup_dns(interface_ip: IpAddr, device: Option<&str>, resolver: DnsResolver, timeout: Duration) -> Result<IpAddr, LookupError> {
+    let (server, name, qtype, qclass): (IpAddr, _, _, _) = match (resolver, interface_ip) {
+        (DnsResolver::Cloudflare, IpAddr::V4(_)) => (Ipv4Addr::new(1, 1, 1, 1).into(), "whoami.cloudflare", DNS_TYPE_TXT, DNS_CLASS_CH),
+        (DnsResolver::Cloudflare, IpAddr::V6(_)) => (Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111).into(), "whoami.cloudflare", DNS_TYPE_TXT, DNS_CLASS_CH),
+        (DnsResolver::OpenDns, IpAddr::V4(_)) => (Ipv4Addr::new(208, 67, 222, 222).into(), "myip.opendns.com", DNS_TYPE_A, DNS_CLASS_IN),
+        (DnsResolver::OpenDns, IpAddr::V6(_)) => (Ipv6Addr::new(0x2620, 0x119, 0x35, 0, 0, 0, 0, 0x35).into(), "myip.opendns.com", DNS_TYPE_AAAA, DNS_CLASS_IN),
+    };
+
+    let socket = tokio::net::UdpSocket::bind(SocketAddr::new(interface_ip, 0))
+        .await
+        .map_err(LookupError::DnsConnection)?;
+    #[cfg(target_os = "linux")]
+    if let Some(device) = device {
+        socket.bind_device(Some(device.as_bytes())).map_err(LookupError::DnsConnection)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = device;
+    socket.connect(SocketAddr::new(server, 53)).await.map_err(LookupError::DnsConnection)?;
+
+    // Only needs to tell this query's answer apart from stray packets
+    let id = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or_default();
+    let query = dns_query(id, name, qtype, qclass);
+
+    let mut buf = [0u8; 512];
+    let exchange = async {
+        socket.send(&query).await?;
+        socket.recv(&mut buf).await
+    };
+    let len = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| LookupError::DnsTimeout)?
+        .map_err(LookupError::DnsConnection)?;
+    Ok(dns_answer(&buf[..len], id, qtype)?)
+}
+
+/// A recursive query for a single name
+fn dns_query(id: u16, name: &str, qtype: u16, qclass: u16) -> Vec<u8> {
+    let mut query = Vec::with_capacity(12 + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&qclass.to_be_bytes());
+    query
+}
+
+/// The address in the first answer of type `qtype`, read from an A, AAAA or TXT record
+fn dns_answer(message: &[u8], id: u16, qtype: u16) -> Result<IpAddr, DnsParseError> {
+    let header = message.get(..12).ok_or(DnsParseError::Truncated)?;
+    if u16::from_be_bytes([header[0], header[1]]) != id || header[2] & 0x80 == 0 {
+        return Err(DnsParseError::Mismatched);
+    }
+    let rcode = header[3] & 0x0f;
+    if rcode != 0 {
+        return Err(DnsParseError::Rcode(rcode));
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_dns_name(message, pos)? + 4;
+    }
+    for _ in 0..answers {
+        pos = skip_dns_name(message, pos)?;
+        let fixed = message.get(pos..pos + 10).ok_or(DnsParseError::Truncated)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        pos += 10;
+        let rdata = message.get(pos..pos + rdlength).ok_or(DnsParseError::Truncated)?;
+        pos += rdlength;
+        if rtype != qtype {
+            continue;
+        }
+        return match rtype {
+            DNS_TYPE_A => <[u8; 4]>::try_from(rdata).map(IpAddr::from).map_err(|_| DnsParseError::Truncated),
+            DNS_TYPE_AAAA => <[u8; 16]>::try_from(rdata).map(IpAddr::from).map_err(|_| DnsParseError::Truncated),
+            _ => {
+                // A TXT record is a length-prefixed string
+                let (len, text) = rdata.split_first().ok_or(DnsParseError::Truncated)?;
+                let text = text.get(..*len as usize).ok_or(DnsParseError::Truncated)?;
+                Ok(String::from_utf8_lossy(text).trim().parse()?)
+            }
+        };
+    }
+    Err(DnsParseError::NoAnswer)
+}
+
+/// The position after a possibly compressed name starting at `pos`
+fn skip_dns_name(message: &[u8], mut pos: usize) -> Result<usize, DnsParseError> {
+    loop {
+        let len = *message.get(pos).ok_or(DnsParseError::Truncated)?;
+        match len {
+            0 => return Ok(pos + 1),
+            // A pointer to a name elsewhere ends this one
+            l if l & 0xc0 == 0xc0 => return Ok(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
+
 fn extract_ip_from_trace(text: &str) -> Result<IpAddr, TraceParseError> {
     let line = text
         .lines()
@@ -73,6 +304,24 @@ fn extract_ip_from_trace(text: &str) -> Result<IpAddr, TraceParseError> {
     Ok(ip_text.parse()?)
 }
 
+/// A body holding nothing but the address, allowing for a trailing newline
+fn extract_ip_from_plain(text: &str) -> Result<IpAddr, TraceParseError> {
+    let ip_text = text.trim();
+    if ip_text.is_empty() {
+        return Err(TraceParseError::NotPresent);
+    }
+    Ok(ip_text.parse()?)
+}
+
+/// A JSON object with the address in its `ip` field
+fn extract_ip_from_json(text: &str) -> Result<IpAddr, TraceParseError> {
+    let body: serde_json::Value = serde_json::from_str(text)?;
+    let ip_text = body.get("ip")
+        .and_then(serde_json::Value::as_str)
+        .ok_or(TraceParseError::NotPresent)?;
+    Ok(ip_text.trim().parse()?)
+}
+
 #[derive(Debug, Error, Diagnostic)]
 pub enum LookupError {
     #[error("failed to initialize web lookup client")]
@@ -92,14 +341,107 @@ pub enum LookupError {
     },
     #[error("request to IP lookup service timed out")]
     #[diagnostic(help("check the network connection for configured interfaces"))]
-    Timeout(#[source] reqwest::Error)
+    Timeout(#[source] reqwest::Error),
+    #[error("could not reach DNS lookup server")]
+    #[diagnostic(help("this interface might not be able to make outbound connections"))]
+    DnsConnection(#[source] io::Error),
+    #[error("request to DNS lookup server timed out")]
+    #[diagnostic(help("check the network connection for configured interfaces, some networks block DNS to outside resolvers"))]
+    DnsTimeout,
+    #[error(transparent)]
+    DnsParsing(#[from] #[diagnostic_source] DnsParseError),
+    #[error("{interface} is behind carrier-grade or double NAT: the interface has {local} but the internet sees {public}")]
+    #[diagnostic(help(
+        "inbound connections to {public} won't reach this host, since the address is shared or translated again upstream. \
+        Publish only AAAA records (type: AAAA) if IPv6 is available, or use Cloudflare Tunnel. \
+        Set `allow_cgnat: true` on the interface to publish the address anyway."
+    ))]
+    BehindCgnat {
+        interface: String,
+        local: Ipv4Addr,
+        public: Ipv4Addr,
+    },
+    #[error("every web lookup provider failed")]
+    #[diagnostic(help("check the network connection for configured interfaces, or add another entry to `lookup_providers`"))]
+    AllProvidersFailed(#[related] Vec<ProviderError>),
+    #[error("web lookup providers did not agree on an address, got {answers}")]
+    #[diagnostic(help("at least {needed} of the {total} providers must return the same address"))]
+    NoConsensus {
+        answers: String,
+        needed: usize,
+        total: usize,
+        #[related]
+        failures: Vec<ProviderError>,
+    },
+}
+
+/// A lookup provider that failed, tried before falling back to the next one
+#[derive(Debug, Error, Diagnostic)]
+#[error("web lookup with {provider} failed")]
+pub struct ProviderError {
+    provider: String,
+    #[source]
+    #[diagnostic_source]
+    source: LookupError,
+}
+
+impl LookupError {
+    /// Whether the lookup failed because the interface address could not be bound,
+    /// which usually means it was removed after being selected
+    pub fn is_bind_failure(&self) -> bool {
+        match self {
+            LookupError::ClientCreation(_) => true,
+            LookupError::Connection(e) => io_error_kind(e) == Some(io::ErrorKind::AddrNotAvailable),
+            LookupError::DnsConnection(e) => e.kind() == io::ErrorKind::AddrNotAvailable,
+            _ => false,
+        }
+    }
+
+    /// Whether the socket options requested for the lookup were refused
+    pub fn is_permission_denied(&self) -> bool {
+        match self {
+            LookupError::Connection(e) => io_error_kind(e) == Some(io::ErrorKind::PermissionDenied),
+            LookupError::DnsConnection(e) => e.kind() == io::ErrorKind::PermissionDenied,
+            _ => false,
+        }
+    }
+}
+
+fn io_error_kind(e: &reqwest::Error) -> Option<io::ErrorKind> {
+    let mut source = e.source();
+    while let Some(err) = source {
+        if let Some(io) = err.downcast_ref::<io::Error>() {
+            return Some(io.kind());
+        }
+        source = err.source();
+    }
+    None
 }
 
 #[derive(Debug, Error, Diagnostic)]
-#[diagnostic(help("this is most likely a server-side issue. Please report it and try again later."))]
 pub enum TraceParseError {
     #[error("the body returned from the ip lookup service did not include an IP")]
+    #[diagnostic(help("this is most likely a server-side issue. Please report it and try again later."))]
     NotPresent,
     #[error("could not parse the IP address from the server response")]
-    Parsing(#[from] AddrParseError)
+    #[diagnostic(help("this is most likely a server-side issue. Please report it and try again later."))]
+    Parsing(#[from] AddrParseError),
+    #[error("the body returned from the ip lookup service is not valid JSON")]
+    #[diagnostic(help("check that `format` in `lookup_providers` matches what the service returns"))]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[diagnostic(help("this is most likely a server-side issue. Please report it and try again later."))]
+pub enum DnsParseError {
+    #[error("the DNS lookup server sent a truncated answer")]
+    Truncated,
+    #[error("the DNS lookup server answered a different query")]
+    Mismatched,
+    #[error("the DNS lookup server returned error code {0}")]
+    Rcode(u8),
+    #[error("the DNS lookup server did not return an address")]
+    NoAnswer,
+    #[error("could not parse the IP address from the DNS answer")]
+    Parsing(#[from] AddrParseError),
 }